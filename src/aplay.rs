@@ -0,0 +1,93 @@
+use std::ffi::OsString;
+use std::path::Path;
+
+/// Whether rplay was invoked through a link named `aplay`.
+pub fn invoked_as_aplay(argv0: &OsString) -> bool {
+    Path::new(argv0).file_stem().is_some_and(|stem| stem == "aplay")
+}
+
+/// Translates an aplay command line into rplay arguments.
+/// Supports the format, rate, channel, device, file type and device listing options.
+pub fn translate(args: impl IntoIterator<Item = OsString>) -> Result<Vec<OsString>, String> {
+    let mut args = args.into_iter();
+    let mut out = Vec::new();
+    out.extend(args.next());
+
+    while let Some(arg) = args.next() {
+        let Some(arg_str) = arg.to_str() else {
+            out.push(arg);
+            continue;
+        };
+        // split `--opt=value` and `-fvalue` forms
+        let (name, inline_value) = if let Some((name, value)) = arg_str.split_once('=').filter(|_| arg_str.starts_with("--")) {
+            (name.to_string(), Some(value.to_string()))
+        } else if arg_str.len() > 2 && arg_str.starts_with('-') && !arg_str.starts_with("--") {
+            (arg_str[..2].to_string(), Some(arg_str[2..].to_string()))
+        } else {
+            (arg_str.to_string(), None)
+        };
+        let mut value = || inline_value.clone()
+            .or_else(|| args.next().and_then(|v| v.into_string().ok()))
+            .ok_or_else(|| format!("Missing value for aplay option '{name}'"));
+
+        match name.as_str() {
+            "-f" | "--format" => out.extend(format_args(&value()?)?),
+            "-r" | "--rate" => {
+                let rate = value()?;
+                // aplay accepts rates in kHz for values below 1000
+                let rate = match rate.parse::<u32>() {
+                    Ok(khz @ 1..1000) => (khz * 1000).to_string(),
+                    _ => rate,
+                };
+                out.extend(["--sample-rate".into(), rate.into()]);
+            },
+            "-c" | "--channels" => out.extend(["--channels".into(), value()?.into()]),
+            "-D" | "--device" => match value()? {
+                device if device == "default" => (),
+                device => out.extend(["--device".into(), device.into()]),
+            },
+            "-t" | "--file-type" => match value()?.to_ascii_lowercase().as_str() {
+                // containers are detected from their header
+                "raw" | "wav" | "au" => (),
+                file_type => return Err(format!("Unsupported aplay file type '{file_type}'")),
+            },
+            "-l" | "--list-devices" | "-L" | "--list-pcms" => out.push("--list-devices".into()),
+            "-q" | "--quiet" => (),
+            _ => out.push(arg),
+        }
+    }
+    Ok(out)
+}
+
+/// rplay arguments for an aplay `-f` sample format.
+fn format_args(format: &str) -> Result<Vec<OsString>, String> {
+    let upper = format.to_ascii_uppercase();
+    let args: &[&str] = match upper.as_str() {
+        "CD" => &["--preset", "cdda"],
+        "CDR" => &["--preset", "cdda", "--big-endian"],
+        "DAT" => &["--preset", "dat"],
+        "S8" => &["-t", "s8"],
+        "U8" => &["-t", "u8"],
+        "S16_LE" => &["-t", "s16le"],
+        "S16_BE" => &["-t", "s16be"],
+        "U16_LE" => &["-t", "u16le"],
+        "U16_BE" => &["-t", "u16be"],
+        // 24 bit samples in the low bytes of 32 bit words
+        "S24_LE" => &["-t", "s32le", "--fixed", "q23"],
+        "S24_BE" => &["-t", "s32be", "--fixed", "q23"],
+        "S24_3LE" => &["-t", "s24le"],
+        "S24_3BE" => &["-t", "s24be"],
+        "U24_3LE" => &["-t", "u24le"],
+        "U24_3BE" => &["-t", "u24be"],
+        "S32_LE" => &["-t", "s32le"],
+        "S32_BE" => &["-t", "s32be"],
+        "U32_LE" => &["-t", "u32le"],
+        "U32_BE" => &["-t", "u32be"],
+        "FLOAT_LE" | "FLOAT32_LE" => &["-t", "f32le"],
+        "FLOAT_BE" | "FLOAT32_BE" => &["-t", "f32be"],
+        "FLOAT64_LE" => &["-t", "f64le"],
+        "FLOAT64_BE" => &["-t", "f64be"],
+        _ => return Err(format!("Unsupported aplay sample format '{format}'")),
+    };
+    Ok(args.iter().map(OsString::from).collect())
+}
@@ -0,0 +1,100 @@
+use std::sync::mpsc;
+use std::time::Duration;
+
+use cpal::traits::{DeviceTrait, StreamTrait};
+
+use crate::devices;
+use crate::dither::Dither;
+
+/// Device buffer to start from, in frames, when the device allows one this small.
+const START_FRAMES: u32 = 64;
+/// Largest device buffer to grow to, in frames, when the device does not say.
+const MAX_FRAMES: u32 = 16384;
+
+/// Tells underruns apart from the ordinary jitter of the callbacks.
+///
+/// Each buffer should start playing where the previous one ends, a later start means the
+/// device ran out of samples in between.
+struct XrunDetector {
+    sample_rate: u32,
+    /// when the end of the last buffer plays
+    expected: Option<cpal::StreamInstant>,
+}
+
+impl XrunDetector {
+    fn observe(&mut self, info: &cpal::OutputCallbackInfo, frames: usize) -> bool {
+        let playback = info.timestamp().playback;
+        let duration = Duration::from_secs_f64(frames as f64 / self.sample_rate as f64);
+        // a gap of less than a buffer is jitter in when the host read the delay
+        let xrun = self.expected
+            .and_then(|expected| playback.duration_since(&expected))
+            .is_some_and(|gap| gap > duration);
+        self.expected = playback.add(duration);
+        xrun
+    }
+}
+
+/// Plays `fill` on `device` in `format` with `dither` starting with a small buffer, and reopens the stream with twice
+/// the buffer whenever it underruns, until it plays cleanly or reaches the device's largest.
+pub fn play(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    format: cpal::SampleFormat,
+    dither: Dither,
+    mut fill: impl FnMut(&mut [f32], &cpal::OutputCallbackInfo) + Send + 'static,
+) -> Result<(), String> {
+    let (min, max) = match device.default_output_config().map(|c| *c.buffer_size()) {
+        Ok(cpal::SupportedBufferSize::Range { min, max }) => (min.max(1), max),
+        _ => (1, MAX_FRAMES),
+    };
+    let max = max.min(MAX_FRAMES).max(min);
+    let mut frames = START_FRAMES.clamp(min, max);
+    let channels = config.channels as usize;
+    let ms = |frames: u32| frames as f64 * 1000.0 / config.sample_rate.0 as f64;
+
+    loop {
+        let (xrun_tx, xrun_rx) = mpsc::sync_channel(1);
+        // the input and processing state carry over from one stream to the next
+        let (mut lent, returned) = devices::lend(fill);
+        let stream = {
+            let mut detector = XrunDetector { sample_rate: config.sample_rate.0, expected: None };
+            devices::build_output_stream(
+                device,
+                &cpal::StreamConfig { buffer_size: cpal::BufferSize::Fixed(frames), ..config.clone() },
+                format,
+                dither,
+                move |data, info| {
+                    if detector.observe(info, data.len() / channels) {
+                        let _ = xrun_tx.try_send(());
+                    }
+                    lent.get()(data, info);
+                },
+                move |err| eprintln!("an error occurred on stream: {}", err),
+            )
+        };
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) if frames < max => {
+                eprintln!("[!] a buffer of {frames} frames failed, {e}");
+                frames = (frames * 2).min(max);
+                fill = devices::take_back(&returned)?;
+                continue;
+            },
+            Err(e) => return Err(format!("failed to open the output device: {e}")),
+        };
+        stream.play().map_err(|e| format!("{e}"))?;
+        eprintln!("[!] device buffer {frames} frames ({:.1} ms)", ms(frames));
+
+        while xrun_rx.recv().is_ok() {
+            if frames < max {
+                let grown = (frames * 2).min(max);
+                eprintln!("[!] underrun with a buffer of {frames} frames, growing it to {grown} frames ({:.1} ms)", ms(grown));
+                frames = grown;
+                break;
+            }
+            eprintln!("[!] underrun with the largest buffer of {frames} frames");
+        }
+        drop(stream);
+        fill = devices::take_back(&returned)?;
+    }
+}
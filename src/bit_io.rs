@@ -16,6 +16,17 @@ pub trait ToBytes: SizedNumber {
     fn to_be_bytes(self) -> Self::Bytes;
 }
 
+/// builds a number from a packed field of `bits` width, left-justified into the container
+/// so that packed samples keep full scale (and their sign, for two's complement)
+pub trait FromPacked: SizedNumber {
+    fn from_packed(raw: u64, bits: u32) -> Self;
+}
+
+/// the inverse of [FromPacked], takes the top `bits` of the container as a packed field
+pub trait ToPacked: SizedNumber {
+    fn to_packed(self, bits: u32) -> u64;
+}
+
 macro_rules! impl_bitio_traits_for {
     ($($t:ty),*) => {
         $(
@@ -48,16 +59,73 @@ macro_rules! impl_bitio_traits_for {
 }
 impl_bitio_traits_for!(u8, i8, u16, i16, u32, i32, u64, i64, u128, i128, f32, f64);
 
+macro_rules! impl_packed_traits_for {
+    ($($t:ty => $bits:ty),*) => {
+        $(
+            impl FromPacked for $t {
+                fn from_packed(raw: u64, bits: u32) -> $t {
+                    let shift = <$bits>::BITS - bits;
+                    <$t>::from_ne_bytes(((raw as $bits) << shift).to_ne_bytes())
+                }
+            }
+
+            impl ToPacked for $t {
+                fn to_packed(self, bits: u32) -> u64 {
+                    let shift = <$bits>::BITS - bits;
+                    (<$bits>::from_ne_bytes(self.to_ne_bytes()) >> shift) as u64
+                }
+            }
+        )*
+    }
+}
+impl_packed_traits_for!(
+    u8 => u8, i8 => u8, u16 => u16, i16 => u16,
+    u32 => u32, i32 => u32, u64 => u64, i64 => u64,
+    f32 => u32, f64 => u64
+);
+
 /// reads only the exact amount of bytes required to serialize primitive nums 
 pub struct BitReader<R> {
     inner: R,
     /// is big endian
     be: bool,
+    /// bits read from `inner` but not yet consumed by `read_packed`
+    acc: u128,
+    acc_bits: u32,
 }
 
 impl<R: io::Read> BitReader<R> {
     pub fn new(inner: R, big_endian: bool) -> Self {
-        BitReader { inner, be: big_endian }
+        BitReader { inner, be: big_endian, acc: 0, acc_bits: 0 }
+    }
+
+    /// reads a `bits` wide field from a packed bitstream into `T`
+    /// little-endian streams are packed LSB-first, big-endian streams MSB-first
+    pub fn read_packed<T: FromPacked>(&mut self, bits: u32) -> io::Result<T> {
+        debug_assert!(0 < bits && bits as usize <= T::SIZE * 8);
+        while self.acc_bits < bits {
+            let [byte] = self.read_helper::<1>()?;
+            if self.be {
+                self.acc = (self.acc << 8) | byte as u128;
+            } else {
+                self.acc |= (byte as u128) << self.acc_bits;
+            }
+            self.acc_bits += 8;
+        }
+
+        let mask = (1u128 << bits) - 1;
+        let raw = if self.be {
+            let raw = (self.acc >> (self.acc_bits - bits)) & mask;
+            self.acc_bits -= bits;
+            self.acc &= (1u128 << self.acc_bits) - 1;
+            raw
+        } else {
+            let raw = self.acc & mask;
+            self.acc >>= bits;
+            self.acc_bits -= bits;
+            raw
+        };
+        Ok(T::from_packed(raw as u64, bits))
     }
 
     /// switches on `T::SIZE`, which is const-generated for every impl of `FromBytes`
@@ -95,11 +163,47 @@ impl<R: io::Read> BitReader<R> {
 pub struct BitWriter<W> {
     inner: W,
     be: bool,
+    /// count of bytes written to `inner`
+    written: u64,
+    /// bits given to `write_packed` but not yet written as a whole byte
+    acc: u128,
+    acc_bits: u32,
 }
 
 impl<W: io::Write> BitWriter<W> {
     pub fn new(inner: W, big_endian: bool) -> Self {
-        BitWriter { inner, be: big_endian }
+        BitWriter { inner, be: big_endian, written: 0, acc: 0, acc_bits: 0 }
+    }
+
+    /// writes the top `bits` of `t` to a packed bitstream, mirroring [BitReader::read_packed]
+    /// trailing bits short of a byte are held until more are written
+    pub fn write_packed<T: ToPacked>(&mut self, t: T, bits: u32) -> io::Result<()> {
+        debug_assert!(0 < bits && bits as usize <= T::SIZE * 8);
+        let raw = t.to_packed(bits) as u128;
+        if self.be {
+            self.acc = (self.acc << bits) | raw;
+        } else {
+            self.acc |= raw << self.acc_bits;
+        }
+        self.acc_bits += bits;
+
+        let mut buf = [0u8; 16];
+        let mut len = 0;
+        while self.acc_bits >= 8 {
+            if self.be {
+                buf[len] = (self.acc >> (self.acc_bits - 8)) as u8;
+                self.acc_bits -= 8;
+                self.acc &= (1u128 << self.acc_bits) - 1;
+            } else {
+                buf[len] = self.acc as u8;
+                self.acc >>= 8;
+                self.acc_bits -= 8;
+            }
+            len += 1;
+        }
+        self.inner.write_all(&buf[..len])?;
+        self.written += len as u64;
+        Ok(())
     }
 
     pub fn write<T: ToBytes>(&mut self, t: T) -> io::Result<()> {
@@ -108,7 +212,79 @@ impl<W: io::Write> BitWriter<W> {
         } else {
             t.to_le_bytes()
         };
-        self.inner.write_all(bytes.as_ref())
+        self.inner.write_all(bytes.as_ref())?;
+        self.written += T::SIZE as u64;
+        Ok(())
+    }
+
+    pub fn written(&self) -> u64 {
+        self.written
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes `values` packed in `bits`, reads them back, and returns the bytes written.
+    fn round_trip<T: FromPacked + ToPacked + Copy + PartialEq + std::fmt::Debug>(
+        values: &[T],
+        bits: u32,
+        big_endian: bool,
+    ) -> Vec<u8> {
+        let mut writer = BitWriter::new(Vec::new(), big_endian);
+        for &value in values {
+            writer.write_packed(value, bits).unwrap();
+        }
+        let bytes = writer.get_mut().clone();
+        let mut reader = BitReader::new(&bytes[..], big_endian);
+        for &value in values {
+            assert_eq!(reader.read_packed::<T>(bits).unwrap(), value, "{bits} bits, big-endian {big_endian}");
+        }
+        bytes
+    }
+
+    #[test]
+    fn packed_samples_round_trip() {
+        // left-justified, so only the top `bits` of each value are kept, and four of them fill whole bytes
+        let signed = [1i32 << 20, -(1 << 20), i32::MAX, i32::MIN];
+        let unsigned = [0u32, 1 << 20, u32::MAX, 1 << 31];
+        for big_endian in [false, true] {
+            for bits in [12, 20] {
+                let keep = !0u32 << (32 - bits);
+                let signed = signed.map(|v| (v as u32 & keep) as i32);
+                let unsigned = unsigned.map(|v| v & keep);
+                assert_eq!(round_trip(&signed, bits, big_endian).len(), (signed.len() * bits as usize) / 8);
+                round_trip(&unsigned, bits, big_endian);
+            }
+        }
+    }
+
+    #[test]
+    fn packs_in_byte_order() {
+        // 0xabc then 0xdef, 12 bits each
+        let values = [0xabc0u16, 0xdef0];
+        assert_eq!(round_trip(&values, 12, false), [0xbc, 0xfa, 0xde]);
+        assert_eq!(round_trip(&values, 12, true), [0xab, 0xcd, 0xef]);
+    }
+
+    #[test]
+    fn holds_a_partial_trailing_byte() {
+        for big_endian in [false, true] {
+            let mut writer = BitWriter::new(Vec::new(), big_endian);
+            writer.write_packed(0xabc0u16, 12).unwrap();
+            assert_eq!(writer.written(), 1);
+            writer.write_packed(0xd000u16, 4).unwrap();
+            assert_eq!(writer.written(), 2);
+
+            // a stream that ends inside a field is an error, not a short value
+            let mut reader = BitReader::new(&[0xab][..], big_endian);
+            assert!(reader.read_packed::<u16>(12).is_err());
+        }
+    }
+}
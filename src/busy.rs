@@ -0,0 +1,102 @@
+use std::sync::Arc;
+
+use clap::ValueEnum;
+
+use crate::control::OutputControl;
+
+/// What to do when another rplay is already playing on the device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OnBusy {
+    /// Wait for it to finish, then play
+    Wait,
+    /// Play over it, dimmed by 20 dB until this one ends
+    Duck,
+    /// Pause it until this one ends
+    Preempt,
+}
+
+/// Coordinates instances started with `--on-busy` on the same device.
+///
+/// The first to play owns the device by binding an abstract socket named after it,
+/// which the kernel releases when the owner exits. A later instance waits for the socket
+/// to be released, or connects to it to ask the owner to duck or pause for as long as
+/// the connection stays open, so the owner recovers however the later one ends.
+#[cfg(target_os = "linux")]
+pub fn claim(device: &str, on_busy: OnBusy, control: Arc<OutputControl>) -> Result<(), String> {
+    use std::io::{self, Write};
+    use std::os::linux::net::SocketAddrExt;
+    use std::os::unix::net::{SocketAddr, UnixListener, UnixStream};
+    use std::time::Duration;
+
+    let addr = SocketAddr::from_abstract_name(format!("rplay-device/{device}"))
+        .map_err(|e| format!("{device}: {e}"))?;
+    let mut waiting = false;
+    loop {
+        match UnixListener::bind_addr(&addr) {
+            Ok(listener) => {
+                serve_guests(listener, control);
+                return Ok(());
+            },
+            Err(e) if e.kind() == io::ErrorKind::AddrInUse => (),
+            Err(e) => return Err(format!("{device}: {e}")),
+        }
+
+        let request = match on_busy {
+            OnBusy::Wait => {
+                if !waiting {
+                    eprintln!("[!] waiting for the rplay playing on {device} to finish");
+                    waiting = true;
+                }
+                std::thread::sleep(Duration::from_millis(200));
+                continue;
+            },
+            OnBusy::Duck => "duck",
+            OnBusy::Preempt => "preempt",
+        };
+        // the owner may have just left, then the device is free to take
+        let Ok(mut stream) = UnixStream::connect_addr(&addr) else {
+            continue;
+        };
+        writeln!(stream, "{request}").map_err(|e| format!("{device}: {e}"))?;
+        let verb = if on_busy == OnBusy::Duck { "ducking" } else { "pausing" };
+        eprintln!("[!] {verb} the rplay playing on {device} while this one plays");
+        // held open until exit, closing it ends the request
+        std::mem::forget(stream);
+        return Ok(());
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn claim(_device: &str, _on_busy: OnBusy, _control: Arc<OutputControl>) -> Result<(), String> {
+    Err("--on-busy needs abstract unix sockets, which only Linux has".into())
+}
+
+/// Ducks or pauses the output while instances that came later are connected.
+#[cfg(target_os = "linux")]
+fn serve_guests(listener: std::os::unix::net::UnixListener, control: Arc<OutputControl>) {
+    use std::io::{self, BufRead, BufReader};
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(stream) = stream else {
+                continue;
+            };
+            let control = Arc::clone(&control);
+            std::thread::spawn(move || {
+                let mut reader = BufReader::new(stream);
+                let mut request = String::new();
+                if reader.read_line(&mut request).is_err() {
+                    return;
+                }
+                let apply: fn(&OutputControl, bool) = match request.trim() {
+                    "duck" => OutputControl::duck,
+                    "preempt" => OutputControl::preempt,
+                    _ => return,
+                };
+                apply(&control, true);
+                // until the guest exits
+                let _ = io::copy(&mut reader, &mut io::sink());
+                apply(&control, false);
+            });
+        }
+    });
+}
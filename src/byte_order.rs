@@ -0,0 +1,95 @@
+use std::io::{self, Read};
+
+use clap::ValueEnum;
+
+use crate::bit_io::BitReader;
+
+/// Byte order of input samples.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ByteOrder {
+    Little,
+    Big,
+    /// Guessed from the first samples of the input
+    Auto,
+}
+
+/// Frames inspected to guess the byte order.
+const WINDOW_FRAMES: usize = 4096;
+/// How much rougher the rejected byte order has to be for a guess to be made.
+const MIN_RATIO: f64 = 2.0;
+/// Non-zero floats smaller than this are counted as noise, swapped bytes tend to land there.
+const MIN_FLOAT: f64 = 1e-20;
+/// Floats larger than this are counted as noise.
+const MAX_FLOAT: f64 = 16.0;
+
+/// Guesses whether the samples at the start of `input` are big-endian by picking the
+/// byte order that gives the smoother waveform. Returns None when neither is clearly smoother.
+/// The returned reader yields the untouched input.
+pub fn guess<R: io::Read + Send + 'static>(
+    mut input: R,
+    sample_size: u32,
+    float: bool,
+    unsigned: bool,
+    channels: usize,
+) -> Result<(Option<bool>, Box<dyn io::Read + Send>), String> {
+    let window_len = WINDOW_FRAMES * channels * sample_size as usize / 8;
+    let mut window = Vec::with_capacity(window_len);
+    (&mut input).take(window_len as u64)
+        .read_to_end(&mut window)
+        .map_err(|e| format!("{e}"))?;
+
+    let little = roughness(&window, false, sample_size, float, unsigned, channels);
+    let big = roughness(&window, true, sample_size, float, unsigned, channels);
+    let big_endian = if big * MIN_RATIO < little {
+        Some(true)
+    } else if little * MIN_RATIO < big {
+        Some(false)
+    } else {
+        None
+    };
+    Ok((big_endian, Box::new(io::Cursor::new(window).chain(input))))
+}
+
+/// Mean distance between consecutive samples of a channel, on a full scale of 1.0.
+fn roughness(
+    window: &[u8],
+    big_endian: bool,
+    sample_size: u32,
+    float: bool,
+    unsigned: bool,
+    channels: usize,
+) -> f64 {
+    // flipping the top bit centers offset-binary values on zero, like signed ones
+    let center = if unsigned { 1 << 63 } else { 0 };
+    let mut reader = BitReader::new(window, big_endian);
+    let mut read_sample = || -> io::Result<Option<f64>> {
+        let value = match (float, sample_size) {
+            (true, 32) => reader.read::<f32>()? as f64,
+            (true, _) => reader.read::<f64>()?,
+            // integers are left-justified, so the scale is the same for every size
+            (false, _) => {
+                let value = (reader.read_packed::<u64>(sample_size)? ^ center) as i64;
+                return Ok(Some(value as f64 / i64::MAX as f64));
+            },
+        };
+        let plausible = value == 0.0 || (MIN_FLOAT..=MAX_FLOAT).contains(&value.abs());
+        Ok(plausible.then_some(value))
+    };
+
+    let mut previous = vec![0.0; channels];
+    let (mut total, mut count) = (0.0, 0usize);
+    'frames: loop {
+        for previous in previous.iter_mut() {
+            let Ok(sample) = read_sample() else {
+                break 'frames;
+            };
+            total += match sample {
+                Some(sample) => (sample - *previous).abs().min(1.0),
+                None => 1.0,
+            };
+            count += 1;
+            *previous = sample.unwrap_or(*previous);
+        }
+    }
+    if count == 0 { 0.0 } else { total / count as f64 }
+}
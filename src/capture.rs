@@ -0,0 +1,107 @@
+use std::collections::VecDeque;
+use std::io::{self, Read};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use cpal::traits::{DeviceTrait, StreamTrait};
+
+/// Buffers of the device held for the reader before new ones are dropped.
+const QUEUE: usize = 64;
+/// Most captured audio a monitor is behind the input device.
+pub const MONITOR_LATENCY: Duration = Duration::from_millis(20);
+/// Output device buffer of a monitor, unless --buffer-frames gives one.
+pub const MONITOR_FRAMES: u32 = 128;
+
+/// Samples recorded by an input device, read as f32le.
+pub struct CaptureSource {
+    buffers: mpsc::Receiver<Vec<f32>>,
+    /// buffers taken from `buffers` and not yet read
+    queued: VecDeque<Vec<f32>>,
+    /// most samples kept in `queued`, the oldest are dropped past it
+    max_queued: Option<usize>,
+    /// whether the last buffer was dropped, to warn once for a run of them
+    skipping: bool,
+    buffer: Vec<u8>,
+    /// bytes of `buffer` already read
+    pos: usize,
+}
+
+impl CaptureSource {
+    /// Records `device` at `config` until the end of the process. With `max_latency` the reader
+    /// is kept at most that far behind the device, dropping what it has not caught up with.
+    pub fn open(device: cpal::Device, config: cpal::StreamConfig, max_latency: Option<Duration>) -> Result<Self, String> {
+        let max_queued = max_latency
+            .map(|latency| (latency.as_secs_f64() * config.sample_rate.0 as f64) as usize * config.channels as usize);
+        let (buffers_tx, buffers) = mpsc::sync_channel(QUEUE);
+        let (opened_tx, opened) = mpsc::channel();
+        // a stream can not move between threads on every host, this one keeps it
+        thread::spawn(move || {
+            let mut dropped = false;
+            let stream = device.build_input_stream(
+                &config,
+                move |data: &[f32], _: &cpal::InputCallbackInfo| {
+                    match buffers_tx.try_send(data.to_vec()) {
+                        Ok(()) => dropped = false,
+                        Err(mpsc::TrySendError::Full(_)) if !dropped => {
+                            eprintln!("[!] playback fell behind the capture, dropping captured samples");
+                            dropped = true;
+                        },
+                        Err(_) => (),
+                    }
+                },
+                move |err| eprintln!("an error occurred on stream: {}", err),
+                None,
+            );
+            let stream = stream
+                .map_err(|e| format!("failed to open the input device: {e}"))
+                .and_then(|stream| stream.play().map(|()| stream).map_err(|e| format!("{e}")));
+            match stream {
+                Ok(_stream) => {
+                    let _ = opened_tx.send(Ok(()));
+                    loop {
+                        thread::park();
+                    }
+                },
+                Err(msg) => {
+                    let _ = opened_tx.send(Err(msg));
+                },
+            }
+        });
+        opened.recv().map_err(|_| "the capture thread stopped".to_string())??;
+        Ok(CaptureSource { buffers, queued: VecDeque::new(), max_queued, skipping: false, buffer: Vec::new(), pos: 0 })
+    }
+}
+
+impl Read for CaptureSource {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.buffer.len() {
+            if self.queued.is_empty() {
+                let Ok(samples) = self.buffers.recv() else {
+                    return Ok(0);
+                };
+                self.queued.push_back(samples);
+            }
+            self.queued.extend(self.buffers.try_iter());
+            if let Some(max_queued) = self.max_queued {
+                let mut len = self.queued.iter().map(Vec::len).sum::<usize>();
+                let mut dropped = false;
+                while len > max_queued && self.queued.len() > 1 {
+                    len -= self.queued.pop_front().map_or(0, |samples| samples.len());
+                    dropped = true;
+                }
+                if dropped && !self.skipping {
+                    eprintln!("[!] playback fell behind the capture, dropped captured samples to keep up");
+                }
+                self.skipping = dropped;
+            }
+            let samples = self.queued.pop_front().unwrap();
+            self.buffer = samples.iter().flat_map(|s| s.to_le_bytes()).collect();
+            self.pos = 0;
+        }
+        let len = buf.len().min(self.buffer.len() - self.pos);
+        buf[..len].copy_from_slice(&self.buffer[self.pos..self.pos + len]);
+        self.pos += len;
+        Ok(len)
+    }
+}
@@ -0,0 +1,277 @@
+use std::collections::VecDeque;
+use std::str::FromStr;
+
+use dasp_sample::{Sample, ToSample};
+
+/// A frame offset for one channel, parsed from `ch=+Nframe`.
+#[derive(Debug, Clone, Copy)]
+pub struct ChannelShift {
+    pub channel: usize,
+    pub frames: i64,
+}
+
+impl FromStr for ChannelShift {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((channel, frames)) = s.split_once('=') else {
+            return Err(format!("Invalid channel shift '{s}', expected ch=+Nframe"));
+        };
+        let channel = channel.trim().parse::<usize>().map_err(|e| format!("{e}"))?;
+        let frames = frames.trim();
+        let frames = frames.strip_suffix("frames")
+            .or_else(|| frames.strip_suffix("frame"))
+            .unwrap_or(frames);
+        let frames = frames.parse::<i64>().map_err(|e| format!("{e}"))?;
+        Ok(ChannelShift { channel, frames })
+    }
+}
+
+/// An input channel played on a device channel, parsed from `in:out`, e.g. `1:5` or `FR:5`.
+#[derive(Debug, Clone)]
+pub struct ChannelMap {
+    pub input: ChannelRef,
+    pub output: usize,
+}
+
+impl FromStr for ChannelMap {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((input, output)) = s.split_once(':') else {
+            return Err(format!("Invalid channel map '{s}', expected in:out such as 0:4"));
+        };
+        let input = input.parse::<ChannelRef>()?;
+        let output = output.trim().parse::<usize>().map_err(|e| format!("Invalid device channel '{output}', {e}"))?;
+        Ok(ChannelMap { input, output })
+    }
+}
+
+/// A gain for one channel, parsed from `ch=gain`, e.g. `0=0.5` or `FL=0.5`.
+#[derive(Debug, Clone)]
+pub struct ChannelGain {
+    pub channel: ChannelRef,
+    pub gain: f32,
+}
+
+impl FromStr for ChannelGain {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((channel, gain)) = s.split_once('=') else {
+            return Err(format!("Invalid channel gain '{s}', expected ch=gain such as 0=0.5"));
+        };
+        let channel = channel.parse::<ChannelRef>()?;
+        let gain = gain.trim().parse::<f32>().map_err(|e| format!("Invalid gain '{gain}', {e}"))?;
+        Ok(ChannelGain { channel, gain })
+    }
+}
+
+/// An input channel mixed into a device channel at a gain.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Route {
+    pub from: usize,
+    pub to: usize,
+    pub gain: f32,
+}
+
+/// Mixes a frame into one of the device's by `routes`, device channels without a route are silent.
+pub fn route_frame(input: &[f32], output: &mut [f32], routes: &[Route]) {
+    output.fill(0.0);
+    for route in routes {
+        output[route.to] += input[route.from] * route.gain;
+    }
+}
+
+/// Level of a centre or surround channel folded into a front one, -3 dB as in ITU-R BS.775.
+const FOLD: f32 = std::f32::consts::FRAC_1_SQRT_2;
+
+/// Routes that mix a surround input with channels named `names` down to stereo, with the
+/// ITU-R BS.775 coefficients scaled so that no output channel can clip. The LFE is left out.
+/// Without names, 6 channels are taken as 5.1 and 8 as 7.1 in WAV order.
+pub fn downmix_routes(names: &[String], channels: usize) -> Result<Vec<Route>, String> {
+    let order = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+    let names = match (names.len(), channels) {
+        (0, 6) => order(&["FL", "FR", "FC", "LFE", "BL", "BR"]),
+        (0, 8) => order(&["FL", "FR", "FC", "LFE", "BL", "BR", "SL", "SR"]),
+        (0, _) => return Err(format!("Can not downmix {channels} channels without a channel mask naming them, only 5.1 and 7.1")),
+        _ => names.to_vec(),
+    };
+    let mut routes = Vec::new();
+    for (from, name) in names.iter().enumerate() {
+        let gains: &[(usize, f32)] = match name.as_str() {
+            "FL" | "FLC" => &[(0, 1.0)],
+            "FR" | "FRC" => &[(1, 1.0)],
+            "FC" | "BC" | "TC" => &[(0, FOLD), (1, FOLD)],
+            "BL" | "SL" => &[(0, FOLD)],
+            "BR" | "SR" => &[(1, FOLD)],
+            "LFE" => &[],
+            name => {
+                eprintln!("[!] downmix leaves out channel {from} ({name}), it has no place in stereo");
+                &[]
+            },
+        };
+        routes.extend(gains.iter().map(|&(to, gain)| Route { from, to, gain }));
+    }
+    let loudest = (0..2)
+        .map(|to| routes.iter().filter(|r| r.to == to).map(|r| r.gain).sum::<f32>())
+        .fold(1.0, f32::max);
+    for route in &mut routes {
+        route.gain /= loudest;
+    }
+    Ok(routes)
+}
+
+/// Copies a frame into one of `output.len()` channels. A single channel plays on all of them,
+/// otherwise channels are copied in order, dropping those past the output's and leaving the
+/// output's extra channels silent.
+pub fn fit_frame(input: &[f32], output: &mut [f32]) {
+    match input {
+        [mono] => output.fill(*mono),
+        _ => {
+            let len = input.len().min(output.len());
+            output[..len].copy_from_slice(&input[..len]);
+            output[len..].fill(0.0);
+        },
+    }
+}
+
+/// Speaker positions of the bits of a WAVE_FORMAT_EXTENSIBLE channel mask, lowest bit first.
+const SPEAKER_POSITIONS: [&str; 18] = [
+    "FL", "FR", "FC", "LFE", "BL", "BR", "FLC", "FRC", "BC",
+    "SL", "SR", "TC", "TFL", "TFC", "TFR", "TBL", "TBC", "TBR",
+];
+
+/// Names of the channels a channel mask assigns, in stream order.
+pub fn speaker_names(mask: u32) -> Vec<String> {
+    (0..32)
+        .filter(|bit| mask & (1 << bit) != 0)
+        .map(|bit| SPEAKER_POSITIONS.get(bit).map_or_else(|| format!("SP{bit}"), |name| name.to_string()))
+        .collect()
+}
+
+/// A channel given by index or by speaker position name, e.g. `0` or `LFE`.
+#[derive(Debug, Clone)]
+pub enum ChannelRef {
+    Index(usize),
+    Name(String),
+}
+
+impl ChannelRef {
+    /// Index of the channel in a stream with `channels` channels named `names`.
+    pub fn resolve(&self, names: &[String], channels: usize) -> Result<usize, String> {
+        match self {
+            ChannelRef::Index(index) if *index < channels => Ok(*index),
+            ChannelRef::Index(index) => Err(format!("No channel '{index}' in a {channels} channel stream")),
+            ChannelRef::Name(name) => names.iter().position(|n| n == name).ok_or_else(|| {
+                if names.is_empty() {
+                    format!("Channel '{name}' given by name, but the input has no channel mask to name channels")
+                } else {
+                    format!("No channel '{name}' in the input, channels are: {}", names.join(", "))
+                }
+            }),
+        }
+    }
+}
+
+impl FromStr for ChannelRef {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Ok(index) = s.parse::<usize>() {
+            return Ok(ChannelRef::Index(index));
+        }
+        if s.is_empty() || !s.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return Err(format!("Invalid channel '{s}', expected an index or a position such as FL"));
+        }
+        Ok(ChannelRef::Name(s.to_ascii_uppercase()))
+    }
+}
+
+/// Delays channels of interleaved frames relative to each other.
+/// Negative shifts are applied by delaying every other channel instead.
+pub struct Shifter<S> {
+    delays: Vec<VecDeque<S>>,
+}
+
+impl<S: Sample> Shifter<S> {
+    pub fn new(channels: usize, shifts: &[ChannelShift]) -> Self {
+        let mut frames = vec![0i64; channels];
+        for shift in shifts {
+            frames[shift.channel] += shift.frames;
+        }
+        let min = frames.iter().copied().min().unwrap_or(0);
+        let delays = frames.iter()
+            .map(|f| VecDeque::from(vec![S::EQUILIBRIUM; (f - min) as usize]))
+            .collect();
+        Shifter { delays }
+    }
+
+    pub fn process(&mut self, frame: &mut [S]) {
+        for (sample, delay) in frame.iter_mut().zip(self.delays.iter_mut()) {
+            if !delay.is_empty() {
+                delay.push_back(*sample);
+                *sample = delay.pop_front().unwrap();
+            }
+        }
+    }
+}
+
+/// Watches the start of a stream for channels that correlate with channel 0
+/// better at a one frame offset than when aligned, and warns about them once.
+pub struct ShiftDetector {
+    frames_left: usize,
+    prev: Vec<f64>,
+    /// per channel: energy, and correlation with channel 0 at -1, 0, +1 frames
+    energy: Vec<f64>,
+    corr: Vec<[f64; 3]>,
+}
+
+impl ShiftDetector {
+    pub fn new(channels: usize, window_frames: usize) -> Self {
+        ShiftDetector {
+            frames_left: window_frames,
+            prev: vec![0.0; channels],
+            energy: vec![0.0; channels],
+            corr: vec![[0.0; 3]; channels],
+        }
+    }
+
+    pub fn observe<S: Sample + ToSample<f64>>(&mut self, frame: &[S]) {
+        if self.frames_left == 0 {
+            return;
+        }
+        let (x0, prev0) = (frame[0].to_sample::<f64>(), self.prev[0]);
+        for (c, &x) in frame.iter().enumerate() {
+            let (x, prev) = (x.to_sample::<f64>(), self.prev[c]);
+            self.energy[c] += x * x;
+            // channel c lagging channel 0 by a frame, aligned, and leading it by a frame
+            self.corr[c][0] += prev0 * x;
+            self.corr[c][1] += x0 * x;
+            self.corr[c][2] += x0 * prev;
+        }
+        for (prev, &x) in self.prev.iter_mut().zip(frame) {
+            *prev = x.to_sample::<f64>();
+        }
+
+        self.frames_left -= 1;
+        if self.frames_left == 0 {
+            self.report();
+        }
+    }
+
+    fn report(&self) {
+        for c in 1..self.corr.len() {
+            let norm = (self.energy[0] * self.energy[c]).sqrt();
+            if norm == 0.0 {
+                continue;
+            }
+            let [lag, aligned, lead] = self.corr[c].map(|s| s / norm);
+            let (best, frames) = if lag > lead { (lag, 1) } else { (lead, -1) };
+            if best > 0.5 && best - aligned > 0.05 {
+                eprintln!("[!] channel {c} appears offset by {frames:+} frame from channel 0, correct with --channel-shift {c}={:+}frame", -frames);
+            }
+        }
+    }
+}
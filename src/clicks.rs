@@ -0,0 +1,155 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use clap::ValueEnum;
+use dasp_sample::{Sample, ToSample};
+
+use crate::control::OutputControl;
+use crate::timecode;
+
+/// Prediction error, relative to its recent level, that counts as a discontinuity, 20 dB.
+const THRESHOLD: f64 = 10.0;
+/// Prediction error below which nothing is flagged, -40 dBFS, keeps hiss and dither quiet.
+const FLOOR: f64 = 0.01;
+/// Time constant of the recent level of the prediction error.
+const BASELINE: Duration = Duration::from_millis(20);
+/// How long after a jump to listen before deciding what it was.
+const SETTLE: Duration = Duration::from_millis(2);
+/// Samples right after a jump that still carry its own prediction error.
+const JUMP_LEN: usize = 3;
+/// Level of the prediction error after a jump, relative to before it, that makes it the onset of a sound.
+const ONSET_RATIO: f64 = 4.0;
+/// Identical samples in a row around a jump that mark a dropout in the capture.
+const MIN_HELD: usize = 8;
+
+/// What to do on a click or dropout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ClickAction {
+    /// Print where it is
+    Report,
+    /// Print where it is and pause, resume with `rplayctl resume`
+    Pause,
+}
+
+/// A jump waiting for the samples after it.
+struct Jump {
+    frame: u64,
+    /// prediction error at the jump, relative to the level before it
+    ratio: f64,
+    level_before: f64,
+    /// identical samples in a row up to the jump
+    held_before: usize,
+    samples: usize,
+    /// sum of the squared prediction error after the jump
+    energy: f64,
+    held_after: usize,
+}
+
+#[derive(Default)]
+struct Channel {
+    /// the last two samples, the latest first
+    history: [f64; 2],
+    /// mean square prediction error
+    level: f64,
+    held: usize,
+    jump: Option<Jump>,
+    /// frame of the last dropout reported, whose end is part of it
+    dropout: Option<u64>,
+}
+
+/// Flags discontinuities in the input, samples that a line through the two before them
+/// predicts far worse than the signal's recent samples, as clicks or as dropouts where
+/// samples are held around them. Jumps followed by a louder signal are the onset of a sound
+/// and are let through.
+pub struct ClickDetector {
+    sample_rate: u32,
+    channels: Vec<Channel>,
+    frame: u64,
+    /// weight of each new prediction error in the level
+    smoothing: f64,
+    settle: usize,
+    /// paused on a detection if set
+    pause: Option<Arc<OutputControl>>,
+}
+
+impl ClickDetector {
+    pub fn new(sample_rate: u32, channels: usize, action: ClickAction, control: Arc<OutputControl>) -> Self {
+        ClickDetector {
+            sample_rate,
+            channels: (0..channels).map(|_| Channel::default()).collect(),
+            frame: 0,
+            smoothing: 1.0 - (-1.0 / (BASELINE.as_secs_f64() * sample_rate as f64)).exp(),
+            settle: (SETTLE.as_secs_f64() * sample_rate as f64).ceil() as usize,
+            pause: (action == ClickAction::Pause).then_some(control),
+        }
+    }
+
+    pub fn observe<S: Sample + ToSample<f64>>(&mut self, frame: &[S]) {
+        for (c, sample) in frame.iter().enumerate() {
+            let x = sample.to_sample::<f64>();
+            let channel = &mut self.channels[c];
+            let [x1, x2] = channel.history;
+            let error = x - 2.0 * x1 + x2;
+            channel.history = [x, x1];
+            let held_before = channel.held;
+            channel.held = if x == x1 { channel.held + 1 } else { 0 };
+            // two samples of history first
+            if self.frame < 2 {
+                continue;
+            }
+
+            if let Some(jump) = &mut channel.jump {
+                jump.samples += 1;
+                if jump.samples > JUMP_LEN {
+                    jump.energy += error * error;
+                }
+                jump.held_after = jump.held_after.max(channel.held);
+                if jump.samples >= self.settle {
+                    let jump = channel.jump.take().unwrap();
+                    self.classify(c, jump);
+                }
+            } else if error.abs() > FLOOR && error * error > THRESHOLD * THRESHOLD * channel.level {
+                channel.jump = Some(Jump {
+                    frame: self.frame,
+                    ratio: error.abs() / channel.level.sqrt(),
+                    level_before: channel.level,
+                    held_before,
+                    samples: 0,
+                    energy: 0.0,
+                    held_after: 0,
+                });
+            }
+            let channel = &mut self.channels[c];
+            channel.level += self.smoothing * (error * error - channel.level);
+        }
+        self.frame += 1;
+    }
+
+    fn classify(&mut self, channel: usize, jump: Jump) {
+        let level_after = jump.energy / (jump.samples - JUMP_LEN).max(1) as f64;
+        if level_after > ONSET_RATIO * ONSET_RATIO * jump.level_before {
+            return;
+        }
+        let dropout = &mut self.channels[channel].dropout;
+        if dropout.is_some_and(|start| jump.frame - start <= jump.held_before as u64 + JUMP_LEN as u64) {
+            return;
+        }
+        let held = jump.held_before.max(jump.held_after);
+        if held >= MIN_HELD {
+            *dropout = Some(jump.frame);
+        }
+        let what = if held >= MIN_HELD { "dropout" } else { "click" };
+        let detail = if held >= MIN_HELD {
+            format!("{held} samples held")
+        } else if jump.ratio.is_finite() {
+            format!("{:.0} dB above the signal", 20.0 * jump.ratio.log10())
+        } else {
+            "out of silence".into()
+        };
+        eprintln!("[!] {what} on channel {} at {}, {detail}", channel + 1, timecode::position(jump.frame, self.sample_rate));
+        if let Some(control) = &self.pause {
+            control.set_paused(true);
+            eprintln!("[!] paused, resume with rplayctl resume");
+        }
+    }
+}
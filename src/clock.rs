@@ -0,0 +1,40 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+use clap::ValueEnum;
+
+/// Buffer handed to the pipeline on every tick of the timer.
+const PERIOD: Duration = Duration::from_millis(10);
+
+/// What paces the pipeline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Clock {
+    /// The callbacks of the output device
+    Device,
+    /// A monotonic timer, without opening any audio device
+    Timer,
+    /// No clock, the pipeline runs as fast as the input is read, e.g. to render it to a file
+    Free,
+}
+
+/// Calls `fill` with a buffer of `channels` interleaved frames every [PERIOD], as an output
+/// device at `sample_rate` would, and drops what it writes. A [Clock::Free] calls it again as
+/// soon as it returns.
+///
+/// The schedule follows the frames filled since the start rather than the time between
+/// calls, so late wakeups do not add up.
+pub fn drive(clock: Clock, sample_rate: u32, channels: usize, mut fill: impl FnMut(&mut [f32])) -> ! {
+    let period = (PERIOD.as_secs_f64() * sample_rate as f64).round().max(1.0) as u64;
+    let mut buffer = vec![0.0; period as usize * channels];
+    let start = Instant::now();
+    let mut frames = 0u64;
+    loop {
+        fill(&mut buffer);
+        if clock == Clock::Free {
+            continue;
+        }
+        frames += period;
+        let due = start + Duration::from_secs_f64(frames as f64 / sample_rate as f64);
+        thread::sleep(due.saturating_duration_since(Instant::now()));
+    }
+}
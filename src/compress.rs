@@ -0,0 +1,80 @@
+use std::io::{self, Read};
+use std::process::{Child, ChildStdout, Command, Stdio};
+use std::thread;
+
+/// Compression formats recognized at the start of an input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Compression {
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    fn detect(prefix: &[u8]) -> Option<Self> {
+        match prefix {
+            // deflate is the only method gzip defines
+            [0x1f, 0x8b, 0x08, ..] => Some(Compression::Gzip),
+            [0x28, 0xb5, 0x2f, 0xfd, ..] => Some(Compression::Zstd),
+            _ => None,
+        }
+    }
+
+    /// Program that decompresses standard input to standard output.
+    fn program(self) -> &'static str {
+        match self {
+            Compression::Gzip => "gzip",
+            Compression::Zstd => "zstd",
+        }
+    }
+}
+
+/// Whether an input starting with `prefix` is compressed in a format [decompress] takes.
+pub fn is_compressed(prefix: &[u8]) -> bool {
+    Compression::detect(prefix).is_some()
+}
+
+/// Decompresses gzip and zstd inputs with the `gzip` and `zstd` programs, other inputs
+/// are passed on as they are.
+pub fn decompress(mut input: Box<dyn Read + Send>) -> Result<Box<dyn Read + Send>, String> {
+    let mut prefix = Vec::with_capacity(4);
+    (&mut input).take(4).read_to_end(&mut prefix).map_err(|e| format!("{e}"))?;
+    let input = Box::new(io::Cursor::new(prefix.clone()).chain(input));
+    let Some(compression) = Compression::detect(&prefix) else {
+        return Ok(input);
+    };
+
+    let program = compression.program();
+    let mut child = Command::new(program)
+        .arg("-dc")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("{compression:?} input needs the {program} program: {e}"))?;
+    let mut stdin = child.stdin.take().expect("piped stdin");
+    let stdout = child.stdout.take().expect("piped stdout");
+    thread::spawn(move || {
+        let mut input = input;
+        // a broken pipe means the decompressor quit, which it reports itself
+        let _ = io::copy(&mut input, &mut stdin);
+    });
+    eprintln!("[!] decompressing {compression:?} input with {program}");
+    Ok(Box::new(Decompressor { child, stdout }))
+}
+
+struct Decompressor {
+    child: Child,
+    stdout: ChildStdout,
+}
+
+impl Read for Decompressor {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.stdout.read(buf)?;
+        if len == 0 && !buf.is_empty() {
+            let status = self.child.wait()?;
+            if !status.success() {
+                return Err(io::Error::other(format!("decompression failed, {status}")));
+            }
+        }
+        Ok(len)
+    }
+}
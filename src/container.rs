@@ -0,0 +1,276 @@
+use std::fmt;
+use std::io::{self, Read};
+
+use crate::header::{self, StreamHeader};
+
+/// Containers recognized at the start of the input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Container {
+    Rplay,
+    Wav,
+    Au,
+    Aiff,
+}
+
+impl fmt::Display for Container {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Container::Rplay => "rplay stream",
+            Container::Wav => "WAV",
+            Container::Au => "AU",
+            Container::Aiff => "AIFF",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// Length of the prefix needed to tell the containers apart.
+const PROBE_LEN: usize = 12;
+
+const WAV_FORMAT_PCM: u16 = 1;
+const WAV_FORMAT_IEEE_FLOAT: u16 = 3;
+const WAV_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+type Input = Box<dyn io::Read + Send>;
+
+/// Format read from a container header.
+pub struct Detected {
+    pub container: Container,
+    pub spec: StreamHeader,
+    /// WAVE_FORMAT_EXTENSIBLE speaker positions of the channels
+    pub channel_mask: Option<u32>,
+    /// where the sample data starts, in bytes from the start of the input
+    pub data_offset: u64,
+    /// length of the sample data, if the header gives one
+    pub data_len: Option<u64>,
+}
+
+/// Counts the bytes read through it, to find where a header ends.
+struct Counted<R> {
+    inner: R,
+    count: u64,
+}
+
+impl<R: Read> Read for Counted<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        self.count += len as u64;
+        Ok(len)
+    }
+}
+
+/// Reads a container header off the front of `input` if there is one.
+/// The returned reader yields only the samples after the header, or the untouched input otherwise.
+pub fn detect<R: io::Read + Send + 'static>(
+    mut input: R,
+) -> Result<(Option<Detected>, Input), String> {
+    let mut prefix = Vec::with_capacity(PROBE_LEN);
+    (&mut input).take(PROBE_LEN as u64)
+        .read_to_end(&mut prefix)
+        .map_err(|e| format!("{e}"))?;
+
+    let container = match (prefix.get(0..4), prefix.get(8..12)) {
+        (Some(magic), _) if magic == header::MAGIC => Container::Rplay,
+        (Some(b"RIFF"), Some(b"WAVE")) => Container::Wav,
+        (Some(b".snd"), _) => Container::Au,
+        (Some(b"FORM"), Some(b"AIFF" | b"AIFC")) => Container::Aiff,
+        _ => return Ok((None, Box::new(io::Cursor::new(prefix).chain(input)))),
+    };
+
+    let mut counted = Counted { inner: io::Cursor::new(prefix).chain(input), count: 0 };
+    let mut channel_mask = None;
+    let (spec, data_len) = match container {
+        Container::Rplay => {
+            let bytes = read_array::<{ header::LEN }>(&mut counted)?;
+            (StreamHeader::from_bytes(&bytes)?, None)
+        },
+        Container::Wav => read_wav(&mut counted, &mut channel_mask)?,
+        Container::Au => read_au(&mut counted)?,
+        Container::Aiff => read_aiff(&mut counted)?,
+    };
+    let data_offset = counted.count;
+    let mut input: Input = Box::new(counted.inner);
+
+    // stop at the end of the sample data, rather than playing trailing chunks as noise
+    if let Some(data_len) = data_len {
+        input = Box::new(input.take(data_len));
+    }
+    if spec.silence_runs {
+        input = Box::new(header::SilenceRuns::new(input, &spec)?);
+    }
+    Ok((Some(Detected { container, spec, channel_mask, data_offset, data_len }), input))
+}
+
+fn read_array<const N: usize>(input: &mut dyn Read) -> Result<[u8; N], String> {
+    let mut buf = [0u8; N];
+    input.read_exact(&mut buf)
+        .map_err(|e| format!("Truncated container header: {e}"))?;
+    Ok(buf)
+}
+
+fn skip(input: &mut dyn Read, len: u64) -> Result<(), String> {
+    let skipped = io::copy(&mut input.take(len), &mut io::sink())
+        .map_err(|e| format!("{e}"))?;
+    if skipped < len {
+        return Err("Truncated container header".into());
+    }
+    Ok(())
+}
+
+/// Parses RIFF WAVE chunks up to the start of the `data` chunk.
+fn read_wav(input: &mut dyn Read, channel_mask: &mut Option<u32>) -> Result<(StreamHeader, Option<u64>), String> {
+    skip(input, 12)?;
+    let mut spec = None;
+    loop {
+        let chunk = read_array::<8>(input)?;
+        let id = &chunk[0..4];
+        let len = u32::from_le_bytes(chunk[4..8].try_into().unwrap()) as u64;
+        match id {
+            b"fmt " => {
+                if len < 16 {
+                    return Err("WAV fmt chunk is too short".into());
+                }
+                let fmt = read_array::<16>(input)?;
+                let mut tag = u16::from_le_bytes([fmt[0], fmt[1]]);
+                let channels = u16::from_le_bytes([fmt[2], fmt[3]]);
+                let sample_rate = u32::from_le_bytes(fmt[4..8].try_into().unwrap());
+                let bits = u16::from_le_bytes([fmt[14], fmt[15]]);
+                let mut rest = len - 16;
+                if tag == WAV_FORMAT_EXTENSIBLE && rest >= 24 {
+                    let ext = read_array::<24>(input)?;
+                    let mask = u32::from_le_bytes(ext[4..8].try_into().unwrap());
+                    *channel_mask = (mask != 0).then_some(mask);
+                    // the sub format GUID starts with the format tag
+                    tag = u16::from_le_bytes([ext[8], ext[9]]);
+                    rest -= 24;
+                }
+                skip(input, rest + (len & 1))?;
+
+                let float = match tag {
+                    WAV_FORMAT_PCM => false,
+                    WAV_FORMAT_IEEE_FLOAT => true,
+                    tag => return Err(format!("Unsupported WAV format tag '{tag:#06x}'")),
+                };
+                spec = Some(StreamHeader {
+                    sample_rate,
+                    channels,
+                    sample_size: bits.div_ceil(8) as u8 * 8,
+                    float,
+                    // 8 bit WAV samples are unsigned, wider ones signed
+                    unsigned: !float && bits <= 8,
+                    big_endian: false,
+                    sign_magnitude: false,
+                    silence_runs: false,
+                });
+            },
+            b"data" => {
+                let Some(spec) = spec else {
+                    return Err("WAV data chunk before its fmt chunk".into());
+                };
+                // streamed WAVs leave the size at zero or its maximum
+                let data_len = (len != 0 && len != u32::MAX as u64).then_some(len);
+                return Ok((spec, data_len));
+            },
+            _ => skip(input, len + (len & 1))?,
+        }
+    }
+}
+
+/// Parses a Sun AU header and skips its annotation.
+fn read_au(input: &mut dyn Read) -> Result<(StreamHeader, Option<u64>), String> {
+    let header = read_array::<24>(input)?;
+    let field = |i: usize| u32::from_be_bytes(header[i..i + 4].try_into().unwrap());
+    let (offset, data_len, encoding, sample_rate, channels) = (field(4), field(8), field(12), field(16), field(20));
+    let (sample_size, float) = match encoding {
+        2 => (8, false),
+        3 => (16, false),
+        4 => (24, false),
+        5 => (32, false),
+        6 => (32, true),
+        7 => (64, true),
+        encoding => return Err(format!("Unsupported AU encoding '{encoding}'")),
+    };
+    skip(input, (offset as u64).saturating_sub(24))?;
+
+    let spec = StreamHeader {
+        sample_rate,
+        channels: channels as u16,
+        sample_size,
+        float,
+        unsigned: false,
+        big_endian: true,
+        sign_magnitude: false,
+        silence_runs: false,
+    };
+    Ok((spec, (data_len != u32::MAX).then_some(data_len as u64)))
+}
+
+/// Parses AIFF/AIFC chunks up to the start of the sample data in the `SSND` chunk.
+fn read_aiff(input: &mut dyn Read) -> Result<(StreamHeader, Option<u64>), String> {
+    let form = read_array::<12>(input)?;
+    let is_aifc = &form[8..12] == b"AIFC";
+    let mut spec = None;
+    loop {
+        let chunk = read_array::<8>(input)?;
+        let id = &chunk[0..4];
+        let len = u32::from_be_bytes(chunk[4..8].try_into().unwrap()) as u64;
+        match id {
+            b"COMM" => {
+                let comm = read_array::<18>(input)?;
+                let channels = u16::from_be_bytes([comm[0], comm[1]]);
+                let bits = u16::from_be_bytes([comm[6], comm[7]]);
+                let sample_rate = extended_to_f64(comm[8..18].try_into().unwrap()).round() as u32;
+                let mut rest = len.saturating_sub(18);
+
+                let (mut float, mut big_endian) = (false, true);
+                if is_aifc && rest >= 4 {
+                    let compression = read_array::<4>(input)?;
+                    rest -= 4;
+                    match &compression {
+                        b"NONE" | b"twos" => (),
+                        b"sowt" => big_endian = false,
+                        b"fl32" | b"FL32" | b"fl64" | b"FL64" => float = true,
+                        other => {
+                            return Err(format!("Unsupported AIFC compression '{}'", String::from_utf8_lossy(other)));
+                        },
+                    }
+                }
+                skip(input, rest + (len & 1))?;
+
+                spec = Some(StreamHeader {
+                    sample_rate,
+                    channels,
+                    // narrower samples are left-justified in whole bytes
+                    sample_size: bits.div_ceil(8) as u8 * 8,
+                    float,
+                    unsigned: false,
+                    big_endian,
+                    sign_magnitude: false,
+                    silence_runs: false,
+                });
+            },
+            b"SSND" => {
+                let Some(spec) = spec else {
+                    return Err("AIFF SSND chunk before its COMM chunk".into());
+                };
+                let ssnd = read_array::<8>(input)?;
+                let offset = u32::from_be_bytes(ssnd[0..4].try_into().unwrap()) as u64;
+                skip(input, offset)?;
+                let data_len = len.checked_sub(8 + offset).filter(|&len| len != 0);
+                return Ok((spec, data_len));
+            },
+            _ => skip(input, len + (len & 1))?,
+        }
+    }
+}
+
+/// Converts an 80-bit IEEE 754 extended precision number, as AIFF stores sample rates.
+fn extended_to_f64(bytes: [u8; 10]) -> f64 {
+    let sign = if bytes[0] & 0x80 != 0 { -1.0 } else { 1.0 };
+    let exponent = (u16::from_be_bytes([bytes[0], bytes[1]]) & 0x7FFF) as i32;
+    let mantissa = u64::from_be_bytes(bytes[2..10].try_into().unwrap());
+    if exponent == 0 && mantissa == 0 {
+        return 0.0;
+    }
+    sign * mantissa as f64 * 2f64.powi(exponent - 16383 - 63)
+}
@@ -0,0 +1,376 @@
+use std::ffi::OsString;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+
+/// Gain of the dimmed output, -20 dB.
+const DIM: f64 = 0.1;
+/// Time the output takes to reach a new level, short enough to feel instant without clicking.
+const RAMP: Duration = Duration::from_millis(10);
+
+/// Commands taken by the control socket and rplayctl.
+const COMMANDS: [&str; 10] = [
+    "mute", "unmute", "dim", "undim", "toggle-mute", "toggle-dim", "pause", "resume", "panic", "status",
+];
+
+/// Mute, dim and pause state of the output, changed from hotkeys, the control socket
+/// or rplayctl while the audio callback reads it.
+#[derive(Debug, Default)]
+pub struct OutputControl {
+    muted: AtomicBool,
+    dimmed: AtomicBool,
+    paused: AtomicBool,
+    /// later instances on the same device ducking this one, see [crate::busy]
+    ducks: AtomicUsize,
+    /// later instances on the same device pausing this one while they play
+    preemptions: AtomicUsize,
+    /// whether the output has ramped all the way down, set by [GainRamp]
+    silent: AtomicBool,
+    /// frames of the last buffer the device asked for, 0 before the first
+    buffer_frames: AtomicUsize,
+    /// microseconds from filling the last buffer to playing it
+    latency_us: AtomicU64,
+}
+
+impl OutputControl {
+    pub fn set_muted(&self, muted: bool) {
+        self.muted.store(muted, Ordering::Relaxed);
+    }
+
+    pub fn set_dimmed(&self, dimmed: bool) {
+        self.dimmed.store(dimmed, Ordering::Relaxed);
+    }
+
+    pub fn set_paused(&self, paused: bool) {
+        self.paused.store(paused, Ordering::Relaxed);
+    }
+
+    /// Mutes and pauses at once.
+    pub fn panic(&self) {
+        self.set_muted(true);
+        self.set_paused(true);
+    }
+
+    /// Starts or ends a duck by another instance.
+    pub fn duck(&self, on: bool) {
+        if on {
+            self.ducks.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.ducks.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Starts or ends a pause by another instance.
+    pub fn preempt(&self, on: bool) {
+        if on {
+            self.preemptions.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.preemptions.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+
+    fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed) || self.preemptions.load(Ordering::Relaxed) > 0
+    }
+
+    /// Whether the input is held where it is rather than played, once paused and faded out.
+    pub fn holds_input(&self) -> bool {
+        self.is_paused() && self.silent.load(Ordering::Relaxed)
+    }
+
+    pub fn toggle_mute(&self) {
+        self.muted.fetch_xor(true, Ordering::Relaxed);
+    }
+
+    pub fn toggle_dim(&self) {
+        self.dimmed.fetch_xor(true, Ordering::Relaxed);
+    }
+
+    /// Records the last buffer the device asked for and how long it takes to play.
+    pub fn set_latency(&self, buffer_frames: usize, latency: Duration) {
+        self.buffer_frames.store(buffer_frames, Ordering::Relaxed);
+        self.latency_us.store(latency.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    /// Gain the output ramps to.
+    fn target(&self) -> f64 {
+        if self.muted.load(Ordering::Relaxed) || self.is_paused() {
+            0.0
+        } else if self.dimmed.load(Ordering::Relaxed) || self.ducks.load(Ordering::Relaxed) > 0 {
+            DIM
+        } else {
+            1.0
+        }
+    }
+
+    fn status(&self) -> String {
+        let muted = if self.muted.load(Ordering::Relaxed) { "muted" } else { "unmuted" };
+        let dimmed = if self.dimmed.load(Ordering::Relaxed) { "dimmed" } else { "undimmed" };
+        let paused = if self.paused.load(Ordering::Relaxed) { "paused" } else { "playing" };
+        match self.buffer_frames.load(Ordering::Relaxed) {
+            0 => format!("{muted} {dimmed} {paused}"),
+            frames => {
+                let latency = self.latency_us.load(Ordering::Relaxed) as f64 / 1000.0;
+                format!("{muted} {dimmed} {paused}, buffer {frames} frames, latency {latency:.1} ms")
+            },
+        }
+    }
+}
+
+/// Final gain of the output, following its [OutputControl] with a linear ramp.
+pub struct GainRamp {
+    control: Arc<OutputControl>,
+    gain: f64,
+    step: f64,
+}
+
+impl GainRamp {
+    pub fn new(control: Arc<OutputControl>, sample_rate: u32) -> Self {
+        let gain = control.target();
+        let step = 1.0 / (RAMP.as_secs_f64() * sample_rate as f64).max(1.0);
+        GainRamp { control, gain, step }
+    }
+
+    /// Gain of the next frame.
+    pub fn next_gain(&mut self) -> f64 {
+        let target = self.control.target();
+        self.gain += (target - self.gain).clamp(-self.step, self.step);
+        self.control.silent.store(self.gain == 0.0, Ordering::Relaxed);
+        self.gain
+    }
+}
+
+/// Runs a command of the control socket or hotkeys, returns its reply.
+fn command(control: &OutputControl, command: &str) -> String {
+    match command {
+        "mute" => control.set_muted(true),
+        "unmute" => control.set_muted(false),
+        "dim" => control.set_dimmed(true),
+        "undim" => control.set_dimmed(false),
+        "toggle-mute" => control.toggle_mute(),
+        "toggle-dim" => control.toggle_dim(),
+        "pause" => control.set_paused(true),
+        "resume" => control.set_paused(false),
+        "panic" => control.panic(),
+        "status" => (),
+        command => return format!("unknown command '{command}', expected one of: {}", COMMANDS.join(", ")),
+    }
+    control.status()
+}
+
+/// Accepts connections on a unix domain socket created at `path`, each sending commands
+/// one per line and getting the resulting state back, e.g. `echo mute | nc -U PATH`.
+#[cfg(unix)]
+pub fn serve(path: &Path, control: Arc<OutputControl>) -> io::Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+    use std::os::unix::net::UnixListener;
+    if std::fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_socket()) {
+        std::fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(stream) = stream else {
+                continue;
+            };
+            let control = Arc::clone(&control);
+            thread::spawn(move || {
+                let mut writer = &stream;
+                for line in io::BufReader::new(&stream).lines() {
+                    let Ok(line) = line else {
+                        break;
+                    };
+                    if line.trim().is_empty() {
+                        continue;
+                    }
+                    let reply = command(&control, line.trim());
+                    if writeln!(writer, "{reply}").is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn serve(_path: &Path, _control: Arc<OutputControl>) -> io::Result<()> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+#[cfg(unix)]
+mod terminal {
+    use std::fs;
+    use std::io;
+    use std::os::fd::{AsRawFd, RawFd};
+    use std::sync::OnceLock;
+
+    /// The terminal and its settings before hotkeys were turned on.
+    static SAVED: OnceLock<(RawFd, libc::termios)> = OnceLock::new();
+
+    extern "C" fn restore() {
+        if let Some((fd, termios)) = SAVED.get() {
+            unsafe { libc::tcsetattr(*fd, libc::TCSANOW, termios) };
+        }
+    }
+
+    extern "C" fn restore_and_raise(signal: libc::c_int) {
+        restore();
+        unsafe {
+            libc::signal(signal, libc::SIG_DFL);
+            libc::raise(signal);
+        }
+    }
+
+    /// Opens the controlling terminal and turns off line buffering and echo, so keys are read
+    /// as they are pressed. The settings are restored on exit and on fatal signals.
+    pub fn open_raw() -> io::Result<fs::File> {
+        let tty = fs::File::open("/dev/tty")?;
+        let fd = tty.as_raw_fd();
+        let mut termios = unsafe { std::mem::zeroed::<libc::termios>() };
+        if unsafe { libc::tcgetattr(fd, &mut termios) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if SAVED.set((fd, termios)).is_err() {
+            return Err(io::Error::other("the terminal is already in use"));
+        }
+        unsafe {
+            libc::atexit(restore);
+            for signal in [libc::SIGINT, libc::SIGTERM, libc::SIGHUP] {
+                libc::signal(signal, restore_and_raise as extern "C" fn(libc::c_int) as libc::sighandler_t);
+            }
+        }
+        termios.c_lflag &= !(libc::ICANON | libc::ECHO);
+        termios.c_cc[libc::VMIN] = 1;
+        termios.c_cc[libc::VTIME] = 0;
+        if unsafe { libc::tcsetattr(fd, libc::TCSANOW, &termios) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(tty)
+    }
+}
+
+/// Reads hotkeys from the terminal: `m` toggles the mute, `d` the dim.
+#[cfg(unix)]
+pub fn read_keys(control: Arc<OutputControl>) -> io::Result<()> {
+    use std::io::Read;
+    let tty = terminal::open_raw()?;
+    eprintln!("[!] hotkeys: m to mute, d to dim by 20 dB");
+    thread::spawn(move || {
+        for key in io::BufReader::new(tty).bytes() {
+            let reply = match key {
+                Ok(b'm' | b'M') => command(&control, "toggle-mute"),
+                Ok(b'd' | b'D') => command(&control, "toggle-dim"),
+                Ok(_) => continue,
+                Err(_) => break,
+            };
+            eprintln!("[!] {reply}");
+        }
+    });
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn read_keys(_control: Arc<OutputControl>) -> io::Result<()> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+/// Prefix of the abstract socket every instance listens on for rplayctl, followed by its process id.
+#[cfg(target_os = "linux")]
+const INSTANCE_PREFIX: &str = "rplay/";
+
+#[cfg(unix)]
+static PANIC_CONTROL: std::sync::OnceLock<Arc<OutputControl>> = std::sync::OnceLock::new();
+
+#[cfg(unix)]
+extern "C" fn on_panic_signal(_signal: libc::c_int) {
+    if let Some(control) = PANIC_CONTROL.get() {
+        control.panic();
+    }
+}
+
+/// Mutes and pauses the output on SIGUSR2, and takes commands from rplayctl
+/// on an abstract socket named after this process.
+#[cfg(unix)]
+pub fn listen_for_panic(control: Arc<OutputControl>) -> io::Result<()> {
+    if PANIC_CONTROL.set(Arc::clone(&control)).is_ok() {
+        unsafe {
+            libc::signal(libc::SIGUSR2, on_panic_signal as extern "C" fn(libc::c_int) as libc::sighandler_t);
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::linux::net::SocketAddrExt;
+        use std::os::unix::net::{SocketAddr, UnixDatagram};
+        let name = format!("{INSTANCE_PREFIX}{}", std::process::id());
+        let socket = UnixDatagram::bind_addr(&SocketAddr::from_abstract_name(name)?)?;
+        thread::spawn(move || {
+            let mut buf = [0u8; 64];
+            while let Ok(len) = socket.recv(&mut buf) {
+                let command_line = String::from_utf8_lossy(&buf[..len]);
+                if command_line.trim() == "panic" {
+                    eprintln!("[!] panic from rplayctl, muted and paused");
+                }
+                command(&control, command_line.trim());
+            }
+        });
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn listen_for_panic(_control: Arc<OutputControl>) -> io::Result<()> {
+    Ok(())
+}
+
+/// Whether rplay was invoked through a link named `rplayctl`.
+pub fn invoked_as_rplayctl(argv0: &OsString) -> bool {
+    Path::new(argv0).file_stem().is_some_and(|stem| stem == "rplayctl")
+}
+
+/// Sends the commands given to rplayctl to every rplay instance on the machine,
+/// e.g. `rplayctl panic`, then `rplayctl unmute resume`.
+#[cfg(target_os = "linux")]
+pub fn rplayctl(args: Vec<OsString>) -> Result<(), String> {
+    use std::os::linux::net::SocketAddrExt;
+    use std::os::unix::net::{SocketAddr, UnixDatagram};
+
+    let commands = args.into_iter().skip(1)
+        .map(|arg| arg.into_string().map_err(|arg| format!("Invalid command '{}'", arg.to_string_lossy())))
+        .collect::<Result<Vec<_>, _>>()?;
+    if commands.is_empty() {
+        return Err(format!("Usage: rplayctl COMMAND..., e.g. rplayctl panic\nCommands: {}", COMMANDS.join(", ")));
+    }
+    if let Some(command) = commands.iter().find(|c| !COMMANDS.contains(&c.as_str())) {
+        return Err(format!("Unknown command '{command}', expected one of: {}", COMMANDS.join(", ")));
+    }
+
+    // abstract sockets are listed with a leading @ in place of the path
+    let sockets = std::fs::read_to_string("/proc/net/unix").map_err(|e| format!("/proc/net/unix: {e}"))?;
+    let mut names = sockets.lines()
+        .filter_map(|line| line.split_whitespace().nth(7)?.strip_prefix('@'))
+        .filter(|name| name.starts_with(INSTANCE_PREFIX))
+        .collect::<Vec<_>>();
+    names.sort_unstable();
+    names.dedup();
+
+    let socket = UnixDatagram::unbound().map_err(|e| format!("{e}"))?;
+    let mut reached = 0;
+    for name in &names {
+        let addr = SocketAddr::from_abstract_name(name).map_err(|e| format!("{e}"))?;
+        let sent = commands.iter().all(|command| socket.send_to_addr(command.as_bytes(), &addr).is_ok());
+        reached += sent as usize;
+    }
+    eprintln!("[!] sent {} to {reached} rplay instance(s)", commands.join(" "));
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn rplayctl(_args: Vec<OsString>) -> Result<(), String> {
+    Err("rplayctl needs abstract unix sockets, which only Linux has, send SIGUSR2 to rplay instead".into())
+}
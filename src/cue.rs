@@ -0,0 +1,116 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Bytes in a raw CD audio sector.
+pub const SECTOR_LEN: u64 = 2352;
+/// Sectors per second, the `ff` of `mm:ss:ff` cue times.
+const SECTORS_PER_SECOND: u64 = 75;
+
+/// A track of a cue sheet.
+#[derive(Debug, Clone)]
+pub struct Track {
+    pub number: u32,
+    pub audio: bool,
+    /// first sector of the track, at its INDEX 01
+    pub start: u64,
+}
+
+/// Tracks of a raw CD image, as described by a single FILE cue sheet.
+#[derive(Debug, Clone)]
+pub struct CueSheet {
+    pub image: PathBuf,
+    /// MOTOROLA images are big-endian, BINARY ones little-endian
+    pub big_endian: bool,
+    pub tracks: Vec<Track>,
+}
+
+impl CueSheet {
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("{}: {e}", path.display()))?;
+        let dir = path.parent().unwrap_or(Path::new(""));
+        let invalid = |line: &str| format!("Invalid cue sheet line '{}' in {}", line.trim(), path.display());
+
+        let mut file = None;
+        let mut tracks: Vec<Track> = Vec::new();
+        for line in text.lines() {
+            let (command, args) = line.trim().split_once(char::is_whitespace).unwrap_or((line.trim(), ""));
+            match command.to_ascii_uppercase().as_str() {
+                "FILE" => {
+                    if file.is_some() {
+                        return Err(format!("Cue sheets with more than one FILE are not supported: {}", path.display()));
+                    }
+                    let (name, file_type) = match args.trim().strip_prefix('"') {
+                        Some(quoted) => quoted.split_once('"').ok_or_else(|| invalid(line))?,
+                        None => args.trim().split_once(char::is_whitespace).ok_or_else(|| invalid(line))?,
+                    };
+                    let big_endian = match file_type.trim().to_ascii_uppercase().as_str() {
+                        "BINARY" => false,
+                        "MOTOROLA" => true,
+                        file_type => return Err(format!("Unsupported cue sheet file type '{file_type}', expected BINARY or MOTOROLA")),
+                    };
+                    file = Some((dir.join(name), big_endian));
+                },
+                "TRACK" => {
+                    let (number, mode) = args.trim().split_once(char::is_whitespace).ok_or_else(|| invalid(line))?;
+                    tracks.push(Track {
+                        number: number.parse().map_err(|_| invalid(line))?,
+                        audio: mode.trim().eq_ignore_ascii_case("AUDIO"),
+                        start: 0,
+                    });
+                },
+                "INDEX" => {
+                    let (index, time) = args.trim().split_once(char::is_whitespace).ok_or_else(|| invalid(line))?;
+                    if index.parse::<u32>() == Ok(1) {
+                        let track = tracks.last_mut().ok_or_else(|| invalid(line))?;
+                        track.start = parse_time(time.trim()).ok_or_else(|| invalid(line))?;
+                    }
+                },
+                _ => (),
+            }
+        }
+
+        let Some((image, big_endian)) = file else {
+            return Err(format!("Cue sheet without a FILE: {}", path.display()));
+        };
+        if tracks.is_empty() {
+            return Err(format!("Cue sheet without tracks: {}", path.display()));
+        }
+        Ok(CueSheet { image, big_endian, tracks })
+    }
+
+    /// The cue sheet next to a `.bin` or `.cdr` image, if there is one.
+    pub fn find_for(image: &Path) -> Option<PathBuf> {
+        let extension = image.extension()?.to_ascii_lowercase();
+        if extension != "bin" && extension != "cdr" {
+            return None;
+        }
+        ["cue", "CUE"].iter()
+            .map(|extension| image.with_extension(extension))
+            .find(|cue| cue.is_file())
+    }
+
+    /// Byte offset and, except for the last track, length of a track in the image.
+    pub fn track_range(&self, number: u32) -> Result<(u64, Option<u64>), String> {
+        let position = self.tracks.iter().position(|t| t.number == number).ok_or_else(|| {
+            let numbers = self.tracks.iter().map(|t| t.number.to_string()).collect::<Vec<_>>();
+            format!("No track '{number}' in the cue sheet, tracks are: {}", numbers.join(", "))
+        })?;
+        let track = &self.tracks[position];
+        if !track.audio {
+            return Err(format!("Track '{number}' is a data track"));
+        }
+        let end = self.tracks.get(position + 1).map(|next| next.start);
+        Ok((track.start * SECTOR_LEN, end.map(|end| end.saturating_sub(track.start) * SECTOR_LEN)))
+    }
+}
+
+/// Sector of a cue sheet `mm:ss:ff` time.
+fn parse_time(time: &str) -> Option<u64> {
+    let mut fields = time.split(':').map(|f| f.parse::<u64>().ok());
+    let (minutes, seconds, sectors) = (fields.next()??, fields.next()??, fields.next()??);
+    if fields.next().is_some() {
+        return None;
+    }
+    Some((minutes * 60 + seconds) * SECTORS_PER_SECOND + sectors)
+}
@@ -0,0 +1,45 @@
+/// Adds a delta to a sample, wrapping around the container for integers so that
+/// left-justified packed samples wrap at their own width.
+pub trait WrappingAdd: Copy + Send + 'static {
+    fn wrapping_add(self, delta: Self) -> Self;
+}
+
+macro_rules! impl_wrapping_add_for {
+    (int: $($i:ty),*; float: $($f:ty),*) => {
+        $(
+            impl WrappingAdd for $i {
+                fn wrapping_add(self, delta: $i) -> $i {
+                    <$i>::wrapping_add(self, delta)
+                }
+            }
+        )*
+        $(
+            impl WrappingAdd for $f {
+                fn wrapping_add(self, delta: $f) -> $f {
+                    self + delta
+                }
+            }
+        )*
+    }
+}
+impl_wrapping_add_for!(int: u8, i8, u16, i16, u32, i32, u64, i64; float: f32, f64);
+
+/// Decodes interleaved DPCM samples, each a delta from the previous sample of its channel.
+pub struct DeltaDecoder<S> {
+    previous: Vec<S>,
+    channel: usize,
+}
+
+impl<S: WrappingAdd> DeltaDecoder<S> {
+    /// Channels start from `start`, the sample value the first delta applies to.
+    pub fn new(channels: usize, start: S) -> Self {
+        DeltaDecoder { previous: vec![start; channels], channel: 0 }
+    }
+
+    pub fn decode(&mut self, delta: S) -> S {
+        let sample = self.previous[self.channel].wrapping_add(delta);
+        self.previous[self.channel] = sample;
+        self.channel = (self.channel + 1) % self.previous.len();
+        sample
+    }
+}
@@ -0,0 +1,258 @@
+use std::sync::mpsc;
+
+use cpal::traits::{DeviceTrait, HostTrait};
+
+use crate::dither::{Dither, Ditherer};
+
+/// The output device `selector` picks, or the default one without it.
+pub fn output_device(selector: Option<&str>) -> Result<cpal::Device, String> {
+    let host = cpal::default_host();
+    match selector {
+        Some(selector) => {
+            let devices = host.output_devices()
+                .map_err(|e| format!("failed to list the output devices: {e}"))?;
+            select(devices.collect(), selector, "output")
+        },
+        None => host.default_output_device().ok_or_else(|| "failed to find output device".into()),
+    }
+}
+
+/// Opens the output device `selector` picks, or the default one, with its default config. When
+/// that fails the other output devices are tried in order, and the first that opens is used.
+pub fn open_output(selector: Option<&str>) -> Result<(cpal::Device, cpal::SupportedStreamConfig), String> {
+    let device = output_device(selector)?;
+    let name = device.name().unwrap_or_else(|_| "default".into());
+    let error = match device.default_output_config() {
+        Ok(config) => return Ok((device, config)),
+        Err(e) => format!("failed to get the output config of {name}: {e}"),
+    };
+    let devices = cpal::default_host().output_devices().map_err(|_| error.clone())?;
+    for device in devices {
+        let other = device.name().unwrap_or_default();
+        if other == name {
+            continue;
+        }
+        if let Ok(config) = device.default_output_config() {
+            eprintln!("[!] {error}");
+            eprintln!("[!] playing on {other} instead of {name}");
+            return Ok((device, config));
+        }
+    }
+    Err(error)
+}
+
+/// The channels and rate nearest `channels` and `sample_rate` that `device` plays, which are
+/// those two when it plays them or does not say what it plays.
+pub fn negotiate(device: &cpal::Device, channels: u16, sample_rate: u32) -> (u16, u32) {
+    let Ok(configs) = device.supported_output_configs() else {
+        return (channels, sample_rate);
+    };
+    let configs = configs.collect::<Vec<_>>();
+
+    // the same channels, otherwise the fewest above them, otherwise the most below
+    let Some(nearest_channels) = configs.iter()
+        .map(|c| c.channels())
+        .min_by_key(|&c| (c < channels, c.abs_diff(channels))) else {
+        return (channels, sample_rate);
+    };
+    let nearest_rate = configs.iter()
+        .filter(|c| c.channels() == nearest_channels)
+        .map(|c| sample_rate.clamp(c.min_sample_rate().0, c.max_sample_rate().0))
+        .min_by_key(|&rate| (rate.abs_diff(sample_rate), std::cmp::Reverse(rate)))
+        .unwrap_or(sample_rate);
+    (nearest_channels, nearest_rate)
+}
+
+/// The sample format `device` plays `channels` at `sample_rate` in, f32 when it can as that is
+/// what playback makes.
+pub fn output_format(device: &cpal::Device, channels: u16, sample_rate: u32) -> cpal::SampleFormat {
+    let formats = device.supported_output_configs()
+        .map(|configs| configs
+            .filter(|c| c.channels() == channels)
+            .filter(|c| (c.min_sample_rate().0..=c.max_sample_rate().0).contains(&sample_rate))
+            .map(|c| c.sample_format())
+            .collect::<Vec<_>>())
+        .unwrap_or_default();
+    match formats.first() {
+        Some(_) if formats.contains(&cpal::SampleFormat::F32) => cpal::SampleFormat::F32,
+        Some(&format) => format,
+        None => cpal::SampleFormat::F32,
+    }
+}
+
+/// Builds an output stream on `device` that plays samples in `format`, converted from the f32
+/// that `fill` writes. 8 and 16 bit formats are dithered with `dither`.
+pub fn build_output_stream(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    format: cpal::SampleFormat,
+    dither: Dither,
+    mut fill: impl FnMut(&mut [f32], &cpal::OutputCallbackInfo) + Send + 'static,
+    error: impl FnMut(cpal::StreamError) + Send + 'static,
+) -> Result<cpal::Stream, cpal::BuildStreamError> {
+    let mut buffer = Vec::new();
+    let mut ditherer = match format {
+        cpal::SampleFormat::I8 | cpal::SampleFormat::U8 => Some(Ditherer::new(dither, 8, config.channels as usize)),
+        cpal::SampleFormat::I16 | cpal::SampleFormat::U16 => Some(Ditherer::new(dither, 16, config.channels as usize)),
+        _ => None,
+    };
+    device.build_output_stream_raw(
+        config,
+        format,
+        move |data: &mut cpal::Data, info: &cpal::OutputCallbackInfo| {
+            if let Some(data) = data.as_slice_mut::<f32>() {
+                fill(data, info);
+                return;
+            }
+            buffer.resize(data.len(), 0.0);
+            fill(&mut buffer, info);
+            if let Some(ditherer) = &mut ditherer {
+                ditherer.process(&mut buffer);
+            }
+            match data.sample_format() {
+                cpal::SampleFormat::I8 => convert::<i8>(&buffer, data),
+                cpal::SampleFormat::U8 => convert::<u8>(&buffer, data),
+                cpal::SampleFormat::I16 => convert::<i16>(&buffer, data),
+                cpal::SampleFormat::U16 => convert::<u16>(&buffer, data),
+                cpal::SampleFormat::I32 => convert::<i32>(&buffer, data),
+                cpal::SampleFormat::U32 => convert::<u32>(&buffer, data),
+                cpal::SampleFormat::I64 => convert::<i64>(&buffer, data),
+                cpal::SampleFormat::U64 => convert::<u64>(&buffer, data),
+                cpal::SampleFormat::F64 => convert::<f64>(&buffer, data),
+                _ => data.bytes_mut().fill(0),
+            }
+        },
+        error,
+        None,
+    )
+}
+
+/// Lends `fill` to a stream, which owns it without a lock on its callback. It comes back on the
+/// receiver when the stream drops it, so that the next stream carries on with its state.
+pub fn lend<F>(fill: F) -> (Lent<F>, mpsc::Receiver<F>) {
+    let (back, returned) = mpsc::channel();
+    (Lent { fill: Some(fill), back }, returned)
+}
+
+/// The fill lent with [lend], once the stream has dropped it.
+pub fn take_back<F>(returned: &mpsc::Receiver<F>) -> Result<F, String> {
+    returned.recv().map_err(|_| "the output stream lost the playback state".to_string())
+}
+
+/// A fill lent to a stream by [lend].
+pub struct Lent<F> {
+    fill: Option<F>,
+    back: mpsc::Sender<F>,
+}
+
+impl<F> Lent<F> {
+    pub fn get(&mut self) -> &mut F {
+        self.fill.as_mut().expect("a lent fill is only taken when dropped")
+    }
+}
+
+impl<F> Drop for Lent<F> {
+    fn drop(&mut self) {
+        if let Some(fill) = self.fill.take() {
+            let _ = self.back.send(fill);
+        }
+    }
+}
+
+fn convert<T: cpal::SizedSample + dasp_sample::FromSample<f32>>(input: &[f32], data: &mut cpal::Data) {
+    if let Some(output) = data.as_slice_mut::<T>() {
+        for (sample, &value) in output.iter_mut().zip(input) {
+            *sample = T::from_sample_(value);
+        }
+    }
+}
+
+/// The input device `selector` picks, or the default one without it.
+pub fn input_device(selector: Option<&str>) -> Result<cpal::Device, String> {
+    let host = cpal::default_host();
+    match selector {
+        Some(selector) => {
+            let devices = host.input_devices()
+                .map_err(|e| format!("failed to list the input devices: {e}"))?;
+            select(devices.collect(), selector, "input")
+        },
+        None => host.default_input_device().ok_or_else(|| "failed to find input device".into()),
+    }
+}
+
+/// Picks a device by its index in the list, its name, or the one name that contains `selector`
+/// ignoring case.
+fn select(devices: Vec<cpal::Device>, selector: &str, kind: &str) -> Result<cpal::Device, String> {
+    let names = devices.iter().map(|d| d.name().unwrap_or_default()).collect::<Vec<_>>();
+    let lowercase = selector.to_lowercase();
+    let index = match selector.parse::<usize>() {
+        Ok(index) if index < devices.len() => Some(index),
+        _ => names.iter().position(|name| name == selector),
+    };
+    let index = match index {
+        Some(index) => index,
+        None => {
+            let matching = (0..names.len())
+                .filter(|&i| names[i].to_lowercase().contains(&lowercase))
+                .collect::<Vec<_>>();
+            match matching[..] {
+                [index] => index,
+                [] if names.is_empty() => return Err(format!("No {kind} device '{selector}', found no {kind} devices")),
+                [] => return Err(format!("No {kind} device '{selector}', {kind} devices are: {}", names.join(", "))),
+                _ => {
+                    let matching = matching.iter().map(|&i| names[i].as_str()).collect::<Vec<_>>();
+                    return Err(format!("'{selector}' matches several {kind} devices: {}", matching.join(", ")));
+                },
+            }
+        },
+    };
+    Ok(devices.into_iter().nth(index).unwrap())
+}
+
+/// Prints the output and input devices of the default host, by the index and name they can be
+/// picked with, and the stream configs each supports.
+pub fn list() -> Result<(), String> {
+    let host = cpal::default_host();
+    println!("host: {}", host.id().name());
+
+    let default_output = host.default_output_device().and_then(|d| d.name().ok());
+    let outputs = host.output_devices()
+        .map_err(|e| format!("failed to list the output devices: {e}"))?;
+    println!("output devices:");
+    for (index, device) in outputs.enumerate() {
+        print_device(index, &device, default_output.as_deref());
+        match device.supported_output_configs() {
+            Ok(configs) => configs.for_each(|config| println!("        {}", describe(&config))),
+            Err(e) => println!("        no configs: {e}"),
+        }
+    }
+
+    let default_input = host.default_input_device().and_then(|d| d.name().ok());
+    let inputs = host.input_devices()
+        .map_err(|e| format!("failed to list the input devices: {e}"))?;
+    println!("input devices:");
+    for (index, device) in inputs.enumerate() {
+        print_device(index, &device, default_input.as_deref());
+        match device.supported_input_configs() {
+            Ok(configs) => configs.for_each(|config| println!("        {}", describe(&config))),
+            Err(e) => println!("        no configs: {e}"),
+        }
+    }
+    Ok(())
+}
+
+fn print_device(index: usize, device: &cpal::Device, default: Option<&str>) {
+    let name = device.name().unwrap_or_else(|e| format!("<{e}>"));
+    let marker = if default == Some(name.as_str()) { " (default)" } else { "" };
+    println!("  {index}: {name}{marker}");
+}
+
+fn describe(config: &cpal::SupportedStreamConfigRange) -> String {
+    let (min, max) = (config.min_sample_rate().0, config.max_sample_rate().0);
+    let rates = if min == max { format!("{min}Hz") } else { format!("{min}-{max}Hz") };
+    let buffer = match config.buffer_size() {
+        cpal::SupportedBufferSize::Range { min, max } => format!(", buffer {min}-{max} frames"),
+        cpal::SupportedBufferSize::Unknown => String::new(),
+    };
+    format!("{} channels, {rates}, {}{buffer}", config.channels(), config.sample_format())
+}
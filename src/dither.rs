@@ -0,0 +1,102 @@
+use clap::ValueEnum;
+
+/// How samples are dithered when a device plays them in 8 or 16 bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Dither {
+    /// Round to the nearest level, quiet passages distort
+    None,
+    /// Triangular noise of up to a level either way, which turns the rounding error into a steady hiss
+    Tpdf,
+    /// TPDF with the rounding error fed back, moving its hiss up to the highest frequencies
+    /// where it is least audible
+    Shaped,
+}
+
+/// Rounds f32 samples to the levels of a narrower integer format with a [Dither].
+pub struct Ditherer {
+    dither: Dither,
+    /// levels in each half of the range
+    scale: f32,
+    rng: u64,
+    /// the last rounding error of each channel, for [Dither::Shaped]
+    errors: Vec<f32>,
+}
+
+impl Ditherer {
+    /// A ditherer for `channels` interleaved channels played in `bits` bits.
+    pub fn new(dither: Dither, bits: u32, channels: usize) -> Self {
+        let seed = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos() as u64;
+        Ditherer {
+            dither,
+            scale: (1u32 << (bits - 1)) as f32,
+            // xorshift state must not be zero
+            rng: seed | 1,
+            errors: vec![0.0; channels.max(1)],
+        }
+    }
+
+    /// Rounds `samples` to the levels of the format, so that converting them to it is exact.
+    pub fn process(&mut self, samples: &mut [f32]) {
+        let channels = self.errors.len();
+        for (i, sample) in samples.iter_mut().enumerate() {
+            let mut wanted = *sample * self.scale;
+            if self.dither == Dither::Shaped {
+                wanted -= self.errors[i % channels];
+            }
+            let noise = match self.dither {
+                Dither::None => 0.0,
+                Dither::Tpdf | Dither::Shaped => self.next_uniform() + self.next_uniform(),
+            };
+            let level = (wanted + noise).round();
+            // the error before clipping, which stays within a couple of levels
+            self.errors[i % channels] = level - wanted;
+            *sample = level.clamp(-self.scale, self.scale - 1.0) / self.scale;
+        }
+    }
+
+    /// Uniform noise from -0.5 to 0.5.
+    fn next_uniform(&mut self) -> f32 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        (self.rng >> 40) as f32 / (1u32 << 24) as f32 - 0.5
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Samples spread over the range, between the levels of 16 bits.
+    fn ramp() -> Vec<f32> {
+        (0..10_000).map(|i| (i as f32 / 5_000.0 - 1.0) * 0.999 + 0.3 / 32768.0).collect()
+    }
+
+    #[test]
+    fn no_dither_rounds_to_the_nearest_level() {
+        let mut samples = ramp();
+        Ditherer::new(Dither::None, 16, 2).process(&mut samples);
+        for (sample, input) in samples.iter().zip(ramp()) {
+            assert_eq!(*sample, (input * 32768.0).round() / 32768.0);
+        }
+    }
+
+    #[test]
+    fn dither_stays_within_a_level() {
+        for dither in [Dither::Tpdf, Dither::Shaped] {
+            let mut samples = ramp();
+            Ditherer::new(dither, 16, 2).process(&mut samples);
+            for (sample, input) in samples.iter().zip(ramp()) {
+                let level = sample * 32768.0;
+                assert_eq!(level, level.round(), "{dither:?} left {sample} between levels");
+                // the error fed back by shaping is up to a level and a half either way
+                let most = if dither == Dither::Shaped { 3.0 } else { 1.0 };
+                let error = (level - (input * 32768.0).round()).abs();
+                assert!(error <= most, "{dither:?} moved {input} by {error} levels");
+            }
+        }
+    }
+}
@@ -0,0 +1,146 @@
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::sync::{Arc, OnceLock};
+use std::thread;
+use std::time::Duration;
+
+use cpal::traits::{DeviceTrait, StreamTrait};
+
+use crate::measure;
+
+/// Buffers of the input device held for the writer before new ones are dropped.
+const QUEUE: usize = 64;
+/// How long the input is recorded after the last frame is played, for the latency of the
+/// devices and the room's decay.
+const TAIL: Duration = Duration::from_secs(1);
+
+/// Records the default input device to a file while the output plays, starting at the instant
+/// the first frame is played so that the response lines up with the played frames.
+pub struct Recorder {
+    /// when the first frame of the output plays, set by its first callback
+    playback: Arc<OnceLock<cpal::StreamInstant>>,
+    /// buffers sent to the writer and not yet written
+    pending: Arc<AtomicUsize>,
+}
+
+impl Recorder {
+    /// Starts recording to `path` at `sample_rate`, as f32le with the input device's channels.
+    pub fn start(path: &Path, sample_rate: u32) -> Result<Self, String> {
+        let (input, channels) = measure::default_input()?;
+        let mut file = File::create(path).map_err(|e| format!("{}: {e}", path.display()))?;
+        let config = cpal::StreamConfig {
+            channels,
+            sample_rate: cpal::SampleRate(sample_rate),
+            buffer_size: cpal::BufferSize::Default,
+        };
+        let playback = Arc::new(OnceLock::<cpal::StreamInstant>::new());
+        let pending = Arc::new(AtomicUsize::new(0));
+
+        let (buffers_tx, buffers) = mpsc::sync_channel::<Vec<f32>>(QUEUE);
+        {
+            let pending = pending.clone();
+            let path = path.to_path_buf();
+            thread::spawn(move || {
+                for buffer in buffers {
+                    let bytes = buffer.iter().flat_map(|s| s.to_le_bytes()).collect::<Vec<_>>();
+                    if let Err(e) = file.write_all(&bytes) {
+                        eprintln!("[!] stopped recording, {}: {e}", path.display());
+                        pending.store(0, Ordering::Relaxed);
+                        return;
+                    }
+                    pending.fetch_sub(1, Ordering::Relaxed);
+                }
+            });
+        }
+
+        let (opened_tx, opened) = mpsc::channel();
+        {
+            let playback = playback.clone();
+            let pending = pending.clone();
+            // a stream can not move between threads on every host, this one keeps it
+            thread::spawn(move || {
+                let channels = channels as usize;
+                let frames = move |d: Duration| (d.as_secs_f64() * sample_rate as f64).round() as usize;
+                let mut started = false;
+                let mut dropped = false;
+                let stream = input.build_input_stream(
+                    &config,
+                    move |data: &[f32], info: &cpal::InputCallbackInfo| {
+                        // captured before the output's first callback, so before it plays
+                        let Some(&playback) = playback.get() else {
+                            return;
+                        };
+                        let mut buffer = Vec::with_capacity(data.len());
+                        if started {
+                            buffer.extend_from_slice(data);
+                        } else {
+                            let capture = info.timestamp().capture;
+                            match playback.duration_since(&capture) {
+                                // what was captured before the first frame played is left out
+                                Some(lead) => {
+                                    let skip = frames(lead) * channels;
+                                    if skip >= data.len() {
+                                        return;
+                                    }
+                                    buffer.extend_from_slice(&data[skip..]);
+                                },
+                                None => {
+                                    let pad = capture.duration_since(&playback).map_or(0, frames);
+                                    eprintln!("[!] recording started {pad} frames after playback, padded with silence");
+                                    buffer.resize(pad * channels, 0.0);
+                                    buffer.extend_from_slice(data);
+                                },
+                            }
+                            started = true;
+                        }
+                        pending.fetch_add(1, Ordering::Relaxed);
+                        match buffers_tx.try_send(buffer) {
+                            Ok(()) => dropped = false,
+                            Err(e) => {
+                                pending.fetch_sub(1, Ordering::Relaxed);
+                                if matches!(e, mpsc::TrySendError::Full(_)) && !dropped {
+                                    eprintln!("[!] writing the recording fell behind, dropping captured samples");
+                                    dropped = true;
+                                }
+                            },
+                        }
+                    },
+                    move |err| eprintln!("an error occurred on stream: {}", err),
+                    None,
+                );
+                let stream = stream
+                    .map_err(|e| format!("failed to open the input device: {e}"))
+                    .and_then(|stream| stream.play().map(|()| stream).map_err(|e| format!("{e}")));
+                match stream {
+                    Ok(_stream) => {
+                        let _ = opened_tx.send(Ok(()));
+                        loop {
+                            thread::park();
+                        }
+                    },
+                    Err(msg) => {
+                        let _ = opened_tx.send(Err(msg));
+                    },
+                }
+            });
+        }
+        opened.recv().map_err(|_| "the recording thread stopped".to_string())??;
+        Ok(Recorder { playback, pending })
+    }
+
+    /// Notes when the output plays, from each of its callbacks.
+    pub fn played(&self, info: &cpal::OutputCallbackInfo) {
+        self.playback.get_or_init(|| info.timestamp().playback);
+    }
+
+    /// Records the tail after the last frame is played and waits for all of it to be written.
+    pub fn finish(&self) {
+        thread::sleep(TAIL);
+        while self.pending.load(Ordering::Relaxed) > 0 {
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
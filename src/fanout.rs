@@ -0,0 +1,56 @@
+use std::time::Duration;
+
+use cpal::traits::{DeviceTrait, StreamTrait};
+
+use crate::devices;
+use crate::dither::Dither;
+use crate::ring::{self, Writer};
+
+/// Most audio buffered for a device before what does not fit is dropped, for one that plays slower.
+const MAX_BUFFERED: Duration = Duration::from_millis(200);
+
+/// Copies what the first device plays to more devices, each through a buffer of its own, so that
+/// their clocks drifting apart costs a dropout on that device alone. The buffers are lock-free
+/// rings, so that no device's callback waits on another's.
+pub struct Fanout {
+    writers: Vec<Writer>,
+    channels: usize,
+}
+
+impl Fanout {
+    /// Opens the devices `selectors` pick at `config` with `dither`, the streams play while they are kept.
+    pub fn open(selectors: &[String], config: &cpal::StreamConfig, dither: Dither) -> Result<(Self, Vec<cpal::Stream>), String> {
+        let capacity = (MAX_BUFFERED.as_secs_f64() * config.sample_rate.0 as f64) as usize * config.channels as usize;
+        let mut writers = Vec::new();
+        let mut streams = Vec::new();
+        for selector in selectors {
+            let device = devices::output_device(Some(selector))?;
+            let name = device.name().unwrap_or_else(|_| selector.clone());
+            let format = devices::output_format(&device, config.channels, config.sample_rate.0);
+            let (writer, mut reader) = ring::ring(capacity);
+            let stream = devices::build_output_stream(
+                &device,
+                config,
+                format,
+                dither,
+                move |data, _| {
+                    let len = reader.read(data);
+                    data[len..].fill(0.0);
+                },
+                move |err| eprintln!("an error occurred on stream: {}", err),
+            ).map_err(|e| format!("failed to open {name}: {e}"))?;
+            stream.play().map_err(|e| format!("{e}"))?;
+            eprintln!("[!] also playing on {name}");
+            writers.push(writer);
+            streams.push(stream);
+        }
+        Ok((Fanout { writers, channels: config.channels as usize }, streams))
+    }
+
+    /// Queues what the first device was given to the others, dropping what a full buffer can not take.
+    pub fn push(&mut self, data: &[f32]) {
+        for writer in &mut self.writers {
+            writer.write(data, self.channels);
+        }
+    }
+}
@@ -0,0 +1,48 @@
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use crate::container;
+
+/// Whether `path` is a named pipe.
+#[cfg(unix)]
+pub fn is_fifo(path: &Path) -> bool {
+    use std::os::unix::fs::FileTypeExt;
+    fs::metadata(path).is_ok_and(|m| m.file_type().is_fifo())
+}
+
+#[cfg(not(unix))]
+pub fn is_fifo(_path: &Path) -> bool {
+    false
+}
+
+/// Reads a named pipe as one endless stream: at the end of a writer's stream the pipe is
+/// reopened, which waits for the next writer. A container header at the start of a later
+/// writer's stream is skipped, the format of the first one applies throughout.
+pub struct ReopenFifo {
+    path: PathBuf,
+    input: Box<dyn Read + Send>,
+}
+
+impl ReopenFifo {
+    pub fn new(path: &Path, file: fs::File) -> Self {
+        ReopenFifo { path: path.to_path_buf(), input: Box::new(file) }
+    }
+}
+
+impl Read for ReopenFifo {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let len = self.input.read(buf)?;
+            if len > 0 || buf.is_empty() {
+                return Ok(len);
+            }
+            let file = fs::File::open(&self.path)?;
+            let (detected, input) = container::detect(file).map_err(io::Error::other)?;
+            if let Some(detected) = detected {
+                eprintln!("[!] skipped the {} header of the next writer", detected.container);
+            }
+            self.input = input;
+        }
+    }
+}
@@ -0,0 +1,148 @@
+use std::f64::consts::{FRAC_1_SQRT_2, TAU};
+
+/// Biquad filter in direct form I.
+#[derive(Debug, Clone, Copy)]
+pub struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    x: [f64; 2],
+    y: [f64; 2],
+}
+
+/// Q of the two sections of a fourth order Butterworth filter.
+const BUTTERWORTH_Q4: [f64; 2] = [0.5411961001461971, 1.3065629648763766];
+
+impl Biquad {
+    /// A filter with coefficients normalized to `a0 = 1`.
+    pub fn new(b: [f64; 3], a: [f64; 2]) -> Self {
+        Biquad { b, a, x: [0.0; 2], y: [0.0; 2] }
+    }
+
+    /// Second order low pass, after the Audio EQ Cookbook.
+    pub fn low_pass(sample_rate: u32, frequency: f64, q: f64) -> Self {
+        let (cos, alpha) = Self::prewarp(sample_rate, frequency, q);
+        let a0 = 1.0 + alpha;
+        let b = (1.0 - cos) / 2.0 / a0;
+        Biquad::new([b, 2.0 * b, b], [-2.0 * cos / a0, (1.0 - alpha) / a0])
+    }
+
+    /// Second order high pass, after the Audio EQ Cookbook.
+    pub fn high_pass(sample_rate: u32, frequency: f64, q: f64) -> Self {
+        let (cos, alpha) = Self::prewarp(sample_rate, frequency, q);
+        let a0 = 1.0 + alpha;
+        let b = (1.0 + cos) / 2.0 / a0;
+        Biquad::new([b, -2.0 * b, b], [-2.0 * cos / a0, (1.0 - alpha) / a0])
+    }
+
+    fn prewarp(sample_rate: u32, frequency: f64, q: f64) -> (f64, f64) {
+        let w0 = TAU * frequency / sample_rate as f64;
+        (w0.cos(), w0.sin() / (2.0 * q))
+    }
+
+    pub fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.b[1] * self.x[0] + self.b[2] * self.x[1]
+            - self.a[0] * self.y[0] - self.a[1] * self.y[1];
+        self.x = [x, self.x[0]];
+        self.y = [y, self.y[0]];
+        y
+    }
+}
+
+/// Fourth order Butterworth sections of one channel.
+type Butterworth4 = [Biquad; 2];
+
+fn butterworth4(design: fn(u32, f64, f64) -> Biquad, sample_rate: u32, frequency: f64) -> Butterworth4 {
+    BUTTERWORTH_Q4.map(|q| design(sample_rate, frequency, q))
+}
+
+fn process4(sections: &mut Butterworth4, x: f64) -> f64 {
+    sections.iter_mut().fold(x, |x, section| section.process(x))
+}
+
+/// Content above this frequency is inaudible to most listeners, but can still burn out tweeters.
+pub const ULTRASONIC: f64 = 18_000.0;
+/// How long the content has to last, the time constant of its level in seconds.
+const SUSTAIN: f64 = 1.0;
+/// RMS level of content above [ULTRASONIC] that is treated as unsafe.
+const ULTRASONIC_LIMIT_DB: f64 = -20.0;
+
+/// How long the low-pass takes to fade in once the guard trips, in seconds.
+const CROSSFADE: f64 = 0.01;
+
+/// Watches for sustained high level content above 18 kHz, as data played as audio tends to have,
+/// and low-passes every channel from the moment it is found.
+///
+/// The low-pass runs from the start, so that when the guard trips its output is crossfaded in
+/// rather than switched to from a cold filter.
+pub struct UltrasonicGuard {
+    detectors: Vec<Butterworth4>,
+    mean_squares: Vec<f64>,
+    smoothing: f64,
+    limit: f64,
+    filters: Vec<Butterworth4>,
+    /// share of the low-passed signal in each channel's output, from 0 to 1
+    mix: Vec<f64>,
+    /// what the share grows by every frame once tripped
+    step: f64,
+    tripped: bool,
+}
+
+impl UltrasonicGuard {
+    /// None when the sample rate is too low to carry content above 18 kHz.
+    pub fn new(sample_rate: u32, channels: usize) -> Option<Self> {
+        if (sample_rate as f64) < 2.2 * ULTRASONIC {
+            return None;
+        }
+        Some(UltrasonicGuard {
+            detectors: vec![butterworth4(Biquad::high_pass, sample_rate, ULTRASONIC); channels],
+            mean_squares: vec![0.0; channels],
+            smoothing: 1.0 - (-1.0 / (SUSTAIN * sample_rate as f64)).exp(),
+            limit: 10f64.powf(ULTRASONIC_LIMIT_DB / 10.0),
+            filters: vec![butterworth4(Biquad::low_pass, sample_rate, ULTRASONIC); channels],
+            mix: vec![0.0; channels],
+            step: 1.0 / (CROSSFADE * sample_rate as f64),
+            tripped: false,
+        })
+    }
+
+    pub fn process(&mut self, channel: usize, x: f64) -> f64 {
+        let low = process4(&mut self.filters[channel], x);
+        if self.tripped {
+            let mix = &mut self.mix[channel];
+            *mix = (*mix + self.step).min(1.0);
+            return x + (low - x) * *mix;
+        }
+
+        let high = process4(&mut self.detectors[channel], x);
+        let mean_square = &mut self.mean_squares[channel];
+        *mean_square += self.smoothing * (high * high - *mean_square);
+        if *mean_square > self.limit {
+            eprintln!(
+                "[!] sustained content above {} kHz louder than {ULTRASONIC_LIMIT_DB} dBFS on channel {}, low-passing the output to protect tweeters, --allow-ultrasonic plays it as is",
+                ULTRASONIC / 1000.0,
+                channel + 1,
+            );
+            self.tripped = true;
+        }
+        x
+    }
+}
+
+/// Below the lowest audible notes, where large cone excursions only strain woofers.
+pub const SUBSONIC: f64 = 15.0;
+
+/// High-passes every channel at 15 Hz, taking out DC offsets and the slow swings
+/// of data played as audio.
+pub struct SubsonicFilter {
+    filters: Vec<Biquad>,
+}
+
+impl SubsonicFilter {
+    pub fn new(sample_rate: u32, channels: usize) -> Self {
+        SubsonicFilter { filters: vec![Biquad::high_pass(sample_rate, SUBSONIC, FRAC_1_SQRT_2); channels] }
+    }
+
+    pub fn process(&mut self, channel: usize, x: f64) -> f64 {
+        self.filters[channel].process(x)
+    }
+}
@@ -0,0 +1,30 @@
+use std::io::{self, Read};
+use std::thread;
+use std::time::Duration;
+
+/// How often the end of a followed file is checked for new data.
+const POLL: Duration = Duration::from_millis(100);
+
+/// Reads a file that is still being written, like `tail -f`: at the end of the file,
+/// waits for more data instead of ending.
+pub struct Follow<R> {
+    inner: R,
+}
+
+impl<R: Read> Follow<R> {
+    pub fn new(inner: R) -> Self {
+        Follow { inner }
+    }
+}
+
+impl<R: Read> Read for Follow<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let len = self.inner.read(buf)?;
+            if len > 0 || buf.is_empty() {
+                return Ok(len);
+            }
+            thread::sleep(POLL);
+        }
+    }
+}
@@ -0,0 +1,482 @@
+use std::f64::consts::TAU;
+use std::io;
+use std::time::Duration;
+
+use clap::ValueEnum;
+
+use crate::loudness::{self, KWeighting};
+
+/// Peak level of tones, half of full scale so a bell isn't startling at the default gain.
+const LEVEL: f64 = 0.5;
+/// Time the tone takes to rise or fall when keyed, avoids clicks at the edges.
+const RAMP: Duration = Duration::from_millis(5);
+/// Silence after the last segment, so the device plays out the end of the tone
+/// before the input runs out.
+const TAIL: Duration = Duration::from_millis(150);
+
+/// A stretch of a [ToneSource], the sum of some tones or silence.
+#[derive(Debug, Clone)]
+pub struct Segment {
+    pub duration: Duration,
+    /// frequencies in Hz, none for silence
+    pub frequencies: Vec<f64>,
+}
+
+impl Segment {
+    pub fn tone(duration: Duration, frequencies: &[f64]) -> Self {
+        Segment { duration, frequencies: frequencies.to_vec() }
+    }
+
+    pub fn silence(duration: Duration) -> Self {
+        Segment { duration, frequencies: Vec::new() }
+    }
+}
+
+/// Generates sine tones keyed on and off by segments, as f32 little-endian samples.
+pub struct ToneSource {
+    segments: Vec<(usize, Vec<f64>)>,
+    segment: usize,
+    frame: usize,
+    channels: usize,
+    /// phase and phase step of each tone, kept through silence so keying off fades the last tones
+    oscillators: Vec<(f64, f64)>,
+    envelope: f64,
+    ramp_step: f64,
+    /// bytes of the current frame not yet read
+    pending: Vec<u8>,
+}
+
+impl ToneSource {
+    pub fn new(sample_rate: u32, channels: usize, segments: &[Segment]) -> Self {
+        let frames = |d: Duration| (d.as_secs_f64() * sample_rate as f64).round() as usize;
+        let mut segments = segments.iter()
+            .map(|s| {
+                let steps = s.frequencies.iter().map(|f| TAU * f / sample_rate as f64).collect();
+                (frames(s.duration), steps)
+            })
+            .collect::<Vec<_>>();
+        segments.push((frames(TAIL), Vec::new()));
+        let oscillators = segments.iter()
+            .find(|(_, steps)| !steps.is_empty())
+            .map_or_else(Vec::new, |(_, steps)| steps.iter().map(|&step| (0.0, step)).collect());
+        ToneSource {
+            segments,
+            segment: 0,
+            frame: 0,
+            channels,
+            oscillators,
+            envelope: 0.0,
+            ramp_step: 1.0 / frames(RAMP).max(1) as f64,
+            pending: Vec::new(),
+        }
+    }
+
+    /// The next sample, None after the last segment.
+    fn next_value(&mut self) -> Option<f32> {
+        while self.frame == self.segments.get(self.segment)?.0 {
+            self.segment += 1;
+            self.frame = 0;
+            let steps = &self.segments.get(self.segment)?.1;
+            if !steps.is_empty() {
+                // phases carry over, so tones that follow each other directly join without a step
+                self.oscillators.resize(steps.len(), (0.0, 0.0));
+                for ((_, step), &new_step) in self.oscillators.iter_mut().zip(steps) {
+                    *step = new_step;
+                }
+            }
+        }
+        let on = !self.segments[self.segment].1.is_empty();
+        self.frame += 1;
+
+        self.envelope = if on {
+            (self.envelope + self.ramp_step).min(1.0)
+        } else {
+            (self.envelope - self.ramp_step).max(0.0)
+        };
+        // raised cosine edges
+        let gain = 0.5 - 0.5 * (self.envelope * std::f64::consts::PI).cos();
+        let level = LEVEL / self.oscillators.len().max(1) as f64;
+        let mut value = 0.0;
+        for (phase, step) in &mut self.oscillators {
+            value += phase.sin();
+            *phase = (*phase + *step) % TAU;
+        }
+        Some((level * gain * value) as f32)
+    }
+}
+
+impl io::Read for ToneSource {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut written = 0;
+        while written < buf.len() {
+            if self.pending.is_empty() {
+                let Some(value) = self.next_value() else {
+                    break;
+                };
+                let bytes = value.to_le_bytes();
+                self.pending = bytes.repeat(self.channels);
+                self.pending.reverse();
+            }
+            while written < buf.len() && let Some(byte) = self.pending.pop() {
+                buf[written] = byte;
+                written += 1;
+            }
+        }
+        Ok(written)
+    }
+}
+
+/// ITU morse code of the characters `morse_segments` can key.
+const MORSE: &[(char, &str)] = &[
+    ('A', ".-"), ('B', "-..."), ('C', "-.-."), ('D', "-.."), ('E', "."), ('F', "..-."),
+    ('G', "--."), ('H', "...."), ('I', ".."), ('J', ".---"), ('K', "-.-"), ('L', ".-.."),
+    ('M', "--"), ('N', "-."), ('O', "---"), ('P', ".--."), ('Q', "--.-"), ('R', ".-."),
+    ('S', "..."), ('T', "-"), ('U', "..-"), ('V', "...-"), ('W', ".--"), ('X', "-..-"),
+    ('Y', "-.--"), ('Z', "--.."),
+    ('0', "-----"), ('1', ".----"), ('2', "..---"), ('3', "...--"), ('4', "....-"),
+    ('5', "....."), ('6', "-...."), ('7', "--..."), ('8', "---.."), ('9', "----."),
+    ('.', ".-.-.-"), (',', "--..--"), ('?', "..--.."), ('\'', ".----."), ('!', "-.-.--"),
+    ('/', "-..-."), ('(', "-.--."), (')', "-.--.-"), ('&', ".-..."), (':', "---..."),
+    (';', "-.-.-."), ('=', "-...-"), ('+', ".-.-."), ('-', "-....-"), ('_', "..--.-"),
+    ('"', ".-..-."), ('$', "...-..-"), ('@', ".--.-."),
+];
+
+/// Keys `text` as morse code at `wpm` words per minute, timed on the word PARIS:
+/// a dah is three dits, and gaps are a dit within a character, three between characters
+/// and seven between words.
+pub fn morse_segments(text: &str, wpm: f64, frequency: f64) -> Result<Vec<Segment>, String> {
+    let invalid = || format!("Invalid speed '{wpm}' words per minute");
+    if wpm.is_nan() || wpm <= 0.0 {
+        return Err(invalid());
+    }
+    // a speed so slow that the gap between words does not fit a Duration is as invalid
+    let dit = Duration::try_from_secs_f64(1.2 / wpm)
+        .ok()
+        .filter(|dit| dit.checked_mul(7).is_some())
+        .ok_or_else(invalid)?;
+    let mut segments = Vec::new();
+    let gap = |segments: &mut Vec<Segment>, dits: u32| match segments.last_mut() {
+        // widen the gap that ends the previous element, nothing precedes the first one
+        Some(last) if last.frequencies.is_empty() => last.duration = last.duration.max(dit * dits),
+        Some(_) => segments.push(Segment::silence(dit * dits)),
+        None => (),
+    };
+
+    for word in text.split_whitespace() {
+        gap(&mut segments, 7);
+        for c in word.chars() {
+            let upper = c.to_ascii_uppercase();
+            let Some((_, code)) = MORSE.iter().find(|(m, _)| *m == upper) else {
+                return Err(format!("No morse code for '{c}'"));
+            };
+            gap(&mut segments, 3);
+            for element in code.chars() {
+                gap(&mut segments, 1);
+                let dits = if element == '-' { 3 } else { 1 };
+                segments.push(Segment::tone(dit * dits, &[frequency]));
+            }
+        }
+    }
+    if segments.is_empty() {
+        return Err("Nothing to key, the text is empty".into());
+    }
+    Ok(segments)
+}
+
+/// DTMF row and column frequencies of the keys `dtmf_segments` can dial.
+const DTMF_KEYS: [[char; 4]; 4] = [
+    ['1', '2', '3', 'A'],
+    ['4', '5', '6', 'B'],
+    ['7', '8', '9', 'C'],
+    ['*', '0', '#', 'D'],
+];
+const DTMF_ROWS: [f64; 4] = [697.0, 770.0, 852.0, 941.0];
+const DTMF_COLUMNS: [f64; 4] = [1209.0, 1336.0, 1477.0, 1633.0];
+
+/// Dials `digits` as DTMF, each key held for `duration` with `gap` between keys.
+/// Spaces, dashes, dots, parentheses and `+` separate digits and are skipped, a comma pauses for a second.
+pub fn dtmf_segments(digits: &str, duration: Duration, gap: Duration) -> Result<Vec<Segment>, String> {
+    let mut segments = Vec::new();
+    for c in digits.chars() {
+        let key = c.to_ascii_uppercase();
+        if let Some((row, column)) = DTMF_KEYS.iter().enumerate()
+            .find_map(|(row, keys)| keys.iter().position(|&k| k == key).map(|column| (row, column))) {
+            segments.push(Segment::tone(duration, &[DTMF_ROWS[row], DTMF_COLUMNS[column]]));
+            segments.push(Segment::silence(gap));
+        } else if c == ',' {
+            segments.push(Segment::silence(Duration::from_secs(1)));
+        } else if !matches!(c, ' ' | '-' | '.' | '(' | ')' | '+') {
+            return Err(format!("No DTMF key for '{c}'"));
+        }
+    }
+    if segments.is_empty() {
+        return Err("Nothing to dial, no digits given".into());
+    }
+    Ok(segments)
+}
+
+/// Call progress tones of the North American precise tone plan, and the special information tone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum TelephonyTone {
+    /// 350+440Hz, continuous
+    Dial,
+    /// 440+480Hz, 2s on, 4s off
+    Ringback,
+    /// 480+620Hz, 0.5s on, 0.5s off
+    Busy,
+    /// 480+620Hz, 0.25s on, 0.25s off, also known as congestion or fast busy
+    Reorder,
+    /// 913.8, 1370.6 and 1776.7Hz in turn, the rising three tones before an intercept message
+    Sit,
+}
+
+impl TelephonyTone {
+    /// Segments repeating the tone's cadence for about `duration`, whole cycles only.
+    /// The special information tone plays once.
+    pub fn segments(self, duration: Duration) -> Vec<Segment> {
+        let ms = Duration::from_millis;
+        let cadence = match self {
+            TelephonyTone::Dial => return vec![Segment::tone(duration, &[350.0, 440.0])],
+            TelephonyTone::Sit => {
+                return vec![
+                    Segment::tone(ms(274), &[913.8]),
+                    Segment::tone(ms(274), &[1370.6]),
+                    Segment::tone(ms(380), &[1776.7]),
+                ];
+            },
+            TelephonyTone::Ringback => [Segment::tone(ms(2000), &[440.0, 480.0]), Segment::silence(ms(4000))],
+            TelephonyTone::Busy => [Segment::tone(ms(500), &[480.0, 620.0]), Segment::silence(ms(500))],
+            TelephonyTone::Reorder => [Segment::tone(ms(250), &[480.0, 620.0]), Segment::silence(ms(250))],
+        };
+        let cycle = cadence.iter().map(|s| s.duration).sum::<Duration>();
+        let cycles = (duration.as_secs_f64() / cycle.as_secs_f64()).round().max(1.0) as usize;
+        cadence.iter().cloned().cycle().take(cycles * cadence.len()).collect()
+    }
+}
+
+/// Frequency of the first channel of a test pattern, each later channel is a step higher.
+const PATTERN_BASE: f64 = 400.0;
+const PATTERN_STEP: f64 = 100.0;
+/// Beeps counting the channel number: long ones for tens, short ones for units.
+const COUNT_SHORT: Duration = Duration::from_millis(150);
+const COUNT_LONG: Duration = Duration::from_millis(450);
+const COUNT_GAP: Duration = Duration::from_millis(150);
+/// Steady tone closing each cycle of a test pattern, after the count.
+const PATTERN_STEADY: Duration = Duration::from_secs(1);
+
+/// Frequency of a channel of a [PatternSource].
+pub fn pattern_frequency(channel: usize) -> f64 {
+    PATTERN_BASE + PATTERN_STEP * channel as f64
+}
+
+/// Generates a test pattern identifying every channel at once, as f32 little-endian samples:
+/// each channel counts its number in beeps, then holds a steady tone, all at a frequency of its own.
+/// The cycle repeats until stopped.
+pub struct PatternSource {
+    /// frame ranges of a cycle the tone is on in, per channel
+    keyed: Vec<Vec<(usize, usize)>>,
+    cycle: usize,
+    frame: usize,
+    /// phase and phase step per channel
+    oscillators: Vec<(f64, f64)>,
+    ramp: usize,
+    pending: Vec<u8>,
+}
+
+impl PatternSource {
+    pub fn new(sample_rate: u32, channels: usize) -> Self {
+        let frames = |d: Duration| (d.as_secs_f64() * sample_rate as f64).round() as usize;
+        let gap = frames(COUNT_GAP);
+        let mut keyed = (1..=channels)
+            .map(|number| {
+                let beeps = std::iter::repeat_n(frames(COUNT_LONG), number / 10)
+                    .chain(std::iter::repeat_n(frames(COUNT_SHORT), number % 10));
+                let mut start = 0;
+                let mut ranges = Vec::new();
+                for len in beeps {
+                    ranges.push((start, start + len));
+                    start += len + gap;
+                }
+                ranges
+            })
+            .collect::<Vec<_>>();
+
+        // the steady tones start together once the longest count is over
+        let steady_start = keyed.iter().filter_map(|ranges| ranges.last()).map(|&(_, end)| end).max().unwrap_or(0) + 2 * gap;
+        let steady_end = steady_start + frames(PATTERN_STEADY);
+        for ranges in &mut keyed {
+            ranges.push((steady_start, steady_end));
+        }
+        let oscillators = (0..channels)
+            .map(|c| (0.0, TAU * pattern_frequency(c) / sample_rate as f64))
+            .collect();
+        PatternSource {
+            keyed,
+            cycle: steady_end + 2 * gap,
+            frame: 0,
+            oscillators,
+            ramp: frames(RAMP).max(1),
+            pending: Vec::new(),
+        }
+    }
+}
+
+impl io::Read for PatternSource {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut written = 0;
+        while written < buf.len() {
+            if self.pending.is_empty() {
+                for (ranges, (phase, step)) in self.keyed.iter().zip(&mut self.oscillators) {
+                    let edge = ranges.iter()
+                        .find(|&&(start, end)| (start..end).contains(&self.frame))
+                        .map_or(0, |&(start, end)| (self.frame - start).min(end - self.frame));
+                    // raised cosine edges
+                    let envelope = (edge as f64 / self.ramp as f64).min(1.0);
+                    let gain = 0.5 - 0.5 * (envelope * std::f64::consts::PI).cos();
+                    let value = (LEVEL * gain * phase.sin()) as f32;
+                    *phase = (*phase + *step) % TAU;
+                    self.pending.extend(value.to_le_bytes());
+                }
+                self.pending.reverse();
+                self.frame = (self.frame + 1) % self.cycle;
+            }
+            while written < buf.len() && let Some(byte) = self.pending.pop() {
+                buf[written] = byte;
+                written += 1;
+            }
+        }
+        Ok(written)
+    }
+}
+
+/// Spectrum of a [NoiseSource].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum NoiseColor {
+    /// Equal power per Hz
+    White,
+    /// Equal power per octave, -3dB/octave
+    Pink,
+    /// -6dB/octave, a deep rumble
+    Brown,
+}
+
+/// Audio measured to find the gain for a loudness.
+const CALIBRATION: Duration = Duration::from_secs(5);
+const NOISE_FADE_IN: Duration = Duration::from_secs(1);
+/// Fade at the end of a timed run, so it doesn't stop abruptly.
+const NOISE_FADE_OUT: Duration = Duration::from_secs(10);
+
+/// Generates uncorrelated noise on every channel at a given loudness, as f32 little-endian samples.
+pub struct NoiseSource {
+    color: NoiseColor,
+    rng: u64,
+    /// filter state per channel
+    states: Vec<[f64; 7]>,
+    gain: f64,
+    frame: usize,
+    /// frames to play, None to play until stopped
+    frames: Option<usize>,
+    fade_in: usize,
+    fade_out: usize,
+    pending: Vec<u8>,
+}
+
+impl NoiseSource {
+    pub fn new(sample_rate: u32, channels: usize, color: NoiseColor, lufs: f64, duration: Option<Duration>) -> Self {
+        let frames = |d: Duration| (d.as_secs_f64() * sample_rate as f64).round() as usize;
+        let seed = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos() as u64;
+        let mut source = NoiseSource {
+            color,
+            // xorshift state must not be zero
+            rng: seed | 1,
+            states: vec![[0.0; 7]; channels],
+            gain: 1.0,
+            frame: 0,
+            frames: duration.map(frames),
+            fade_in: frames(NOISE_FADE_IN).max(1),
+            fade_out: duration.map_or(1, |d| frames(NOISE_FADE_OUT.min(d / 2)).max(1)),
+            pending: Vec::new(),
+        };
+
+        let mut weighting = vec![KWeighting::new(sample_rate); channels];
+        let mut mean_squares = vec![0.0; channels];
+        let mut peak = 0f64;
+        let calibration_frames = frames(CALIBRATION);
+        for _ in 0..calibration_frames {
+            for c in 0..channels {
+                let value = source.next_raw(c);
+                peak = peak.max(value.abs());
+                mean_squares[c] += weighting[c].process(value).powi(2) / calibration_frames as f64;
+            }
+        }
+        source.gain = 10f64.powf((lufs - loudness::lufs(&mean_squares)) / 20.0);
+        if peak * source.gain > 1.0 {
+            eprintln!("[!] {color:?} noise at {lufs} LUFS peaks above full scale and will clip");
+        }
+        source
+    }
+
+    fn next_white(&mut self) -> f64 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        (self.rng >> 11) as f64 / (1u64 << 52) as f64 - 1.0
+    }
+
+    /// The next unscaled sample of `channel`.
+    fn next_raw(&mut self, channel: usize) -> f64 {
+        let white = self.next_white();
+        let b = &mut self.states[channel];
+        match self.color {
+            NoiseColor::White => white,
+            // Paul Kellet's refined pink noise filter
+            NoiseColor::Pink => {
+                b[0] = 0.99886 * b[0] + white * 0.0555179;
+                b[1] = 0.99332 * b[1] + white * 0.0750759;
+                b[2] = 0.96900 * b[2] + white * 0.1538520;
+                b[3] = 0.86650 * b[3] + white * 0.3104856;
+                b[4] = 0.55000 * b[4] + white * 0.5329522;
+                b[5] = -0.7616 * b[5] - white * 0.0168980;
+                let pink = b[0] + b[1] + b[2] + b[3] + b[4] + b[5] + b[6] + white * 0.5362;
+                b[6] = white * 0.115926;
+                pink * 0.11
+            },
+            // leaky integration of white noise
+            NoiseColor::Brown => {
+                b[0] = (b[0] + 0.02 * white) / 1.02;
+                b[0] * 3.5
+            },
+        }
+    }
+}
+
+impl io::Read for NoiseSource {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut written = 0;
+        while written < buf.len() {
+            if self.pending.is_empty() {
+                if self.frames.is_some_and(|frames| self.frame >= frames) {
+                    break;
+                }
+                let frames_left = self.frames.map_or(usize::MAX, |frames| frames - self.frame);
+                let fade = (self.frame as f64 / self.fade_in as f64)
+                    .min(frames_left as f64 / self.fade_out as f64)
+                    .min(1.0);
+                self.frame += 1;
+                for c in 0..self.states.len() {
+                    let value = (self.next_raw(c) * self.gain * fade).clamp(-1.0, 1.0) as f32;
+                    self.pending.extend(value.to_le_bytes());
+                }
+                self.pending.reverse();
+            }
+            while written < buf.len() && let Some(byte) = self.pending.pop() {
+                buf[written] = byte;
+                written += 1;
+            }
+        }
+        Ok(written)
+    }
+}
@@ -0,0 +1,261 @@
+use std::io;
+
+/// marks the start of an rplay stream header
+pub const MAGIC: [u8; 4] = *b"RPLY";
+pub const VERSION: u8 = 1;
+/// header length in bytes, including the magic
+pub const LEN: usize = 16;
+
+const FLAG_FLOAT: u8 = 1 << 0;
+const FLAG_UNSIGNED: u8 = 1 << 1;
+const FLAG_BIG_ENDIAN: u8 = 1 << 2;
+const FLAG_SIGN_MAGNITUDE: u8 = 1 << 3;
+const FLAG_SILENCE_RUNS: u8 = 1 << 4;
+
+/// set in a run token for a run of silent frames, clear for a run of sample frames
+pub const RUN_SILENCE: u32 = 1 << 31;
+/// run token that ends the runs, an index of them may follow
+pub const RUNS_END: u32 = RUN_SILENCE;
+/// marks the end of an index of runs, after its entry count
+pub const INDEX_MAGIC: [u8; 4] = *b"RIDX";
+
+/// Describes the raw samples that follow it in a stream.
+///
+/// Layout, multi-byte fields little-endian:
+/// `magic[4] version[1] flags[1] sample_size[1] reserved[1] channels[2] reserved[2] sample_rate[4]`
+///
+/// With the silence runs flag the samples are split into runs, see [SilenceRuns].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StreamHeader {
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub sample_size: u8,
+    pub float: bool,
+    pub unsigned: bool,
+    pub big_endian: bool,
+    pub sign_magnitude: bool,
+    pub silence_runs: bool,
+}
+
+impl StreamHeader {
+    pub fn to_bytes(self) -> [u8; LEN] {
+        let mut flags = 0;
+        for (set, flag) in [
+            (self.float, FLAG_FLOAT),
+            (self.unsigned, FLAG_UNSIGNED),
+            (self.big_endian, FLAG_BIG_ENDIAN),
+            (self.sign_magnitude, FLAG_SIGN_MAGNITUDE),
+            (self.silence_runs, FLAG_SILENCE_RUNS),
+        ] {
+            if set {
+                flags |= flag;
+            }
+        }
+
+        let mut bytes = [0u8; LEN];
+        bytes[0..4].copy_from_slice(&MAGIC);
+        bytes[4] = VERSION;
+        bytes[5] = flags;
+        bytes[6] = self.sample_size;
+        bytes[8..10].copy_from_slice(&self.channels.to_le_bytes());
+        bytes[12..16].copy_from_slice(&self.sample_rate.to_le_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8; LEN]) -> Result<Self, String> {
+        if bytes[0..4] != MAGIC {
+            return Err("Missing rplay stream header magic".into());
+        }
+        if bytes[4] != VERSION {
+            return Err(format!("Unsupported rplay stream header version '{}'", bytes[4]));
+        }
+        let flags = bytes[5];
+        let sample_rate = u32::from_le_bytes(bytes[12..16].try_into().unwrap());
+        let channels = u16::from_le_bytes(bytes[8..10].try_into().unwrap());
+        if sample_rate == 0 || channels == 0 {
+            return Err(format!("Invalid rplay stream header, {sample_rate}Hz with {channels} channels"));
+        }
+        Ok(StreamHeader {
+            sample_rate,
+            channels,
+            sample_size: bytes[6],
+            float: flags & FLAG_FLOAT != 0,
+            unsigned: flags & FLAG_UNSIGNED != 0,
+            big_endian: flags & FLAG_BIG_ENDIAN != 0,
+            sign_magnitude: flags & FLAG_SIGN_MAGNITUDE != 0,
+            silence_runs: flags & FLAG_SILENCE_RUNS != 0,
+        })
+    }
+
+    /// Bytes of one silent frame, None when frames don't end on byte boundaries.
+    pub fn silent_frame(&self) -> Option<Vec<u8>> {
+        if self.sample_size == 0 || !self.sample_size.is_multiple_of(8) {
+            return None;
+        }
+        let mut sample = vec![0u8; self.sample_size as usize / 8];
+        if self.unsigned {
+            // offset binary silence is the midpoint, only the top bit set
+            let msb = if self.big_endian { 0 } else { sample.len() - 1 };
+            sample[msb] = 0x80;
+        }
+        Some(sample.repeat(self.channels as usize))
+    }
+}
+
+/// The container [cpal::SampleFormat] samples of a format are read into, or why they can't be.
+pub fn sample_format(float: bool, unsigned: bool, sample_size: u32) -> Result<cpal::SampleFormat, String> {
+    use cpal::SampleFormat::*;
+    Ok(match (float, unsigned, sample_size) {
+        (false, false, 8) => I8,
+        (false,  true, 8) => U8,
+
+        (false, false, 16) => I16,
+        (false,  true, 16) => U16,
+
+        (false, false, 32) => I32,
+        (false,  true, 32) => U32,
+
+        (false, false, 64) => I64,
+        (false,  true, 64) => U64,
+
+        // packed widths are read into the next container size up
+        (false, false, 1..=7) => I8,
+        (false,  true, 1..=7) => U8,
+
+        (false, false, 9..=15) => I16,
+        (false,  true, 9..=15) => U16,
+
+        (false, false, 17..=31) => I32,
+        (false,  true, 17..=31) => U32,
+
+        (false, false, 33..=63) => I64,
+        (false,  true, 33..=63) => U64,
+
+        (true, false, 32) => F32,
+        (true, false, 64) => F64,
+
+        (true, true, _) => {
+            return Err("Floating point values can not be represented as unsigned".into());
+        },
+
+        (true, false, invalid_size) => {
+            return Err(format!("Unsupported floating point size: '{invalid_size}', can only be: [32, 64]"));
+        },
+
+        (false, _, invalid_size) => {
+            return Err(format!("Unsupported sample size: '{invalid_size}'"));
+        },
+    })
+}
+
+/// Expands a stream of runs into plain samples, so sparse streams don't have to send their silence.
+///
+/// Each run starts with a little-endian u32 token, the low 31 bits count frames.
+/// With the top bit set the frames are silent and nothing follows the token,
+/// otherwise that many frames of samples follow it.
+///
+/// A silent run of no frames ends the runs. An index may follow it, with a little-endian
+/// u64 pair per run of its first frame and the offset of its token from the end of the
+/// stream header, then the u64 count of pairs and [INDEX_MAGIC].
+pub struct SilenceRuns<R> {
+    inner: R,
+    silent_frame: Vec<u8>,
+    /// bytes left of the current run of samples
+    samples_left: u64,
+    /// bytes left of the current run of silence
+    silence_left: u64,
+    ended: bool,
+}
+
+impl<R: io::Read> SilenceRuns<R> {
+    pub fn new(inner: R, spec: &StreamHeader) -> Result<Self, String> {
+        let silent_frame = spec.silent_frame()
+            .ok_or_else(|| format!("Silence runs need whole byte samples, not '{}' bit ones", spec.sample_size))?;
+        Ok(SilenceRuns { inner, silent_frame, samples_left: 0, silence_left: 0, ended: false })
+    }
+}
+
+impl<R: io::Read> io::Read for SilenceRuns<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.samples_left == 0 && self.silence_left == 0 {
+            if self.ended {
+                return Ok(0);
+            }
+            let mut token = [0u8; 4];
+            // a stream may only end between runs
+            match self.inner.read(&mut token[..1])? {
+                0 => return Ok(0),
+                _ => self.inner.read_exact(&mut token[1..])?,
+            }
+            let token = u32::from_le_bytes(token);
+            if token == RUNS_END {
+                self.ended = true;
+                continue;
+            }
+            let len = (token & !RUN_SILENCE) as u64 * self.silent_frame.len() as u64;
+            if token & RUN_SILENCE != 0 {
+                self.silence_left = len;
+            } else {
+                self.samples_left = len;
+            }
+        }
+
+        if self.samples_left > 0 {
+            let len = buf.len().min(self.samples_left.min(usize::MAX as u64) as usize);
+            let read = self.inner.read(&mut buf[..len])?;
+            if read == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            self.samples_left -= read as u64;
+            return Ok(read);
+        }
+
+        // silence runs are whole frames, so the offset into a frame follows from what is left
+        let frame_len = self.silent_frame.len();
+        let len = buf.len().min(self.silence_left.min(usize::MAX as u64) as usize);
+        let offset = (frame_len - (self.silence_left % frame_len as u64) as usize) % frame_len;
+        for (i, byte) in buf[..len].iter_mut().enumerate() {
+            *byte = self.silent_frame[(offset + i) % frame_len];
+        }
+        self.silence_left -= len as u64;
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPEC: StreamHeader = StreamHeader {
+        sample_rate: 48000,
+        channels: 2,
+        sample_size: 24,
+        float: false,
+        unsigned: false,
+        big_endian: true,
+        sign_magnitude: true,
+        silence_runs: true,
+    };
+
+    #[test]
+    fn header_round_trips() {
+        assert_eq!(StreamHeader::from_bytes(&SPEC.to_bytes()), Ok(SPEC));
+        let float = StreamHeader { sample_size: 32, float: true, big_endian: false, sign_magnitude: false, ..SPEC };
+        assert_eq!(StreamHeader::from_bytes(&float.to_bytes()), Ok(float));
+    }
+
+    #[test]
+    fn rejects_bad_headers() {
+        let mut magic = SPEC.to_bytes();
+        magic[0] = b'X';
+        assert!(StreamHeader::from_bytes(&magic).is_err());
+
+        let mut version = SPEC.to_bytes();
+        version[4] = VERSION + 1;
+        assert!(StreamHeader::from_bytes(&version).is_err());
+
+        for spec in [StreamHeader { channels: 0, ..SPEC }, StreamHeader { sample_rate: 0, ..SPEC }] {
+            assert!(StreamHeader::from_bytes(&spec.to_bytes()).is_err(), "{spec:?}");
+        }
+    }
+}
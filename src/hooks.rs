@@ -0,0 +1,109 @@
+use std::io;
+use std::process::Command;
+use std::sync::mpsc::{self, SyncSender};
+use std::thread;
+
+/// Commands run through `sh -c` when playback finishes, fails or clips.
+///
+/// They see `RPLAY_EVENT` (finish, error or clip), `RPLAY_INPUT`, `RPLAY_FRAMES` and
+/// `RPLAY_SECONDS` played so far, `RPLAY_ERROR` with the error and `RPLAY_CLIPPED` with
+/// the samples clipped since the last clip event.
+pub struct Hooks {
+    on_finish: Option<String>,
+    on_error: Option<String>,
+    /// clip events for the thread that runs `--on-clip`, kept off the audio callback
+    clips: Option<SyncSender<Vec<(&'static str, String)>>>,
+    input: String,
+    sample_rate: u32,
+    /// frames played so far
+    frames: u64,
+    /// samples clipped since the last clip event
+    clipped: u64,
+    /// frame of the last clip event
+    last_clip: Option<u64>,
+}
+
+impl Hooks {
+    pub fn new(on_finish: Option<String>, on_error: Option<String>, on_clip: Option<String>, input: String, sample_rate: u32) -> Self {
+        let clips = on_clip.map(|command| {
+            // one event waiting at most, later ones are dropped while the command runs
+            let (sender, receiver) = mpsc::sync_channel::<Vec<(&str, String)>>(1);
+            thread::spawn(move || {
+                for env in receiver {
+                    run(&command, &env);
+                }
+            });
+            sender
+        });
+        Hooks { on_finish, on_error, clips, input, sample_rate, frames: 0, clipped: 0, last_clip: None }
+    }
+
+    fn env(&self, event: &'static str) -> Vec<(&'static str, String)> {
+        vec![
+            ("RPLAY_EVENT", event.to_string()),
+            ("RPLAY_INPUT", self.input.clone()),
+            ("RPLAY_FRAMES", self.frames.to_string()),
+            ("RPLAY_SECONDS", format!("{:.3}", self.frames as f64 / self.sample_rate as f64)),
+        ]
+    }
+
+    /// Whether clipping is watched for, see [Hooks::played].
+    pub fn watches_clipping(&self) -> bool {
+        self.clips.is_some()
+    }
+
+    /// Counts `frames` played with `clipped` samples beyond full scale, raising a clip event
+    /// at most once a second.
+    pub fn played(&mut self, frames: usize, clipped: usize) {
+        self.frames += frames as u64;
+        self.clipped += clipped as u64;
+        let due = self.last_clip.is_none_or(|last| self.frames - last >= self.sample_rate as u64);
+        if self.clipped > 0 && due && let Some(clips) = &self.clips {
+            let mut env = self.env("clip");
+            env.push(("RPLAY_CLIPPED", self.clipped.to_string()));
+            let _ = clips.try_send(env);
+            self.clipped = 0;
+            self.last_clip = Some(self.frames);
+        }
+    }
+
+    /// The `--on-finish` hook when the input ended, or `--on-error` for other errors, to be run
+    /// off the audio callback.
+    pub fn ended(&self, error: &io::Error) -> Option<Hook> {
+        if error.kind() == io::ErrorKind::UnexpectedEof {
+            let command = self.on_finish.clone()?;
+            Some(Hook { command, env: self.env("finish") })
+        } else {
+            let command = self.on_error.clone()?;
+            let mut env = self.env("error");
+            env.push(("RPLAY_ERROR", error.to_string()));
+            Some(Hook { command, env })
+        }
+    }
+}
+
+/// A hook command with the environment of its event.
+pub struct Hook {
+    command: String,
+    env: Vec<(&'static str, String)>,
+}
+
+impl Hook {
+    /// Runs the command and waits for it.
+    pub fn run(&self) {
+        run(&self.command, &self.env);
+    }
+}
+
+fn run(command: &str, env: &[(&str, String)]) {
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .envs(env.iter().map(|(name, value)| (name, value)))
+        .status();
+    match status {
+        Ok(status) if !status.success() => eprintln!("[!] hook '{command}' failed, {status}"),
+        Ok(_) => (),
+        Err(e) => eprintln!("[!] failed to run hook '{command}': {e}"),
+    }
+}
@@ -0,0 +1,242 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::thread;
+use std::time::Duration;
+
+const MAX_REDIRECTS: usize = 5;
+/// Reconnections tried in a row after the connection drops, before giving up.
+const MAX_RESUMES: usize = 5;
+const RESUME_DELAY: Duration = Duration::from_secs(1);
+/// How long a stalled connection is waited on before it is resumed.
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Whether an input path is a URL rather than a file.
+pub fn is_url(path: &str) -> bool {
+    let lower = path.to_ascii_lowercase();
+    lower.starts_with("http://") || lower.starts_with("https://")
+}
+
+#[derive(Debug, Clone)]
+struct Url {
+    /// host and port as sent in the Host header
+    host: String,
+    /// host and port to connect to
+    addr: String,
+    path: String,
+}
+
+impl Url {
+    fn parse(url: &str) -> Result<Self, String> {
+        let rest = match url.split_at_checked(7) {
+            Some((scheme, rest)) if scheme.eq_ignore_ascii_case("http://") => rest,
+            _ if url.to_ascii_lowercase().starts_with("https://") => {
+                return Err("HTTPS is not supported, fetch the stream with e.g. curl and pipe it into rplay".into());
+            },
+            _ => return Err(format!("Invalid URL '{url}', expected http://HOST[:PORT]/PATH")),
+        };
+        let (host, path) = match rest.find('/') {
+            Some(i) => rest.split_at(i),
+            None => (rest, "/"),
+        };
+        if host.is_empty() || host.contains('@') {
+            return Err(format!("Invalid URL '{url}', expected http://HOST[:PORT]/PATH"));
+        }
+        let has_port = !host.ends_with(']')
+            && host.rsplit_once(':').is_some_and(|(_, port)| port.parse::<u16>().is_ok());
+        Ok(Url {
+            host: host.to_string(),
+            addr: if has_port { host.to_string() } else { format!("{host}:80") },
+            path: path.split('#').next().unwrap_or(path).to_string(),
+        })
+    }
+
+    /// The target of a redirect from this URL.
+    fn join(&self, location: &str) -> Result<Self, String> {
+        if location.starts_with('/') {
+            return Ok(Url { path: location.to_string(), ..self.clone() });
+        }
+        Url::parse(location)
+    }
+}
+
+struct Response {
+    status: u16,
+    /// names in lowercase
+    headers: Vec<(String, String)>,
+    reader: BufReader<TcpStream>,
+}
+
+impl Response {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn is_chunked(&self) -> bool {
+        self.header("transfer-encoding").is_some_and(|e| e.eq_ignore_ascii_case("chunked"))
+    }
+
+    fn content_length(&self) -> Option<u64> {
+        self.header("content-length").and_then(|len| len.parse().ok())
+    }
+
+    fn into_body(self) -> Box<dyn Read + Send> {
+        if self.is_chunked() {
+            Box::new(Chunked { inner: self.reader, remaining: 0, done: false })
+        } else if let Some(len) = self.content_length() {
+            Box::new(self.reader.take(len))
+        } else {
+            Box::new(self.reader)
+        }
+    }
+}
+
+/// Sends a GET for `url`, from byte `offset` of the body.
+fn request(url: &Url, offset: u64) -> io::Result<Response> {
+    let stream = TcpStream::connect(&url.addr)?;
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let mut request = format!(
+        "GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: rplay/{}\r\nAccept-Encoding: identity\r\nConnection: close\r\n",
+        url.path,
+        url.host,
+        env!("CARGO_PKG_VERSION"),
+    );
+    if offset > 0 {
+        request += &format!("Range: bytes={offset}-\r\n");
+    }
+    request += "\r\n";
+    (&stream).write_all(request.as_bytes())?;
+
+    let mut reader = BufReader::new(stream);
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid HTTP response");
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let status = line.split_whitespace().nth(1)
+        .and_then(|status| status.parse().ok())
+        .ok_or_else(invalid)?;
+    let mut headers = Vec::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(invalid());
+        }
+        if line.trim().is_empty() {
+            break;
+        }
+        let (name, value) = line.split_once(':').ok_or_else(invalid)?;
+        headers.push((name.trim().to_ascii_lowercase(), value.trim().to_string()));
+    }
+    Ok(Response { status, headers, reader })
+}
+
+/// Streams the body of an HTTP GET, resuming with a range request where the connection
+/// dropped if the server supports it.
+pub struct HttpSource {
+    url: Url,
+    body: Box<dyn Read + Send>,
+    /// bytes of the body read so far
+    position: u64,
+    /// length of the whole body, when the server gave it
+    len: Option<u64>,
+    resumable: bool,
+}
+
+impl HttpSource {
+    pub fn open(url: &str) -> Result<Self, String> {
+        let mut target = Url::parse(url)?;
+        for _ in 0..=MAX_REDIRECTS {
+            let response = request(&target, 0).map_err(|e| format!("{url}: {e}"))?;
+            match response.status {
+                200 => {
+                    let resumable = response.header("accept-ranges").is_some_and(|r| r.eq_ignore_ascii_case("bytes"));
+                    let len = if response.is_chunked() { None } else { response.content_length() };
+                    return Ok(HttpSource { url: target, body: response.into_body(), position: 0, len, resumable });
+                },
+                301 | 302 | 303 | 307 | 308 => {
+                    let location = response.header("location")
+                        .ok_or_else(|| format!("{url}: redirect without a location"))?;
+                    target = target.join(location)?;
+                },
+                status => return Err(format!("{url}: HTTP status {status}")),
+            }
+        }
+        Err(format!("{url}: too many redirects"))
+    }
+
+    fn resume(&mut self) -> io::Result<()> {
+        let response = request(&self.url, self.position)?;
+        if response.status != 206 {
+            return Err(io::Error::other(format!("HTTP status {} to a range request", response.status)));
+        }
+        self.body = response.into_body();
+        Ok(())
+    }
+}
+
+impl Read for HttpSource {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut resumes = 0;
+        loop {
+            let error = match self.body.read(buf) {
+                Ok(0) if buf.is_empty() || self.len.is_none_or(|len| self.position >= len) => return Ok(0),
+                Ok(0) => io::Error::from(io::ErrorKind::UnexpectedEof),
+                Ok(len) => {
+                    self.position += len as u64;
+                    return Ok(len);
+                },
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => e,
+            };
+            if !self.resumable || resumes == MAX_RESUMES {
+                return Err(error);
+            }
+            resumes += 1;
+            eprintln!("[!] {error} at byte {}, resuming", self.position);
+            thread::sleep(RESUME_DELAY);
+            if let Err(e) = self.resume() {
+                eprintln!("[!] resuming failed: {e}");
+            }
+        }
+    }
+}
+
+/// Decodes a `Transfer-Encoding: chunked` body.
+struct Chunked<R> {
+    inner: R,
+    /// bytes left in the current chunk
+    remaining: u64,
+    done: bool,
+}
+
+impl<R: BufRead> Read for Chunked<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.done || buf.is_empty() {
+            return Ok(0);
+        }
+        if self.remaining == 0 {
+            let mut line = String::new();
+            if self.inner.read_line(&mut line)? == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            let size = line.split(';').next().unwrap_or_default().trim();
+            self.remaining = u64::from_str_radix(size, 16)
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "invalid HTTP chunk size"))?;
+            if self.remaining == 0 {
+                self.done = true;
+                return Ok(0);
+            }
+        }
+        let len = buf.len().min(self.remaining.try_into().unwrap_or(usize::MAX));
+        let len = self.inner.read(&mut buf[..len])?;
+        if len == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        self.remaining -= len as u64;
+        if self.remaining == 0 {
+            // the line break closing the chunk
+            self.inner.read_line(&mut String::new())?;
+        }
+        Ok(len)
+    }
+}
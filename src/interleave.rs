@@ -0,0 +1,91 @@
+use std::io::{self, Read};
+
+/// Samples read from each input at a time.
+const BLOCK: usize = 4096;
+
+/// Interleaves the samples of single channel inputs into the frames of one stream, which ends
+/// with the shortest input.
+pub struct Interleave {
+    /// the inputs in channel order, with the names they are reported by
+    inputs: Vec<(String, Box<dyn Read + Send>)>,
+    sample_len: usize,
+    block: Vec<u8>,
+    frames: Vec<u8>,
+    /// bytes of `frames` already read
+    pos: usize,
+    ended: bool,
+}
+
+impl Interleave {
+    /// Interleaves `inputs` of `sample_len` byte samples, one channel each.
+    pub fn new(inputs: Vec<(String, Box<dyn Read + Send>)>, sample_len: usize) -> Self {
+        Interleave {
+            inputs,
+            sample_len,
+            block: vec![0; BLOCK * sample_len],
+            frames: Vec::new(),
+            pos: 0,
+            ended: false,
+        }
+    }
+
+    /// Reads the next block of every input and interleaves the frames they all have.
+    fn fill(&mut self) -> io::Result<()> {
+        let channels = self.inputs.len();
+        let mut lens = Vec::with_capacity(channels);
+        self.frames.resize(BLOCK * channels * self.sample_len, 0);
+        for (c, (_, input)) in self.inputs.iter_mut().enumerate() {
+            let len = read_full(input, &mut self.block)? / self.sample_len;
+            for (i, sample) in self.block.chunks_exact(self.sample_len).take(len).enumerate() {
+                let at = (i * channels + c) * self.sample_len;
+                self.frames[at..at + self.sample_len].copy_from_slice(sample);
+            }
+            lens.push(len);
+        }
+
+        let frames = lens.iter().copied().min().unwrap_or(0);
+        if frames < BLOCK {
+            self.ended = true;
+            if lens.iter().any(|&len| len > frames) {
+                let names = |ended: bool| self.inputs.iter().zip(&lens)
+                    .filter(|(_, len)| (**len == frames) == ended)
+                    .map(|((name, _), _)| name.as_str())
+                    .collect::<Vec<_>>()
+                    .join(" and ");
+                eprintln!("[!] {} ended first, the rest of {} is not played", names(true), names(false));
+            }
+        }
+        self.frames.truncate(frames * channels * self.sample_len);
+        self.pos = 0;
+        Ok(())
+    }
+}
+
+impl Read for Interleave {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.frames.len() {
+            if self.ended {
+                return Ok(0);
+            }
+            self.fill()?;
+        }
+        let len = buf.len().min(self.frames.len() - self.pos);
+        buf[..len].copy_from_slice(&self.frames[self.pos..self.pos + len]);
+        self.pos += len;
+        Ok(len)
+    }
+}
+
+/// Reads into all of `buf` unless the input ends first, returns the bytes read.
+fn read_full(input: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match input.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(len) => filled += len,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
@@ -0,0 +1,28 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Set by SIGINT and SIGTERM once they are caught.
+static CAUGHT: AtomicBool = AtomicBool::new(false);
+
+/// Whether SIGINT or SIGTERM arrived since [catch].
+pub fn caught() -> bool {
+    CAUGHT.load(Ordering::Relaxed)
+}
+
+#[cfg(unix)]
+extern "C" fn on_interrupt(_signal: libc::c_int) {
+    CAUGHT.store(true, Ordering::Relaxed);
+}
+
+/// Catches SIGINT and SIGTERM instead of exiting on them, so that files being written can be
+/// finalized first. [caught] tells when one arrived.
+#[cfg(unix)]
+pub fn catch() {
+    unsafe {
+        for signal in [libc::SIGINT, libc::SIGTERM] {
+            libc::signal(signal, on_interrupt as extern "C" fn(libc::c_int) as libc::sighandler_t);
+        }
+    }
+}
+
+#[cfg(not(unix))]
+pub fn catch() {}
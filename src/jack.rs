@@ -0,0 +1,66 @@
+use std::thread;
+
+/// Opens the output of a JACK client `name`, connected to the system playback ports when
+/// `connect` is set. Its ports are `NAME_out:out_0` and on.
+#[cfg(all(feature = "jack", any(target_os = "linux", target_os = "dragonfly", target_os = "freebsd", target_os = "netbsd")))]
+pub fn open_output(name: &str, connect: bool) -> Result<(cpal::Device, cpal::SupportedStreamConfig), String> {
+    use cpal::traits::DeviceTrait;
+
+    let device = cpal::platform::JackDevice::default_output_device(name, connect, false)
+        .map_err(|e| format!("failed to open JACK client {name}: {e}"))?;
+    let config = device.default_output_config()
+        .map_err(|e| format!("failed to get the output config of JACK client {name}: {e}"))?;
+    Ok((device.into(), config))
+}
+
+#[cfg(not(all(feature = "jack", any(target_os = "linux", target_os = "dragonfly", target_os = "freebsd", target_os = "netbsd"))))]
+pub fn open_output(_name: &str, _connect: bool) -> Result<(cpal::Device, cpal::SupportedStreamConfig), String> {
+    Err("rplay was built without JACK, rebuild it with --features jack on Linux or BSD".into())
+}
+
+/// Connects the output ports of client `name` to `ports` in order, once it has registered them.
+pub fn connect(name: &str, ports: &[String]) {
+    let name = name.to_string();
+    let ports = ports.to_vec();
+    thread::spawn(move || {
+        if let Err(msg) = connect_when_ready(&name, &ports) {
+            eprintln!("[!] {msg}");
+        }
+    });
+}
+
+#[cfg(all(feature = "jack", any(target_os = "linux", target_os = "dragonfly", target_os = "freebsd", target_os = "netbsd")))]
+fn connect_when_ready(name: &str, ports: &[String]) -> Result<(), String> {
+    use std::time::{Duration, Instant};
+
+    /// How often to look for the ports of the playing client.
+    const RETRY: Duration = Duration::from_millis(100);
+    /// How long the playing client may take to register its ports.
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    let (client, _) = ::jack::Client::new(&format!("{name}_connect"), ::jack::ClientOptions::NO_START_SERVER)
+        .map_err(|e| format!("failed to connect the JACK ports: {e}"))?;
+    let sources = (0..ports.len()).map(|i| format!("{name}_out:out_{i}")).collect::<Vec<_>>();
+    let deadline = Instant::now() + TIMEOUT;
+    while client.port_by_name(&sources[0]).is_none() {
+        if Instant::now() > deadline {
+            return Err(format!("JACK client {name}_out registered no ports to connect"));
+        }
+        thread::sleep(RETRY);
+    }
+    for (source, port) in sources.iter().zip(ports) {
+        if client.port_by_name(source).is_none() {
+            eprintln!("[!] not connecting {port}, {name}_out has fewer channels");
+            continue;
+        }
+        if let Err(e) = client.connect_ports_by_name(source, port) {
+            eprintln!("[!] failed to connect {source} to {port}: {e}");
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(all(feature = "jack", any(target_os = "linux", target_os = "dragonfly", target_os = "freebsd", target_os = "netbsd"))))]
+fn connect_when_ready(_name: &str, _ports: &[String]) -> Result<(), String> {
+    Err("rplay was built without JACK, rebuild it with --features jack on Linux or BSD".into())
+}
@@ -0,0 +1,215 @@
+use std::f64::consts::TAU;
+use std::time::Duration;
+
+use crate::measure::{self, Capture, Stimulus};
+
+/// Shortest sweep, long enough for the stretches compared to find drift.
+const MIN_DURATION: Duration = Duration::from_secs(2);
+/// Quietest response taken as the stimulus coming back, in dB below full scale.
+const SILENCE_DB: f64 = -70.0;
+/// Blocks the response is split into to follow the chirp through its frequencies.
+const BLOCK: Duration = Duration::from_millis(10);
+/// Blocks on each side of a block that make up its neighbourhood when looking for dropouts.
+const NEIGHBOURHOOD: usize = 25;
+/// How far a block falls below its neighbourhood to count as a dropout.
+const DROPOUT_DB: f64 = 20.0;
+/// Length of the stretches compared at both ends of the sweep to find drift.
+const DRIFT_WINDOW: Duration = Duration::from_millis(250);
+/// How far the end of the sweep is searched for around where the start puts it.
+const DRIFT_SEARCH: Duration = Duration::from_millis(50);
+/// Drift in frames put down to group delay differing between the ends of the sweep.
+const DRIFT_TOLERANCE: isize = 1;
+/// Octave band centres, the frequency response is reported for those inside the sweep.
+const BANDS: [f64; 10] = [31.5, 63.0, 125.0, 250.0, 500.0, 1000.0, 2000.0, 4000.0, 8000.0, 16000.0];
+/// How far a band may stray from the 1 kHz band before it counts as an anomaly.
+const ANOMALY_DB: f64 = 3.0;
+
+/// Plays a chirp out of `output` and records it back through a loopback cable into the
+/// default input device, then reports the latency, drift, dropouts, level and frequency
+/// response of the round trip. Fails when the round trip is not clean.
+pub fn run(output: &cpal::Device, sample_rate: u32, channels: u16, gain: f32, duration: Duration) -> Result<(), String> {
+    if duration < MIN_DURATION {
+        return Err(format!("Loopcheck duration {duration:?} is too short, expected at least {MIN_DURATION:?}"));
+    }
+    let stimulus = Stimulus::Chirp.samples(sample_rate, duration)?;
+    let capture = measure::capture(output, sample_rate, channels, gain, &stimulus)?;
+    report(&capture, stimulus.len(), sample_rate)
+}
+
+/// Analyses a capture of a chirp `len` frames long.
+fn report(capture: &Capture, len: usize, sample_rate: u32) -> Result<(), String> {
+    let Capture { played, response, input_channels } = capture;
+    let (played, input_channels) = (&played[..len], *input_channels);
+    let fs = sample_rate as f64;
+    let ms = |frames: f64| frames * 1000.0 / fs;
+
+    // the loopback may go into any input channel, take the loudest
+    let (channel, recorded) = (0..input_channels)
+        .map(|c| (c, response.iter().skip(c).step_by(input_channels).copied().collect::<Vec<_>>()))
+        .max_by(|(_, a), (_, b)| energy(a).total_cmp(&energy(b)))
+        .ok_or("The input device has no channels")?;
+    if db(rms(&recorded)) < SILENCE_DB {
+        return Err("Nothing came back, check the loopback cable and the input level".into());
+    }
+    println!("input channel: {}", channel + 1);
+
+    let mut problems = Vec::new();
+
+    let lag = best_lag(played, &recorded);
+    println!("latency: {lag} frames ({:.2} ms) beyond what the devices report", ms(lag as f64));
+    let aligned = &recorded[lag..lag + played.len()];
+
+    let window = (DRIFT_WINDOW.as_secs_f64() * fs) as usize;
+    let search = (DRIFT_SEARCH.as_secs_f64() * fs) as usize;
+    let local_lag = |start: usize| {
+        let from = (lag + start).saturating_sub(search);
+        let to = (lag + start + window + search).min(recorded.len());
+        (from + best_lag(&played[start..start + window], &recorded[from..to])) as isize - start as isize
+    };
+    let (start, end) = (played.len() / 10, played.len() * 9 / 10 - window);
+    let drift = local_lag(end) - local_lag(start);
+    println!("drift: {drift} frames over {:.2} s", (end - start) as f64 / fs);
+    if drift.abs() > DRIFT_TOLERANCE {
+        problems.push(format!("{drift} frames of drift, from dropped or repeated samples or unlocked clocks"));
+    }
+
+    let gain_db = db(rms(aligned)) - db(rms(played));
+    println!("level: {gain_db:+.1} dB");
+
+    // level of the response relative to the stimulus through the sweep, skipping the fades
+    let block = (BLOCK.as_secs_f64() * fs) as usize;
+    let levels = (2..played.len() / block - 2)
+        .map(|b| {
+            let range = b * block..(b + 1) * block;
+            let level = db(rms(&aligned[range.clone()])) - db(rms(&played[range]));
+            ((b as f64 + 0.5) * block as f64, level)
+        })
+        .collect::<Vec<_>>();
+
+    let mut dropouts = 0;
+    for (i, &(position, level)) in levels.iter().enumerate() {
+        let mut neighbourhood = levels[i.saturating_sub(NEIGHBOURHOOD)..(i + NEIGHBOURHOOD + 1).min(levels.len())]
+            .iter()
+            .map(|&(_, level)| level)
+            .collect::<Vec<_>>();
+        neighbourhood.sort_by(f64::total_cmp);
+        if level < neighbourhood[neighbourhood.len() / 2] - DROPOUT_DB {
+            println!("dropout: at {:.3} s", position / fs);
+            dropouts += 1;
+        }
+    }
+    if dropouts > 0 {
+        problems.push(format!("{dropouts} blocks of {} ms dropped out", BLOCK.as_millis()));
+    }
+
+    // the chirp is at a known frequency at every instant
+    let (f1, f2) = measure::sweep_range(sample_rate);
+    let frequency = |position: f64| f1 * (position / played.len() as f64 * (f2 / f1).ln()).exp();
+    let bands = BANDS.iter()
+        .filter(|&&centre| centre / 2f64.sqrt() >= f1 && centre * 2f64.sqrt() <= f2)
+        .filter_map(|&centre| {
+            let inside = levels.iter()
+                .filter(|&&(position, _)| (frequency(position) / centre).log2().abs() <= 0.5)
+                .map(|&(_, level)| 10f64.powf(level / 10.0))
+                .collect::<Vec<_>>();
+            (!inside.is_empty()).then(|| (centre, 10.0 * (inside.iter().sum::<f64>() / inside.len() as f64).log10()))
+        })
+        .collect::<Vec<_>>();
+    let reference = bands.iter()
+        .find(|&&(centre, _)| centre == 1000.0)
+        .map_or(gain_db, |&(_, level)| level);
+    for (centre, level) in bands {
+        let deviation = level - reference;
+        let flag = if deviation.abs() > ANOMALY_DB { "  <- anomaly" } else { "" };
+        println!("response: {centre:>7} Hz {deviation:+6.1} dB{flag}");
+        if deviation.abs() > ANOMALY_DB {
+            problems.push(format!("{deviation:+.1} dB at {centre} Hz"));
+        }
+    }
+
+    if problems.is_empty() {
+        println!("loopcheck passed");
+        Ok(())
+    } else {
+        Err(format!("loopcheck failed: {}", problems.join(", ")))
+    }
+}
+
+fn energy(samples: &[f32]) -> f64 {
+    samples.iter().map(|&s| s as f64 * s as f64).sum()
+}
+
+fn rms(samples: &[f32]) -> f64 {
+    (energy(samples) / samples.len().max(1) as f64).sqrt()
+}
+
+fn db(amplitude: f64) -> f64 {
+    20.0 * amplitude.max(1e-10).log10()
+}
+
+/// Offset into `signal` where `reference` matches best, by cross-correlation normalized by the
+/// energy of the stretch of `signal` it is compared with.
+fn best_lag(reference: &[f32], signal: &[f32]) -> usize {
+    if signal.len() <= reference.len() {
+        return 0;
+    }
+    let n = (reference.len() + signal.len()).next_power_of_two();
+    let spectrum = |samples: &[f32]| {
+        let mut re = samples.iter().map(|&s| s as f64).collect::<Vec<_>>();
+        re.resize(n, 0.0);
+        let mut im = vec![0.0; n];
+        fft(&mut re, &mut im, false);
+        (re, im)
+    };
+    let (sr, si) = spectrum(signal);
+    let (rr, ri) = spectrum(reference);
+    // signal times the conjugate of the reference
+    let mut re = (0..n).map(|k| sr[k] * rr[k] + si[k] * ri[k]).collect::<Vec<_>>();
+    let mut im = (0..n).map(|k| si[k] * rr[k] - sr[k] * ri[k]).collect::<Vec<_>>();
+    fft(&mut re, &mut im, true);
+    let mut energies = vec![0.0];
+    for &s in signal {
+        energies.push(energies[energies.len() - 1] + s as f64 * s as f64);
+    }
+    let score = |offset: usize| {
+        let energy = energies[offset + reference.len()] - energies[offset];
+        re[offset] / energy.max(f64::MIN_POSITIVE).sqrt()
+    };
+    (0..=signal.len() - reference.len())
+        .max_by(|&a, &b| score(a).total_cmp(&score(b)))
+        .unwrap_or(0)
+}
+
+/// In-place radix-2 FFT of `re` + i`im`, whose length is a power of two. The inverse is unscaled.
+pub fn fft(re: &mut [f64], im: &mut [f64], inverse: bool) {
+    let n = re.len();
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+    let mut len = 2;
+    while len <= n {
+        let angle = if inverse { TAU } else { -TAU } / len as f64;
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                let (wi, wr) = (angle * k as f64).sin_cos();
+                let (a, b) = (start + k, start + k + len / 2);
+                let (xr, xi) = (re[b] * wr - im[b] * wi, re[b] * wi + im[b] * wr);
+                re[b] = re[a] - xr;
+                im[b] = im[a] - xi;
+                re[a] += xr;
+                im[a] += xi;
+            }
+        }
+        len <<= 1;
+    }
+}
@@ -0,0 +1,85 @@
+use std::io::{self, Read};
+use std::str::FromStr;
+use std::sync::Arc;
+use crate::timecode::Time;
+
+/// The slice of the input `--loop-region` repeats, from `start` up to but not including `end`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Region {
+    pub start: Time,
+    pub end: Time,
+}
+
+impl FromStr for Region {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // clock times have colons of their own, they need the two dots
+        let (start, end) = s.split_once("..")
+            .or_else(|| s.split_once(':').filter(|(_, end)| !end.contains(':')))
+            .ok_or_else(|| format!("Invalid region '{s}', expected START..END such as @48000..@96000 or 01:00..01:02.5"))?;
+        Ok(Region { start: start.parse()?, end: end.parse()? })
+    }
+}
+
+/// Opens the input again from the start, for inputs that can be read more than once.
+pub type Reopen = Box<dyn FnMut() -> io::Result<Box<dyn Read + Send>> + Send>;
+
+/// How a [Looper] gets back to the start of its input.
+enum Rewind {
+    Reopen(Reopen),
+    /// the first pass, kept as it is read
+    Recording(Vec<u8>),
+    Cached(Arc<[u8]>),
+}
+
+/// Plays its input a number of times, or forever.
+pub struct Looper {
+    input: Box<dyn Read + Send>,
+    rewind: Rewind,
+    /// passes left including the current one, forever if `None`
+    passes: Option<u32>,
+    /// bytes read in the current pass
+    pass_len: u64,
+}
+
+impl Looper {
+    /// Loops `input` by calling `reopen` at the end of every pass, or by keeping the first pass
+    /// in memory without one.
+    pub fn new(input: Box<dyn Read + Send>, reopen: Option<Reopen>, passes: Option<u32>) -> Self {
+        let rewind = match reopen {
+            Some(reopen) => Rewind::Reopen(reopen),
+            None => Rewind::Recording(Vec::new()),
+        };
+        Looper { input, rewind, passes, pass_len: 0 }
+    }
+}
+
+impl Read for Looper {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let len = self.input.read(buf)?;
+            if len > 0 || buf.is_empty() {
+                if let Rewind::Recording(data) = &mut self.rewind {
+                    data.extend_from_slice(&buf[..len]);
+                }
+                self.pass_len += len as u64;
+                return Ok(len);
+            }
+            // an empty pass would loop without end
+            if self.pass_len == 0 || self.passes == Some(1) {
+                return Ok(0);
+            }
+            self.passes = self.passes.map(|passes| passes - 1);
+            self.pass_len = 0;
+            if let Rewind::Recording(data) = &mut self.rewind {
+                self.rewind = Rewind::Cached(std::mem::take(data).into());
+            }
+            self.input = match &mut self.rewind {
+                Rewind::Reopen(reopen) => reopen()?,
+                Rewind::Cached(data) => Box::new(io::Cursor::new(data.clone())),
+                Rewind::Recording(_) => unreachable!("the first pass is cached"),
+            };
+        }
+    }
+}
@@ -0,0 +1,44 @@
+use std::f64::consts::PI;
+
+use crate::filter::Biquad;
+
+/// ITU-R BS.1770 K-weighting of one channel: a high shelf for the head,
+/// then a high pass, designed for any sample rate.
+#[derive(Debug, Clone, Copy)]
+pub struct KWeighting {
+    shelf: Biquad,
+    high_pass: Biquad,
+}
+
+impl KWeighting {
+    pub fn new(sample_rate: u32) -> Self {
+        let fs = sample_rate as f64;
+
+        let (f0, gain_db, q) = (1681.974450955533, 3.999843853973347, 0.7071752369554196);
+        let k = (PI * f0 / fs).tan();
+        let vh = 10f64.powf(gain_db / 20.0);
+        let vb = vh.powf(0.4996667741545416);
+        let a0 = 1.0 + k / q + k * k;
+        let shelf = Biquad::new(
+            [(vh + vb * k / q + k * k) / a0, 2.0 * (k * k - vh) / a0, (vh - vb * k / q + k * k) / a0],
+            [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        );
+
+        let (f0, q) = (38.13547087602444, 0.5003270373238773);
+        let k = (PI * f0 / fs).tan();
+        let a0 = 1.0 + k / q + k * k;
+        let high_pass = Biquad::new([1.0, -2.0, 1.0], [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0]);
+
+        KWeighting { shelf, high_pass }
+    }
+
+    pub fn process(&mut self, x: f64) -> f64 {
+        self.high_pass.process(self.shelf.process(x))
+    }
+}
+
+/// Loudness in LUFS of channels with the given mean squares of K-weighted samples,
+/// all channels weighted as front channels.
+pub fn lufs(mean_squares: &[f64]) -> f64 {
+    -0.691 + 10.0 * mean_squares.iter().sum::<f64>().log10()
+}
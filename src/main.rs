@@ -2,34 +2,124 @@
 use std::io;
 use std::fs;
 use std::process;
-use std::path::PathBuf;
+use std::io::{Read, Seek};
+use std::path::{Path, PathBuf};
 use std::error::Error;
 use std::str::FromStr;
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
 
-use bit_io::BitWriter;
 use bit_io::ToBytes;
-use clap::Parser;
-use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
+use clap::parser::ValueSource;
+use cpal::traits::DeviceTrait;
 use cpal::Sample;
 
+mod aplay;
+mod autobuffer;
 mod bit_io;
-use bit_io::{BitReader, FromBytes};
+use bit_io::{BitReader, FromBytes, FromPacked};
+mod busy;
+mod capture;
+use busy::OnBusy;
+mod byte_order;
+use byte_order::ByteOrder;
+mod channel;
+mod clicks;
+use clicks::{ClickAction, ClickDetector};
+mod clock;
+use clock::Clock;
+mod compress;
+mod container;
+mod control;
+use control::{GainRamp, OutputControl};
+mod cue;
+use cue::CueSheet;
+mod delta;
+mod devices;
+mod dither;
+use dither::Dither;
+mod duplex;
+use duplex::Recorder;
+mod fanout;
+use fanout::Fanout;
+mod fifo;
+mod filter;
+mod follow;
+use follow::Follow;
+use filter::{SubsonicFilter, UltrasonicGuard};
+mod generator;
+use generator::{NoiseColor, NoiseSource, PatternSource, Segment, TelephonyTone, ToneSource};
+use delta::{DeltaDecoder, WrappingAdd};
+mod header;
+mod hooks;
+use hooks::{Hook, Hooks};
+mod http;
+mod interleave;
+mod interrupt;
+mod jack;
+mod loopcheck;
+mod looper;
+mod loudness;
+mod manifest;
+use manifest::Json;
+mod measure;
+mod mix;
+use mix::MixInput;
+use measure::Stimulus;
+mod net;
+mod playlist;
+mod prebuffer;
+mod punch;
+use prebuffer::Prebuffer;
+mod reconnect;
+mod record;
+mod resample;
+mod reverse;
+use resample::{Resampler, ResamplerKind};
+mod ring;
+mod rtp;
+use rtp::{RtpFormat, RtpSink, RtpSource};
+mod serial;
+mod sha256;
+mod sink;
+use sink::{FileSink, StdoutSink};
+mod tape;
+use tape::TapeCheck;
+mod tap;
+use tap::{Tap, TapFormat, TapWriter};
+mod timecode;
+use timecode::Time;
+mod zone;
+use zone::Zone;
+use channel::{ChannelGain, ChannelMap, ChannelRef, ChannelShift, Route, ShiftDetector, Shifter};
 
 #[derive(Parser, Debug, Clone)]
 #[command(version, about="Playback raw audio samples.", long_about=None)]
 struct Opt {
+    /// Common input format, sets the rate, size, channels and signedness unless they are given
+    #[arg(long, value_enum)]
+    preset: Option<Preset>,
+
     /// Playback sample rate
     #[arg(short='r', long, default_value_t = 48_000)]
     sample_rate: u32,
 
-    /// Size of samples in bits, supports: 8, 16, 32, 64
+    /// Size of samples in bits, supports: 8, 16, 32, 64, or packed integer widths (e.g. 12, 20, 24)
     #[arg(short='s', long, default_value_t = 32)]
     sample_size: u32,
 
     /// Number of channels in the audio stream
-    #[arg(short, long, default_value_t = 2)]
+    #[arg(short, long, default_value_t = 2, global = true)]
     channels: u16,
 
+    /// Compact sample format, e.g. s16le, u8, f32be, instead of the size and format flags
+    #[arg(short='t', long="type", value_name="TYPE",
+        conflicts_with_all=["preset", "sample_size", "unsigned", "float", "be"])]
+    sample_type: Option<SampleType>,
+
     /// Loudness of the audio from 0.0 to 1.0
     ///
     /// --dangerous allows for this value to be set to higher than 1.0
@@ -48,7 +138,162 @@ struct Opt {
     #[arg(short, long="big-endian", default_value_t = false)]
     be: bool,
 
-    /// Send post-process f32 values to stdout, incompatible with --pre
+    /// Byte order of input samples, auto picks the one giving the smoother waveform at the start of the input
+    #[arg(long="be", value_name="ORDER", value_enum, conflicts_with="be")]
+    byte_order: Option<ByteOrder>,
+
+    /// Binary encoding of integer samples, --unsigned is the same as offset-binary
+    #[arg(long, value_enum, default_value_t = Encoding::TwosComplement)]
+    encoding: Encoding,
+
+    /// Input samples are deltas from the previous sample of their channel (DPCM), starting from silence
+    #[arg(long, default_value_t = false)]
+    delta: bool,
+
+    /// Interpret integer samples as signed fixed point, e.g. q15, q31, q8.23
+    ///
+    /// qN.M has N integer bits and M fractional bits after the sign bit,
+    /// qM is shorthand for q0.M
+    #[arg(long, value_name="qN.M")]
+    fixed: Option<QFormat>,
+
+    /// Precision of the sample conversion and processing, the device still receives f32
+    #[arg(long, value_enum, default_value_t = Precision::F32)]
+    precision: Precision,
+
+    /// Delay a channel by a number of frames relative to the others, e.g. 1=+1frame
+    ///
+    /// Corrects captures where a channel was offset at the start of the stream,
+    /// can be given multiple times
+    #[arg(long, value_name="CH=FRAMES")]
+    channel_shift: Vec<ChannelShift>,
+
+    /// Silence a channel, by index or by the speaker position a WAV channel mask gives it, e.g. 3 or LFE
+    ///
+    /// Can be given multiple times
+    #[arg(long, value_name="CH")]
+    mute: Vec<ChannelRef>,
+
+    /// Play only the given channel and silence the others, by index or speaker position, e.g. FL
+    ///
+    /// A single soloed channel is played on every channel, the quickest way to find which channel
+    /// of a capture holds a signal. Can be given multiple times
+    #[arg(long, value_name="CH")]
+    solo: Vec<ChannelRef>,
+
+    /// Scale one channel by a gain from 0.0 to 1.0 on top of --gain, by index or speaker position,
+    /// e.g. 0=0.5 to tame a hot channel. Repeat it for more channels
+    ///
+    /// --dangerous allows gains above 1.0
+    #[arg(long, value_name="CH=GAIN")]
+    gain_ch: Vec<ChannelGain>,
+
+    /// Play an input channel on a device channel, e.g. 0:4, the input channel by index or speaker
+    /// position. Repeat it to route more, e.g. --map 0:4 --map 1:5
+    ///
+    /// Device channels nothing is mapped to are silent, input channels not mapped are not played
+    #[arg(long, value_name="IN:OUT")]
+    map: Vec<ChannelMap>,
+
+    /// Play a mono input on every channel of the device, at its default channel count, even on a
+    /// device that plays mono itself. --map picks some channels instead
+    #[arg(long, default_value_t = false, conflicts_with = "map")]
+    upmix: bool,
+
+    /// Mix a 5.1 or 7.1 input down to stereo with the ITU-R BS.775 coefficients, leaving out the
+    /// LFE. Channels are named by the WAV channel mask, or taken in WAV order without one
+    #[arg(long, default_value_t = false, conflicts_with_all = ["map", "upmix"])]
+    downmix: bool,
+
+    /// Read hotkeys from the terminal while playing: m toggles a mute, d a 20 dB dim
+    #[arg(long, default_value_t = false)]
+    keys: bool,
+
+    /// Take mute and dim commands on a unix domain socket created at PATH, one per line,
+    /// e.g. `echo toggle-mute | nc -U PATH`
+    ///
+    /// Commands: mute, unmute, dim, undim, toggle-mute, toggle-dim, pause, resume, panic and status
+    #[arg(long, value_name="PATH")]
+    control: Option<PathBuf>,
+
+    /// When another rplay started with --on-busy is playing on the same device, wait for it to finish,
+    /// duck it by 20 dB or pause it, for as long as this one plays
+    #[arg(long, value_name="ACTION")]
+    on_busy: Option<OnBusy>,
+
+    /// Pace playback off the output device, or off a timer without opening any audio device, e.g. to
+    /// send RTP or write --post in real time on a machine without one, or not at all
+    #[arg(long, value_enum, default_value_t = Clock::Device)]
+    clock: Clock,
+
+    /// Play at the output device's default rate, resampling the input with this algorithm,
+    /// instead of opening the device at the input rate. Without it an input rate the device does
+    /// not play is resampled with sinc to the nearest one it does
+    #[arg(long, value_enum, value_name = "KIND")]
+    resampler: Option<ResamplerKind>,
+
+    /// Dither used when the output device plays 8 or 16 bit samples, to keep quiet passages
+    /// from distorting as they are rounded
+    #[arg(long, value_enum, default_value_t = Dither::Tpdf)]
+    dither: Dither,
+
+    /// Run CMD through `sh -c` when the input ends, with RPLAY_EVENT, RPLAY_INPUT, RPLAY_FRAMES and
+    /// RPLAY_SECONDS set in its environment
+    #[arg(long, value_name = "CMD")]
+    on_finish: Option<String>,
+
+    /// Run CMD when playback fails, like --on-finish with RPLAY_ERROR set to the error
+    #[arg(long, value_name = "CMD")]
+    on_error: Option<String>,
+
+    /// Run CMD when the output clips, at most once a second, like --on-finish with RPLAY_CLIPPED
+    /// set to the samples clipped since the last time
+    #[arg(long, value_name = "CMD")]
+    on_clip: Option<String>,
+
+    /// Write the command line, resolved format, processing, range, output and input hashes to FILE as JSON, to reproduce the run
+    #[arg(long, value_name="FILE")]
+    manifest: Option<PathBuf>,
+
+    /// Print the input format after detection and checks, then exit without playing
+    #[arg(long, default_value_t = false)]
+    print_config: bool,
+
+    /// Output device to play on, by its index from --list-devices, its name or a part of its name.
+    /// Repeat it to play on several devices at once, each with a buffer of its own
+    #[arg(short='D', long, value_name="DEVICE")]
+    device: Vec<String>,
+
+    /// How long to wait for an output device to play on after losing one, e.g. 30s, before giving up
+    #[arg(long, value_name = "DURATION", default_value = "1m", value_parser = timecode::parse_duration)]
+    reconnect_wait: Duration,
+
+    /// Play FILE on DEVICE, picked like --device. Repeat it to play several files on their own devices at
+    /// once, e.g. the rooms of a multi-room setup, each with the rest of the options
+    #[arg(long, value_name="FILE=DEVICE",
+        conflicts_with_all=["infile", "device", "left", "capture", "serial", "socket_activation", "listen", "connect",
+            "listen_unix", "udp", "rtp_listen", "render", "pre_out", "post_out", "manifest", "control", "keys"])]
+    zone: Vec<Zone>,
+
+    /// Play through JACK as client NAME (rplay by default), its ports are NAME_out:out_0 and on
+    #[arg(long, value_name = "NAME", conflicts_with = "device")]
+    jack_name: Option<String>,
+
+    /// Play through JACK, connecting its ports in order to PORTS instead of the system playback ports,
+    /// e.g. system:playback_1,system:playback_2
+    #[arg(long, value_name = "PORTS", value_delimiter = ',', conflicts_with = "device")]
+    jack_connect: Vec<String>,
+
+    /// Record the default input device to FILE while playing, as f32le with its channels, from the
+    /// instant the first frame plays to a second after the last, e.g. for a loopback or room response
+    #[arg(long, value_name = "FILE")]
+    record_response: Option<PathBuf>,
+
+    /// List the output and input devices with their indices and supported configs, then exit
+    #[arg(long, default_value_t = false)]
+    list_devices: bool,
+
+    /// Send post-process values (f32, or f64 with --precision f64) to stdout, incompatible with --pre
     #[arg(long="post", default_value_t = false)]
     post_out: bool,
 
@@ -56,83 +301,1042 @@ struct Opt {
     #[arg(long="pre", default_value_t = false)]
     pre_out: bool,
 
+    /// Container for --pre/--post output
+    ///
+    /// Input starting with an rplay stream, WAV, AU or AIFF header is played with the format it describes
+    #[arg(long, value_enum, default_value_t = TapFormat::Raw)]
+    out_format: TapFormat,
+
+    /// Write the post-process values to FILE as fast as the input is read, instead of playing them
+    ///
+    /// The container is --out-format if given, WAV or AU for a .wav or .au FILE, raw otherwise
+    #[arg(long, value_name="FILE", conflicts_with_all=["pre_out", "post_out", "clock"])]
+    render: Option<PathBuf>,
+
+    /// Send the post-process signal as an RTP stream to ADDR:PORT, e.g. an AES67 multicast group (experimental)
+    #[arg(long, value_name="ADDR:PORT")]
+    rtp: Option<SocketAddr>,
+
+    /// Wait for a TCP connection on ADDR:PORT and play what the sender pushes
+    #[arg(long, value_name="ADDR:PORT", conflicts_with_all=["infile", "rtp_listen", "track"])]
+    listen: Option<SocketAddr>,
+
+    /// Connect to a TCP server at ADDR:PORT and play the stream it sends
+    #[arg(long, value_name="ADDR:PORT", conflicts_with_all=["infile", "rtp_listen", "listen", "track"])]
+    connect: Option<SocketAddr>,
+
+    /// Wait for a connection on a unix domain socket created at PATH and play what the sender pushes
+    ///
+    /// An infile that is a unix domain socket is connected to instead
+    #[arg(long, value_name="PATH", conflicts_with_all=["infile", "rtp_listen", "listen", "connect", "track"])]
+    listen_unix: Option<PathBuf>,
+
+    /// Play raw samples from UDP datagrams sent to ADDR:PORT, joining it if ADDR is a multicast group
+    ///
+    /// Datagrams are played in the order they arrive, gaps are filled with silence
+    #[arg(long, value_name="ADDR:PORT", conflicts_with_all=["infile", "rtp_listen", "listen", "connect", "listen_unix", "track"])]
+    udp: Option<SocketAddr>,
+
+    /// Play from the socket systemd passes to a socket-activated service, e.g. an on-demand network sink
+    ///
+    /// A listening stream socket plays its first connection like --listen, a UDP socket its datagrams like
+    /// --udp, and a connection systemd accepted itself (Accept=yes) is played as it is
+    #[arg(long, default_value_t = false,
+        conflicts_with_all=["infile", "rtp_listen", "listen", "connect", "listen_unix", "udp", "serial", "capture", "left", "track"])]
+    socket_activation: bool,
+
+    /// Play raw samples read from a serial device at PATH, e.g. a microcontroller on /dev/ttyUSB0
+    ///
+    /// The line is set to 8 data bits, no parity and no flow control at --baud
+    #[arg(long, value_name="PATH", conflicts_with_all=["infile", "rtp_listen", "listen", "connect", "listen_unix", "udp", "left", "track"])]
+    serial: Option<PathBuf>,
+
+    /// Speed of the --serial line
+    #[arg(long, value_name="RATE", default_value_t = 115_200, requires = "serial")]
+    baud: u32,
+
+    /// Play what an input device records, the default one without a DEVICE, e.g. a PulseAudio or
+    /// PipeWire monitor of an output to put system audio through the gain and taps
+    ///
+    /// DEVICE is picked like --device from the input devices. Records at the device's own rate and
+    /// channels unless -r or -c are given
+    #[arg(long, value_name="DEVICE", num_args = 0..=1, require_equals = true,
+        conflicts_with_all=["infile", "rtp_listen", "listen", "connect", "listen_unix", "udp", "serial", "left", "track",
+            "preset", "sample_type", "sample_size", "unsigned", "float", "be", "byte_order"])]
+    capture: Option<Option<String>>,
+
+    /// Keep --capture low latency, as a software monitor of a microphone or line input: the output
+    /// device takes small buffers and captured audio playback falls behind on is dropped, not queued
+    #[arg(long, default_value_t = false, requires = "capture")]
+    monitor: bool,
+
+    /// Play an L16/L24 RTP stream received on ADDR:PORT, joining it if ADDR is a multicast group (experimental)
+    ///
+    /// Takes the rate and channels from -r and -c, the sample format from --rtp-format
+    /// of a dynamic payload type. Other payload types, e.g. comfort noise or telephone events, are skipped
+    #[arg(long, value_name="ADDR:PORT",
+        conflicts_with_all=["infile", "preset", "sample_type", "sample_size", "unsigned", "float", "be", "byte_order"])]
+    rtp_listen: Option<SocketAddr>,
+
+    /// Audio held back to reorder --rtp-listen packets in, in milliseconds
+    #[arg(long, value_name="MS", default_value_t = 10.0)]
+    rtp_jitter: f32,
+
+    /// Payload format of the --rtp and --rtp-listen streams
+    #[arg(long, value_enum, default_value_t = RtpFormat::L24)]
+    rtp_format: RtpFormat,
+
+    /// Packet time of the --rtp stream in milliseconds
+    #[arg(long, value_name="MS", default_value_t = 1.0)]
+    rtp_ptime: f32,
+
+    /// Which format to use when an input header and the format flags disagree, defaults to the header with a warning
+    #[arg(long, value_enum)]
+    prefer: Option<Prefer>,
+
+    /// Play with the format flags even where an input header disagrees, same as '--prefer flags'
+    #[arg(long, default_value_t = false, conflicts_with = "prefer")]
+    force_cli: bool,
+
     /// Disables limits on gain (-g, --gain)
     #[arg(long, default_value_t = false)]
     dangerous: bool,
 
-    /// Input file path, if not specified, stdin will be used
+    /// Play sustained content above 18 kHz as is, e.g. for measurements, instead of low-passing it to protect tweeters
+    #[arg(long, default_value_t = false)]
+    allow_ultrasonic: bool,
+
+    /// Play content below 15 Hz as is, instead of high-passing it to protect woofers
+    #[arg(long, default_value_t = false)]
+    allow_subsonic: bool,
+
+    /// Play the samples as they are, without the subsonic high-pass, ultrasonic guard, dither or
+    /// resampling. A device that does not play the input rate is an error
+    #[arg(long, default_value_t = false, conflicts_with_all = ["resampler", "dither"])]
+    bit_perfect: bool,
+
+    /// Play only track N of a raw CD image, needs a .cue input or a .bin/.cdr input with a .cue next to it
+    ///
+    /// CD images play as CD audio unless the format is given
+    #[arg(long, value_name="N", conflicts_with="rtp_listen")]
+    track: Option<u32>,
+
+    /// When the input named pipe has no writer left, reopen it and wait for the next one instead of exiting
+    #[arg(long, default_value_t = false, requires = "infile", conflicts_with = "track")]
+    reopen: bool,
+
+    /// Keep reading the input file as it grows, like `tail -f`, waiting at its end instead of exiting
+    #[arg(short='F', long, default_value_t = false, requires = "infile", conflicts_with_all = ["track", "reopen"])]
+    follow: bool,
+
+    /// Play the entries of an M3U playlist input in random order, shuffled again on every repeat
+    #[arg(long, default_value_t = false, requires = "infile")]
+    shuffle: bool,
+
+    /// Read ahead and start playing only once this much input is buffered, as a duration (e.g. 500ms),
+    /// frames (e.g. @24000) or a number of bytes, for slow pipes and network sources
+    #[arg(long, value_name = "AMOUNT")]
+    prebuffer: Option<prebuffer::Threshold>,
+
+    /// Discard this many bytes of sample data before playing, after any container header
+    #[arg(long, value_name = "N", conflicts_with_all = ["skip_frames", "start"])]
+    skip_bytes: Option<u64>,
+
+    /// Discard this many frames of sample data before playing, after any container header
+    #[arg(long, value_name = "N", conflicts_with = "start")]
+    skip_frames: Option<u64>,
+
+    /// Start playing this far into the input, e.g. 1m30s, 00:01:30 or @48000 frames, by discarding the
+    /// frames before it
+    #[arg(long, value_name = "TIME")]
+    start: Option<Time>,
+
+    /// Stop playing after this long, e.g. 10s or @480000 frames, counted in frames at the sample rate
+    #[arg(long, value_name = "DURATION")]
+    duration: Option<Time>,
+
+    /// Play the input N times with --loop=N, or forever with --loop, rewinding files and replaying other
+    /// inputs from memory. Generators, datagrams and captures do not end, and can not loop
+    #[arg(long = "loop", value_name = "N", num_args = 0..=1, require_equals = true, value_parser = clap::value_parser!(u32).range(1..),
+        conflicts_with_all = ["follow", "reopen"])]
+    loop_passes: Option<Option<u32>>,
+
+    /// Repeat only the frames from START up to END, as times or frames, e.g. 1m..1m2.5s, 01:00..01:02.5
+    /// or @48000..@96000. Loops forever unless --loop=N is given
+    #[arg(long, value_name = "START..END", conflicts_with_all = ["skip_bytes", "skip_frames", "start", "duration"])]
+    loop_region: Option<looper::Region>,
+
+    /// Sum these files into the input, each in the input's format and scaled by GAIN if given, e.g. a click
+    /// track over a capture. A file that ends early stops adding to the mix
+    ///
+    /// --dangerous allows a GAIN above 1.0
+    #[arg(long, value_name = "FILE[:GAIN]", num_args = 1..)]
+    mix: Vec<MixInput>,
+
+    /// Play two single channel files as the left and right channels of one stream, e.g. the captures
+    /// of two converters. The format flags or headers describe each file, the stream ends with the shorter
+    #[arg(long, value_name = "FILE", requires = "right",
+        conflicts_with_all = ["infile", "rtp_listen", "listen", "udp", "connect", "listen_unix"])]
+    left: Option<PathBuf>,
+
+    /// The file played as the right channel with --left
+    #[arg(long, value_name = "FILE", requires = "left")]
+    right: Option<PathBuf>,
+
+    /// Play the frames of a file from the last to the first, reading it backwards in chunks
+    #[arg(long, default_value_t = false, requires = "infile", conflicts_with_all = ["follow", "reopen"])]
+    reverse: bool,
+
+    /// Watch the input for clicks and dropouts, sudden jumps the signal's bandwidth does not explain,
+    /// and report each with its time, or also pause there
+    #[arg(long, value_name = "ACTION", value_enum)]
+    clicks: Option<ClickAction>,
+
+    /// Start with a small device buffer and double it on every underrun, to find the lowest
+    /// latency this machine plays reliably at
+    #[arg(long, default_value_t = false)]
+    auto_buffer: bool,
+
+    /// Ask the output device for buffers of N frames instead of its default, larger ones play
+    /// reliably on a busy machine at the cost of latency
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..), conflicts_with = "auto_buffer")]
+    buffer_frames: Option<u32>,
+
+    /// Read the whole input into memory before playing, so slow disks and network mounts can not stall playback
+    #[arg(long, default_value_t = false, conflicts_with_all = ["prebuffer", "follow", "reopen"])]
+    preload: bool,
+
+    /// Play an M3U playlist input over and over: all of it, each entry (one) or a number of times
+    #[arg(long, value_name = "MODE", requires = "infile")]
+    repeat: Option<playlist::Repeat>,
+
+    /// Input file path, M3U playlist, unix socket or http:// URL, if not specified, stdin will be used
     infile: Option<String>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Speaker positions of the channels, from the input channel mask
+    #[arg(skip)]
+    channel_names: Vec<String>,
+
+    /// Channels silenced by --mute and --solo
+    #[arg(skip)]
+    muted: Vec<bool>,
+
+    /// The one channel --solo plays on every channel, without --map or --downmix
+    #[arg(skip)]
+    soloed: Option<usize>,
+
+    /// Gain of each channel from --gain-ch, empty without it
+    #[arg(skip)]
+    channel_gains: Vec<f32>,
+
+    /// Input channels mixed into device channels by --map and --downmix
+    #[arg(skip)]
+    routes: Vec<Route>,
+
+    /// Mute and dim of the output, set by --keys and --control
+    #[arg(skip)]
+    output_control: Arc<OutputControl>,
+}
+
+/// How the bits of an integer sample map to its value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Encoding {
+    /// Signed integers, the default
+    TwosComplement,
+    /// Unsigned integers with zero at the midpoint
+    OffsetBinary,
+    /// Sign bit followed by the magnitude
+    SignMagnitude,
+}
+
+/// Signed fixed point layout of integer samples, with `int_bits` + `frac_bits` + a sign bit.
+#[derive(Debug, Clone, Copy)]
+struct QFormat {
+    int_bits: u32,
+    frac_bits: u32,
+}
+
+impl QFormat {
+    /// Factor that takes a sample normalized by [dasp_sample] to its fixed point value.
+    fn scale(&self, sample_size: u32) -> f32 {
+        2f32.powi(sample_size as i32 - 1 - self.frac_bits as i32)
+    }
+}
+
+impl FromStr for QFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let lower = s.to_ascii_lowercase();
+        let Some(bits) = lower.strip_prefix('q') else {
+            return Err(format!("Invalid Q format '{s}', expected qN.M or qM"));
+        };
+        let (int_bits, frac_bits) = match bits.split_once('.') {
+            Some((int_bits, frac_bits)) => (int_bits, frac_bits),
+            None => ("0", bits),
+        };
+        let int_bits = int_bits.parse::<u32>().map_err(|e| format!("{e}"))?;
+        let frac_bits = frac_bits.parse::<u32>().map_err(|e| format!("{e}"))?;
+        Ok(QFormat { int_bits, frac_bits })
+    }
+}
+
+/// Named input formats, expanding to the format flags.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Preset {
+    /// CD audio, 44.1kHz 16 bit signed stereo
+    Cdda,
+    /// Digital audio tape, 48kHz 16 bit signed stereo
+    Dat,
+    /// Narrowband telephony, 8kHz 16 bit signed mono
+    Telephony,
+    /// Broadcast production, 48kHz 24 bit signed stereo
+    Broadcast,
+}
+
+impl Preset {
+    /// Format flags set by the preset, as (sample rate, sample size, channels).
+    fn format(self) -> (u32, u32, u16) {
+        match self {
+            Preset::Cdda      => (44_100, 16, 2),
+            Preset::Dat       => (48_000, 16, 2),
+            Preset::Telephony => ( 8_000, 16, 1),
+            Preset::Broadcast => (48_000, 24, 2),
+        }
+    }
+}
+
+/// sox style sample format, `s`, `u` or `f` followed by the size and optionally `le` or `be`.
+#[derive(Debug, Clone, Copy)]
+struct SampleType {
+    float: bool,
+    unsigned: bool,
+    sample_size: u32,
+    big_endian: Option<bool>,
+}
+
+impl SampleType {
+    /// Whether this type sets the argument `id`.
+    fn sets(&self, id: &str) -> bool {
+        match id {
+            "sample_size" | "unsigned" | "float" => true,
+            "be" => self.big_endian.is_some(),
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for SampleType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let lower = s.to_ascii_lowercase();
+        let invalid = || format!("Invalid sample type '{s}', expected e.g. s16le, u8, f32be");
+        let (float, unsigned, rest) = match lower.split_at_checked(1) {
+            Some(("s", rest)) => (false, false, rest),
+            Some(("u", rest)) => (false, true, rest),
+            Some(("f", rest)) => (true, false, rest),
+            _ => return Err(invalid()),
+        };
+        let (size, big_endian) = if let Some(size) = rest.strip_suffix("le") {
+            (size, Some(false))
+        } else if let Some(size) = rest.strip_suffix("be") {
+            (size, Some(true))
+        } else {
+            (rest, None)
+        };
+        let sample_size = size.parse::<u32>().map_err(|_| invalid())?;
+        Ok(SampleType { float, unsigned, sample_size, big_endian })
+    }
+}
+
+/// Arguments that a [Preset] sets.
+const PRESET_ARGS: [&str; 5] = ["sample_rate", "sample_size", "channels", "unsigned", "float"];
+
+/// Whether an argument was given on the command line, directly or through `--preset`.
+fn is_explicit(matches: &ArgMatches, id: &str) -> bool {
+    matches.value_source(id) == Some(ValueSource::CommandLine)
+        || (matches.value_source("preset").is_some() && PRESET_ARGS.contains(&id))
+        || matches.get_one::<SampleType>("sample_type").is_some_and(|t| t.sets(id))
+        || (id == "be" && matches!(matches.get_one::<ByteOrder>("byte_order"), Some(ByteOrder::Little | ByteOrder::Big)))
+}
+
+/// Generated inputs, played instead of a file or stdin.
+#[derive(Subcommand, Debug, Clone)]
+enum Command {
+    /// Play a short sine tone, e.g. as a terminal bell
+    Beep {
+        /// Tone frequency in Hz
+        #[arg(default_value_t = 880.0)]
+        frequency: f64,
+
+        /// Tone length, e.g. 0.5, 2s, 200ms or @4800 frames
+        #[arg(default_value = "150ms")]
+        duration: Time,
+    },
+    /// Key text as morse code (CW)
+    Morse {
+        /// Text to key, letters, digits and common punctuation
+        text: String,
+
+        /// Speed in words per minute, timed on the word PARIS
+        #[arg(long, default_value_t = 20.0)]
+        wpm: f64,
+
+        /// Tone frequency in Hz
+        #[arg(long, default_value_t = 600.0)]
+        tone: f64,
+    },
+    /// Dial digits as DTMF tones, e.g. 1-800-555-1234, with a comma for a one second pause
+    Dtmf {
+        /// Keys to dial: 0-9, *, #, A-D
+        digits: String,
+
+        /// How long each key is held
+        #[arg(long, default_value = "100ms")]
+        duration: Time,
+
+        /// Silence between keys
+        #[arg(long, default_value = "100ms")]
+        gap: Time,
+    },
+    /// Play a telephony reference tone: dial, ringback, busy, reorder or the special information tone
+    Telephony {
+        #[arg(value_enum)]
+        tone: TelephonyTone,
+
+        /// How long to repeat the tone's cadence for, the special information tone plays once
+        #[arg(default_value = "4s")]
+        duration: Time,
+    },
+    /// Play background noise at a set loudness, e.g. to mask sounds while sleeping
+    Noise {
+        #[arg(value_enum, default_value_t = NoiseColor::Pink)]
+        color: NoiseColor,
+
+        /// Integrated loudness of the noise in LUFS, before --gain
+        #[arg(long, default_value_t = -30.0, allow_negative_numbers = true)]
+        lufs: f64,
+
+        /// Stop after this long with a fade out, e.g. 45m or 1h, plays until stopped otherwise
+        #[arg(long, value_name = "DURATION")]
+        sleep: Option<Time>,
+    },
+    /// Play a test pattern on every channel at once, for checking a multichannel interface or cable loom
+    ///
+    /// Each channel beeps its number, long beeps for tens and short ones for units,
+    /// then holds a steady tone. Channel 1 is at 400 Hz, each later channel 100 Hz higher.
+    Pattern,
+    /// Play a stimulus while recording the default input device, for impulse response extraction
+    Measure {
+        #[arg(long, value_enum, default_value_t = Stimulus::Chirp)]
+        stimulus: Stimulus,
+
+        /// Length of the chirp, e.g. 5s
+        #[arg(long, default_value = "5s")]
+        duration: Time,
+
+        /// File to write the response to, as f32le with the input device's channels
+        #[arg(long, value_name = "FILE")]
+        record: PathBuf,
+
+        /// File to write the stimulus as played to, as mono f32le [default: RECORD with a .stimulus.raw extension]
+        #[arg(long, value_name = "FILE")]
+        stimulus_out: Option<PathBuf>,
+    },
+    /// Play a chirp and record it back through a loopback cable from an output to the default
+    /// input device, then report latency, drift, dropouts, level and frequency response
+    ///
+    /// Exits with an error when the round trip is not clean.
+    Loopcheck {
+        /// Length of the chirp, e.g. 5s
+        #[arg(long, default_value = "5s")]
+        duration: Time,
+    },
+    /// Re-record a region of a take made with measure, replacing its frames between the punch-in
+    /// and punch-out points with the default input device
+    ///
+    /// The take plays from the pre-roll before punch-in to punch-out as a cue, and the frames
+    /// around the region are left as they were.
+    Punch {
+        /// The take to punch into, f32le with the input device's channels
+        take: PathBuf,
+
+        /// Where the replaced region starts, e.g. 1m or 61.5s
+        #[arg(long = "in", value_name = "TIME")]
+        punch_in: Time,
+
+        /// Where the replaced region ends
+        #[arg(long = "out", value_name = "TIME")]
+        punch_out: Time,
+
+        /// How much of the take to play before punch-in
+        #[arg(long, value_name = "DURATION", default_value = "2s")]
+        pre_roll: Time,
+    },
+    /// Record an input device to FILE in the format the format flags give, e.g. -r 44100 -s 16,
+    /// as raw samples or, by its extension or --out-format, WAV or AU
+    Record {
+        /// File to record to
+        file: PathBuf,
+
+        /// Input device to record, picked like --device among the input devices [default: the
+        /// default input device]
+        #[arg(long, value_name = "DEVICE")]
+        from: Option<String>,
+
+        /// Stop after this long, e.g. 10s or 1h, records until interrupted otherwise
+        #[arg(long)]
+        duration: Option<Time>,
+    },
+    /// Report wow, flutter and dropouts of a digitized tape capture from the test tone or pilot
+    /// recorded on it
+    ///
+    /// The tone is found in the first seconds of the capture unless --pilot gives it, and the
+    /// speed error is reported when it does. Takes the format flags of the file like playback.
+    TapeCheck {
+        /// The capture to analyse
+        file: PathBuf,
+
+        /// Frequency of the tone as it was recorded, e.g. 3150 or 3000
+        #[arg(long, value_name = "HZ")]
+        pilot: Option<f64>,
+    },
+}
+
+/// Source of the input format when more than one describes it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Prefer {
+    /// The header at the start of the input
+    Header,
+    /// The format flags on the command line
+    Flags,
+}
+
+/// Floating point precision used between decoding samples and the device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Precision {
+    F32,
+    F64,
 }
 
 struct ValidConfigOut {
     sample_format: cpal::SampleFormat,
     sample_source: Box<dyn io::Read + Send>,
-    sample_sink: Option<Box<dyn io::Write + Send>>
+    /// inputs summed into the sample source, with their gains
+    mixes: Vec<(Box<dyn io::Read + Send>, f32)>,
+    taps: Vec<TapWriter>,
 }
 
-/// Sanity checks the sample format configuration, emits some errors.
-/// Returns the sample format in the appropriate [cpal::SampleFormat] enum.
-fn config_sanity_check(opt: &mut Opt) -> Result<ValidConfigOut, String> {
-    use cpal::SampleFormat::*;
-    let sample_format = match (opt.float, opt.unsigned, opt.sample_size) {
-        (false, false, 8) => I8,
-        (false,  true, 8) => U8,
+/// Takes the input format from a container header.
+/// Format flags given on the command line that disagree with the header are overridden with a warning,
+/// unless `--prefer` picks which of the two to use.
+fn apply_stream_header(
+    opt: &mut Opt,
+    matches: &ArgMatches,
+    container: container::Container,
+    stream_header: &header::StreamHeader,
+) -> Result<(), String> {
+    let header_encoding = if stream_header.sign_magnitude {
+        Encoding::SignMagnitude
+    } else {
+        Encoding::TwosComplement
+    };
+    let is_explicit = |id: &str| is_explicit(matches, id);
 
-        (false, false, 16) => I16,
-        (false,  true, 16) => U16,
+    let claims = [
+        ("sample_rate", "sample rate", stream_header.sample_rate.to_string(), opt.sample_rate.to_string()),
+        ("channels", "channels", stream_header.channels.to_string(), opt.channels.to_string()),
+        ("sample_size", "sample size", stream_header.sample_size.to_string(), opt.sample_size.to_string()),
+        ("float", "float", stream_header.float.to_string(), opt.float.to_string()),
+        ("unsigned", "unsigned", stream_header.unsigned.to_string(), opt.unsigned.to_string()),
+        ("be", "big-endian", stream_header.big_endian.to_string(), opt.be.to_string()),
+        ("encoding", "encoding", format!("{header_encoding:?}"), format!("{:?}", opt.encoding)),
+    ];
+    if opt.prefer.is_none() {
+        for (_, name, from_header, from_flags) in claims.iter()
+            .filter(|(id, _, from_header, from_flags)| is_explicit(id) && from_header != from_flags) {
+            eprintln!("[!] {container} header sets {name} to {from_header}, overriding {from_flags} from the command line");
+        }
+    }
 
-        (false, false, 32) => I32,
-        (false,  true, 32) => U32,
+    let use_header = |id: &str| opt.prefer != Some(Prefer::Flags) || !is_explicit(id);
+    if use_header("sample_rate") {
+        opt.sample_rate = stream_header.sample_rate;
+    }
+    if use_header("channels") {
+        opt.channels = stream_header.channels;
+    }
+    if use_header("sample_size") {
+        opt.sample_size = stream_header.sample_size as u32;
+    }
+    if use_header("float") {
+        opt.float = stream_header.float;
+    }
+    if use_header("unsigned") {
+        opt.unsigned = stream_header.unsigned;
+    }
+    if use_header("be") {
+        opt.be = stream_header.big_endian;
+    }
+    if use_header("encoding") {
+        opt.encoding = header_encoding;
+    }
+    Ok(())
+}
 
-        (false, false, 64) => I64,
-        (false,  true, 64) => U64,
+/// Sanity checks the sample format configuration, emits some errors.
+/// Returns the sample format in the appropriate [cpal::SampleFormat] enum.
+fn config_sanity_check(opt: &mut Opt, matches: &ArgMatches) -> Result<ValidConfigOut, String> {
+    if opt.bit_perfect {
+        opt.allow_subsonic = true;
+        opt.allow_ultrasonic = true;
+        opt.dither = Dither::None;
+    }
+    let cue_sheet = match opt.infile.as_deref().filter(|f| !http::is_url(f)).map(Path::new) {
+        Some(path) if path.extension().is_some_and(|e| e.eq_ignore_ascii_case("cue")) => Some(CueSheet::open(path)?),
+        Some(path) => CueSheet::find_for(path).map(|cue| CueSheet::open(&cue)).transpose()?,
+        None => None,
+    };
+    if let Some(cue_sheet) = &cue_sheet {
+        if opt.preset.is_none() && opt.sample_type.is_none() {
+            opt.preset = Some(Preset::Cdda);
+        }
+        if !is_explicit(matches, "be") {
+            opt.be = cue_sheet.big_endian;
+        }
+    } else if opt.track.is_some() {
+        return Err("--track needs a cue sheet, as the input or next to a .bin/.cdr input".into());
+    }
 
-        (true, false, 32) => F32,
-        (true, false, 64) => F64,
+    if let Some(preset) = opt.preset {
+        let (sample_rate, sample_size, channels) = preset.format();
+        let given = |id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+        if !given("sample_rate") {
+            opt.sample_rate = sample_rate;
+        }
+        if !given("sample_size") {
+            opt.sample_size = sample_size;
+        }
+        if !given("channels") {
+            opt.channels = channels;
+        }
+        // every preset is signed integer
+        if !given("unsigned") && !given("encoding") {
+            opt.unsigned = false;
+        }
+        if !given("float") {
+            opt.float = false;
+        }
+    }
 
-        (true, true, _) => {
-            return Err("Floating point values can not be represented as unsigned".into());
-        },
+    if let Some(sample_type) = opt.sample_type {
+        opt.float = sample_type.float;
+        opt.unsigned = sample_type.unsigned;
+        opt.sample_size = sample_type.sample_size;
+        opt.be = sample_type.big_endian.unwrap_or(opt.be);
+    }
 
-        (true, false, invalid_size) => {
-            return Err(format!("Unsupported floating point size: '{invalid_size}', can only be: [32, 64]"));
+    match opt.byte_order {
+        Some(_) if opt.sample_type.is_some_and(|t| t.big_endian.is_some()) => {
+            return Err("Incompatible options '--type' with a byte order and '--be', can choose only one".into());
         },
+        Some(ByteOrder::Little) => opt.be = false,
+        Some(ByteOrder::Big) => opt.be = true,
+        _ => (),
+    }
 
-        (false, _, invalid_size) => {
-            return Err(format!("Unsupported sample size: '{invalid_size}'"));
-        },
+    let needs_device = matches!(opt.command, Some(Command::Measure { .. } | Command::Loopcheck { .. } | Command::Punch { .. }))
+        || opt.on_busy.is_some() || opt.record_response.is_some();
+    let written = match &opt.command {
+        Some(Command::Record { file, .. }) => Some(file),
+        _ => opt.render.as_ref(),
+    };
+    if let Some(path) = written
+        && !is_explicit(matches, "out_format")
+    {
+        let extension = path.extension().map(|e| e.to_ascii_lowercase());
+        opt.out_format = match extension.as_ref().and_then(|e| e.to_str()) {
+            Some("wav") => TapFormat::Wav,
+            Some("au") => TapFormat::Au,
+            _ => TapFormat::Raw,
+        };
+    }
+    let without_device = match opt.clock {
+        Clock::Device => None,
+        _ if matches!(opt.command, Some(Command::Record { .. })) => Some("record"),
+        Clock::Timer => Some("--clock timer"),
+        Clock::Free if opt.render.is_some() => Some("--render"),
+        Clock::Free => Some("--clock free"),
     };
+    if let Some(without_device) = without_device {
+        if needs_device {
+            return Err(format!("measure, loopcheck, punch, --on-busy and --record-response need an audio device, they can not run with {without_device}"));
+        }
+        if opt.auto_buffer {
+            eprintln!("[!] --auto-buffer has no effect with {without_device}, there is no device buffer to tune");
+        }
+        if opt.buffer_frames.is_some() {
+            eprintln!("[!] --buffer-frames has no effect with {without_device}, there is no device buffer to size");
+        }
+        if !opt.device.is_empty() {
+            eprintln!("[!] --device has no effect with {without_device}, nothing is played on a device");
+        }
+        if is_explicit(matches, "reconnect_wait") {
+            eprintln!("[!] --reconnect-wait has no effect with {without_device}, there is no device to lose");
+        }
+        if !opt.map.is_empty() || opt.upmix || opt.downmix {
+            eprintln!("[!] --map, --upmix and --downmix have no effect with {without_device}, there are no device channels to play on");
+        }
+        if opt.jack_name.is_some() || !opt.jack_connect.is_empty() {
+            eprintln!("[!] --jack-name and --jack-connect have no effect with {without_device}, nothing is played through JACK");
+        }
+        if opt.resampler.is_some() {
+            eprintln!("[!] --resampler has no effect with {without_device}, there is no device rate to resample to");
+        }
+    }
 
-    if let (true, true) = (opt.pre_out, opt.post_out) {
-        return Err("Incompatible options '--pre' and '--post', can choose only one or none".into());
+    if opt.left.is_some() {
+        if opt.command.is_some() || opt.infile.is_some() {
+            return Err("--left and --right are the input, they can not be combined with another one".into());
+        }
+        if is_explicit(matches, "channels") && !matches!(opt.channels, 1 | 2) {
+            eprintln!("[!] --channels {} ignored, --left and --right are one channel each of a stereo stream", opt.channels);
+        }
     }
 
-    let input: Box<dyn io::Read + Send> = if let Some(ref infile) = opt.infile {
-        let path = PathBuf::from_str(infile)
+    let is_playlist = opt.infile.as_deref().is_some_and(|f| playlist::is_playlist(f) && !http::is_url(f));
+    if (opt.shuffle || opt.repeat.is_some()) && (!is_playlist || opt.command.is_some()) {
+        return Err("--shuffle and --repeat need an M3U playlist input".into());
+    }
+
+    // a regular file and the cue sheet track in it, which can be read again to loop it
+    let mut seekable = None;
+    let mut datagrams = opt.udp.is_some();
+    let input: Box<dyn io::Read + Send> = if let Some(command) = opt.command.clone() {
+        // generators write native f32 samples, record writes the format the flags give
+        if !matches!(command, Command::Record { .. }) {
+            opt.float = true;
+            opt.unsigned = false;
+            opt.sample_size = 32;
+            opt.be = false;
+        }
+        let channels = opt.channels as usize;
+        match command {
+            Command::Beep { frequency, duration } => {
+                let segments = [Segment::tone(duration.duration(opt.sample_rate), &[frequency])];
+                Box::new(ToneSource::new(opt.sample_rate, channels, &segments))
+            },
+            Command::Morse { text, wpm, tone } => {
+                let segments = generator::morse_segments(&text, wpm, tone)?;
+                Box::new(ToneSource::new(opt.sample_rate, channels, &segments))
+            },
+            Command::Dtmf { digits, duration, gap } => {
+                let segments = generator::dtmf_segments(&digits, duration.duration(opt.sample_rate), gap.duration(opt.sample_rate))?;
+                Box::new(ToneSource::new(opt.sample_rate, channels, &segments))
+            },
+            Command::Telephony { tone, duration } => {
+                Box::new(ToneSource::new(opt.sample_rate, channels, &tone.segments(duration.duration(opt.sample_rate))))
+            },
+            Command::Noise { color, lufs, sleep } => {
+                let sleep = sleep.map(|sleep| sleep.duration(opt.sample_rate));
+                Box::new(NoiseSource::new(opt.sample_rate, channels, color, lufs, sleep))
+            },
+            Command::Pattern => {
+                let frequencies = (0..channels)
+                    .map(|c| format!("{}: {} Hz", c + 1, generator::pattern_frequency(c)))
+                    .collect::<Vec<_>>();
+                eprintln!("[!] channel frequencies {}", frequencies.join(", "));
+                Box::new(PatternSource::new(opt.sample_rate, channels))
+            },
+            // measure, loopcheck and punch play their stimulus themselves, alongside a capture stream
+            Command::Measure { .. } | Command::Loopcheck { .. } | Command::Punch { .. } => Box::new(io::empty()),
+            // record reads an input device instead of playing
+            Command::Record { .. } => Box::new(io::empty()),
+            Command::TapeCheck { .. } => unreachable!("tape-check reads its file as the input"),
+        }
+    } else if let Some(addr) = opt.rtp_listen {
+        opt.sample_size = opt.rtp_format.sample_size();
+        opt.be = true;
+        let frame_len = opt.channels as usize * opt.sample_size as usize / 8;
+        let jitter = Duration::from_secs_f32(opt.rtp_jitter.max(0.0) / 1000.0);
+        let source = RtpSource::new(addr, opt.sample_rate, frame_len, jitter)
             .map_err(|e| format!("{e}"))?;
+        eprintln!("[!] waiting for RTP packets on {addr}");
+        Box::new(source)
+    } else if let Some(addr) = opt.listen {
+        let stream = net::accept(addr).map_err(|e| format!("{addr}: {e}"))?;
+        Box::new(io::BufReader::new(stream))
+    } else if let Some(addr) = opt.udp {
+        let (silent_frame, max_buffered) = datagram_buffer(opt)?;
+        let source = net::UdpSource::new(addr, silent_frame, max_buffered)
+            .map_err(|e| format!("{addr}: {e}"))?;
+        eprintln!("[!] waiting for datagrams on {addr}");
+        Box::new(source)
+    } else if opt.socket_activation {
+        match net::activated().map_err(|e| format!("socket activation: {e}"))? {
+            net::Activated::Stream(stream) => Box::new(io::BufReader::new(stream)),
+            net::Activated::Datagram(socket) => {
+                let (silent_frame, max_buffered) = datagram_buffer(opt)?;
+                datagrams = true;
+                eprintln!("[!] waiting for datagrams on the socket passed by systemd");
+                Box::new(net::UdpSource::from_socket(socket, silent_frame, max_buffered))
+            },
+        }
+    } else if let Some(name) = opt.capture.clone() {
+        let device = devices::input_device(name.as_deref())?;
+        let default = device.default_input_config()
+            .map_err(|e| format!("failed to get the input config: {e}"))?;
+        if !is_explicit(matches, "sample_rate") {
+            opt.sample_rate = default.sample_rate().0;
+        }
+        if !is_explicit(matches, "channels") {
+            opt.channels = default.channels();
+        }
+        // captured samples are read as native f32
+        opt.float = true;
+        opt.unsigned = false;
+        opt.sample_size = 32;
+        opt.be = false;
+        let config = cpal::StreamConfig {
+            channels: opt.channels,
+            sample_rate: cpal::SampleRate(opt.sample_rate),
+            buffer_size: cpal::BufferSize::Default,
+        };
+        let name = device.name().unwrap_or_else(|_| "default".into());
+        eprintln!("[!] capturing {name} at {}Hz, {} channels", opt.sample_rate, opt.channels);
+        if opt.monitor {
+            opt.buffer_frames.get_or_insert(capture::MONITOR_FRAMES);
+        }
+        let max_latency = opt.monitor.then_some(capture::MONITOR_LATENCY);
+        Box::new(capture::CaptureSource::open(device, config, max_latency)?)
+    } else if let Some(path) = &opt.serial {
+        let device = serial::open(path, opt.baud).map_err(|e| format!("{}: {e}", path.display()))?;
+        Box::new(io::BufReader::new(device))
+    } else if let Some(addr) = opt.connect {
+        let stream = net::connect(addr).map_err(|e| format!("{addr}: {e}"))?;
+        Box::new(io::BufReader::new(stream))
+    } else if let Some(path) = &opt.listen_unix {
+        let stream = net::accept_unix(path).map_err(|e| format!("{}: {e}", path.display()))?;
+        Box::new(io::BufReader::new(stream))
+    } else if let Some(path) = opt.infile.as_deref().map(Path::new).filter(|p| net::is_unix_socket(p)) {
+        let stream = net::connect_unix(path).map_err(|e| format!("{}: {e}", path.display()))?;
+        Box::new(io::BufReader::new(stream))
+    } else if let Some(path) = opt.infile.as_deref().map(Path::new).filter(|_| opt.reopen) {
+        if !fifo::is_fifo(path) {
+            return Err(format!("--reopen needs a named pipe, {} is not one", path.display()));
+        }
+        let file = fs::File::open(path).map_err(|e| format!("{}: {e}", path.display()))?;
+        Box::new(io::BufReader::new(fifo::ReopenFifo::new(path, file)))
+    } else if let Some(path) = opt.infile.as_deref().filter(|_| is_playlist) {
+        let flags = header::StreamHeader {
+            sample_rate: opt.sample_rate,
+            channels: opt.channels,
+            sample_size: opt.sample_size as u8,
+            float: opt.float,
+            unsigned: opt.unsigned || opt.encoding == Encoding::OffsetBinary,
+            big_endian: opt.be,
+            sign_magnitude: opt.encoding == Encoding::SignMagnitude,
+            silence_runs: false,
+        };
+        Box::new(playlist::Playlist::open(Path::new(path), flags, opt.shuffle, opt.repeat)?)
+    } else if let Some(url) = opt.infile.as_deref().filter(|f| http::is_url(f)) {
+        Box::new(io::BufReader::new(http::HttpSource::open(url)?))
+    } else if let Some(path) = &opt.left {
+        let file = fs::File::open(path).map_err(|e| format!("{}: {e}", path.display()))?;
+        Box::new(io::BufReader::new(file))
+    } else if let Some(ref infile) = opt.infile {
+        let path = match &cue_sheet {
+            Some(cue_sheet) if infile.to_ascii_lowercase().ends_with(".cue") => cue_sheet.image.clone(),
+            _ => PathBuf::from_str(infile).map_err(|e| format!("{e}"))?,
+        };
 
-        let file = fs::File::options()
+        let mut file = fs::File::options()
             .read(true)
             .write(false)
             .create(false)
-            .open(path)
-                .map_err(|e| format!("{e}"))?;
+            .open(&path)
+                .map_err(|e| format!("{}: {e}", path.display()))?;
+
+        let track = match (&cue_sheet, opt.track) {
+            (Some(cue_sheet), Some(number)) => Some(cue_sheet.track_range(number)?),
+            _ => None,
+        };
+        if let Some((start, _)) = track {
+            file.seek(io::SeekFrom::Start(start)).map_err(|e| format!("{e}"))?;
+        }
 
+        if !opt.follow && file.metadata().is_ok_and(|m| m.is_file()) {
+            seekable = Some((path.clone(), track));
+        }
         let buffered_file = io::BufReader::new(file);
-        Box::new(buffered_file)
+        match track {
+            Some((_, Some(len))) => Box::new(buffered_file.take(len)),
+            _ if opt.follow => Box::new(Follow::new(buffered_file)),
+            _ => Box::new(buffered_file),
+        }
     } else {
         let stdin = io::stdin();
         let buffered_stdin = io::BufReader::new(stdin);
         Box::new(buffered_stdin)
     };
 
-    let output: Option<Box<dyn io::Write + Send>> = if opt.pre_out || opt.post_out {
-        let stdout = io::stdout();
-        Some(Box::new(stdout))
+    if opt.force_cli {
+        opt.prefer = Some(Prefer::Flags);
+    }
+    // datagrams, generated and captured samples are bare samples
+    let (stream_header, input) = if opt.rtp_listen.is_some() || datagrams || opt.command.is_some() || opt.capture.is_some() {
+        (None, input)
     } else {
-        None
+        container::detect(compress::decompress(input)?)?
+    };
+    let data_region = stream_header.as_ref().map(|d| (d.data_offset, d.data_len, d.spec.silence_runs));
+    let header_sets_order = stream_header.is_some() && opt.prefer != Some(Prefer::Flags);
+    if let Some(detected) = &stream_header {
+        apply_stream_header(opt, matches, detected.container, &detected.spec)?;
+    }
+    if opt.left.is_some() {
+        if let Some(detected) = &stream_header && detected.spec.channels != 1 {
+            return Err(format!("--left takes a single channel file, it has {} channels", detected.spec.channels));
+        }
+        opt.channels = 2;
+    }
+    if let Some(mask) = stream_header.and_then(|d| d.channel_mask).filter(|_| opt.left.is_none()) {
+        let names = channel::speaker_names(mask);
+        if names.len() == opt.channels as usize {
+            opt.channel_names = names;
+        } else {
+            eprintln!("[!] channel mask names {} channels, the stream has {}, ignoring it", names.len(), opt.channels);
+        }
+    }
+
+    match opt.encoding {
+        Encoding::TwosComplement => (),
+        _ if opt.float => {
+            return Err(format!("Floating point values can not use the '{:?}' encoding", opt.encoding));
+        },
+        Encoding::OffsetBinary => opt.unsigned = true,
+        Encoding::SignMagnitude if opt.unsigned => {
+            return Err("Sign-magnitude values can not be represented as unsigned".into());
+        },
+        Encoding::SignMagnitude => (),
+    }
+    if opt.delta && opt.encoding == Encoding::SignMagnitude {
+        return Err("Delta encoded samples (--delta) can not use the 'SignMagnitude' encoding".into());
+    }
+
+    let sample_format = header::sample_format(opt.float, opt.unsigned, opt.sample_size)?;
+
+    if let Some(q) = opt.fixed {
+        if opt.float || opt.unsigned {
+            return Err("Fixed point samples (--fixed) must be signed integers".into());
+        }
+        let q_bits = q.int_bits + q.frac_bits + 1;
+        if q_bits > opt.sample_size {
+            return Err(format!("Fixed point format needs {q_bits} bits, larger than the sample size '{}'", opt.sample_size));
+        }
+    }
+
+    let input = match (&opt.left, &opt.right) {
+        (Some(left), Some(right)) => {
+            if !opt.sample_size.is_multiple_of(8) {
+                return Err(format!("--left and --right need samples of whole bytes, these are {} bits", opt.sample_size));
+            }
+            let spec = header::StreamHeader {
+                channels: 1,
+                sample_size: opt.sample_size as u8,
+                ..tap_spec(opt, Tap::Pre, sample_format)
+            };
+            let right_input = mix::open(right, &spec, left.to_string_lossy().as_ref())?;
+            let inputs = vec![(left.display().to_string(), input), (right.display().to_string(), right_input)];
+            Box::new(interleave::Interleave::new(inputs, opt.sample_size as usize / 8))
+        },
+        _ => input,
+    };
+
+    let input = if opt.byte_order == Some(ByteOrder::Auto) && !header_sets_order && opt.sample_size > 8 {
+        let (big_endian, input) = byte_order::guess(input, opt.sample_size, opt.float, opt.unsigned, opt.channels as usize)?;
+        match big_endian {
+            Some(big_endian) => {
+                opt.be = big_endian;
+                eprintln!("[!] input looks {}-endian (--be auto)", if big_endian { "big" } else { "little" });
+            },
+            None => eprintln!("[!] could not tell the byte order of the input (--be auto), assuming little-endian"),
+        }
+        input
+    } else {
+        input
     };
 
+    if let Some(shift) = opt.channel_shift.iter().find(|s| s.channel >= opt.channels as usize) {
+        return Err(format!("Can not shift channel '{}' of a {} channel stream", shift.channel, opt.channels));
+    }
+
+    let channels = opt.channels as usize;
+    let resolve = |refs: &[ChannelRef]| refs.iter()
+        .map(|r| r.resolve(&opt.channel_names, channels))
+        .collect::<Result<Vec<_>, _>>();
+    let (muted, soloed) = (resolve(&opt.mute)?, resolve(&opt.solo)?);
+    opt.muted = (0..channels)
+        .map(|c| muted.contains(&c) || (!soloed.is_empty() && !soloed.contains(&c)))
+        .collect();
+    if let [solo] = soloed[..] && opt.map.is_empty() && !opt.downmix {
+        opt.soloed = Some(solo);
+    }
+    if !opt.gain_ch.is_empty() {
+        opt.channel_gains = vec![1.0; channels];
+        for gain in &opt.gain_ch {
+            opt.channel_gains[gain.channel.resolve(&opt.channel_names, channels)?] = gain.gain;
+        }
+    }
+    opt.routes = opt.map.iter()
+        .map(|map| Ok(Route { from: map.input.resolve(&opt.channel_names, channels)?, to: map.output, gain: 1.0 }))
+        .collect::<Result<_, String>>()?;
+    if opt.downmix {
+        if channels <= 2 {
+            eprintln!("[!] --downmix has no effect, the input has {channels} channels");
+        } else {
+            opt.routes = channel::downmix_routes(&opt.channel_names, channels)?;
+        }
+    }
+    if opt.upmix && channels != 1 {
+        eprintln!("[!] --upmix has no effect, the input has {channels} channels rather than one");
+    }
+
+    if let (true, true) = (opt.pre_out, opt.post_out) {
+        return Err("Incompatible options '--pre' and '--post', can choose only one or none".into());
+    }
+
+    if opt.pre_out || opt.post_out || opt.render.is_some() {
+        let tap = if opt.pre_out { Tap::Pre } else { Tap::Post };
+        let encoder = opt.out_format.encoder();
+        encoder.check(&tap_spec(opt, tap, sample_format))?;
+        match encoder.big_endian() {
+            Some(false) if opt.be => {
+                eprintln!("[!] {:?} output is always little-endian, --be only applies to the input", opt.out_format);
+            },
+            Some(true) if !opt.be => {
+                eprintln!("[!] {:?} output is always big-endian", opt.out_format);
+            },
+            _ => (),
+        }
+    }
+
+    if opt.rtp.is_some() && opt.sample_rate != 48_000 {
+        eprintln!("[!] AES67 receivers expect 48kHz, RTP stream is sent at {}Hz", opt.sample_rate);
+    }
+
+    if opt.serial.is_some() {
+        // a start and a stop bit around every byte
+        let needed = opt.sample_rate as u64 * opt.channels as u64 * opt.sample_size as u64 * 10 / 8;
+        if needed > opt.baud as u64 {
+            eprintln!("[!] the stream needs {needed} baud, more than the {} of the serial line, expect dropouts", opt.baud);
+        }
+    }
+
     if opt.be && opt.sample_size == 8 {
         eprintln!("[!] endianness ignored (--be), irrelevant with 8-bit samples");
     }
@@ -151,36 +1355,651 @@ fn config_sanity_check(opt: &mut Opt) -> Result<ValidConfigOut, String> {
             is_config_dangerous = true;
         }
         opt.gain = opt.gain.clamp(0.0, 1.0);
+        for (channel, gain) in opt.channel_gains.iter_mut().enumerate() {
+            if !(0.0 <= *gain && *gain <= 1.0) {
+                eprintln!("[!] gain value {gain} of channel {channel} exceeds safety limit (0.0 <= gain <= 1.0)");
+                is_config_dangerous = true;
+            }
+            *gain = gain.clamp(0.0, 1.0);
+        }
+        for mix in &mut opt.mix {
+            if !(0.0 <= mix.gain && mix.gain <= 1.0) {
+                eprintln!("[!] gain value {} of mix input {} exceeds safety limit (0.0 <= gain <= 1.0)", mix.gain, mix.path.display());
+                is_config_dangerous = true;
+            }
+            mix.gain = mix.gain.clamp(0.0, 1.0);
+        }
     }
 
 
+    if opt.bit_perfect && (opt.gain != 1.0 || !opt.channel_gains.is_empty() || !opt.mix.is_empty()) {
+        eprintln!("[!] --gain, --gain-ch and --mix change the samples that --bit-perfect plays");
+    }
+
     if is_config_dangerous && !acknowledged {
         eprintln!("[!] WARNING: may generate very loud sounds that could permanently damage your hearing and/or computer.");
         eprintln!("[!] Pass --dangerous to the program or set the RPLAY_DANGEROUS environment variable to acknowledge this.");
         std::process::exit(1);
     }
 
+    if opt.print_config {
+        print_config(opt, sample_format);
+        process::exit(0);
+    }
+
+    if opt.keys {
+        control::read_keys(opt.output_control.clone())
+            .map_err(|e| format!("failed to read hotkeys from the terminal: {e}"))?;
+    }
+    if let Some(path) = &opt.control {
+        control::serve(path, opt.output_control.clone())
+            .map_err(|e| format!("{}: {e}", path.display()))?;
+    }
+    if let Err(e) = control::listen_for_panic(opt.output_control.clone()) {
+        eprintln!("[!] failed to listen for rplayctl: {e}");
+    }
+
+    let mut taps = Vec::new();
+    if opt.pre_out || opt.post_out {
+        let tap = if opt.pre_out { Tap::Pre } else { Tap::Post };
+        let spec = tap_spec(opt, tap, sample_format);
+        let tap_writer = TapWriter::new(tap, opt.out_format.encoder(), spec, Box::new(StdoutSink::new()))
+            .map_err(|e| format!("{e}"))?;
+        taps.push(tap_writer);
+    }
+    if let Some(path) = &opt.render {
+        let sink = FileSink::create(path).map_err(|e| format!("{}: {e}", path.display()))?;
+        let spec = tap_spec(opt, Tap::Post, sample_format);
+        let tap_writer = TapWriter::new(Tap::Post, opt.out_format.encoder(), spec, Box::new(sink))
+            .map_err(|e| format!("{e}"))?;
+        taps.push(tap_writer);
+    }
+
+    if let Some(addr) = opt.rtp {
+        let spec = header::StreamHeader {
+            sample_size: opt.rtp_format.sample_size() as u8,
+            float: false,
+            big_endian: true,
+            ..tap_spec(opt, Tap::Post, sample_format)
+        };
+        let frame_len = opt.channels as usize * spec.sample_size as usize / 8;
+        let packet_time = Duration::from_secs_f32(opt.rtp_ptime / 1000.0);
+        let sink = RtpSink::new(addr, opt.sample_rate, frame_len, packet_time)
+            .map_err(|e| format!("{e}"))?;
+        let tap_writer = TapWriter::new(Tap::Post, TapFormat::Raw.encoder(), spec, Box::new(sink))
+            .map_err(|e| format!("{e}"))?;
+        taps.push(tap_writer);
+    }
+
+    let frame_bits = opt.channels as u64 * opt.sample_size as u64;
+    let in_frames = opt.skip_frames.is_some() || opt.start.is_some() || opt.duration.is_some() || opt.loop_region.is_some();
+    if in_frames && !frame_bits.is_multiple_of(8) {
+        return Err(format!(
+            "--skip-frames, --start, --duration and --loop-region need frames of whole bytes, these are {frame_bits} bits, use --skip-bytes",
+        ));
+    }
+    let region = match opt.loop_region {
+        Some(region) => {
+            let (start, end) = (region.start.frames(opt.sample_rate), region.end.frames(opt.sample_rate));
+            if end <= start {
+                return Err(format!("The loop region ends at frame {end}, which is not after its start at frame {start}"));
+            }
+            opt.loop_passes.get_or_insert(None);
+            Some((start, end))
+        },
+        None => None,
+    };
+    let skip = match (opt.skip_bytes, opt.skip_frames, opt.start, region) {
+        (Some(bytes), _, _, _) => bytes,
+        (_, Some(skip_frames), _, _) => skip_frames * frame_bits / 8,
+        (_, _, Some(start), _) => start.frames(opt.sample_rate) * frame_bits / 8,
+        (_, _, _, Some((start, _))) => start * frame_bits / 8,
+        _ => 0,
+    };
+    let mut input = input;
+    if skip > 0 {
+        let skipped = io::copy(&mut (&mut input).take(skip), &mut io::sink()).map_err(|e| format!("{e}"))?;
+        if skipped < skip {
+            return Err(format!("Input ended after {skipped} of the {skip} bytes to skip"));
+        }
+    }
+    let limit = match (opt.duration, region) {
+        (Some(duration), _) => Some(duration.frames(opt.sample_rate) * frame_bits / 8),
+        (_, Some((start, end))) => Some((end - start) * frame_bits / 8),
+        _ => None,
+    };
+    let mut input: Box<dyn io::Read + Send> = match limit {
+        Some(limit) => Box::new(input.take(limit)),
+        None => input,
+    };
+
+    let mut reopen: Option<looper::Reopen> = seekable.clone().map(|(path, track)| -> looper::Reopen {
+        Box::new(move || {
+            let mut file = fs::File::open(&path)?;
+            if let Some((start, _)) = track {
+                file.seek(io::SeekFrom::Start(start))?;
+            }
+            let file = io::BufReader::new(file);
+            let file: Box<dyn io::Read + Send> = match track {
+                Some((_, Some(len))) => Box::new(file.take(len)),
+                _ => Box::new(file),
+            };
+            let (_, mut input) = container::detect(compress::decompress(file).map_err(io::Error::other)?)
+                .map_err(io::Error::other)?;
+            io::copy(&mut (&mut input).take(skip), &mut io::sink())?;
+            Ok(match limit {
+                Some(limit) => Box::new(input.take(limit)),
+                None => input,
+            })
+        })
+    });
+
+    if opt.reverse {
+        let Some((path, track)) = seekable else {
+            return Err("--reverse needs a regular file to read backwards".into());
+        };
+        if opt.delta {
+            return Err("--reverse can not play delta encoded samples, each one depends on the one before".into());
+        }
+        if !frame_bits.is_multiple_of(8) {
+            return Err(format!("--reverse needs frames of whole bytes, these are {frame_bits} bits"));
+        }
+        let (data_offset, data_len, silence_runs) = data_region.unwrap_or((0, None, false));
+        if silence_runs {
+            return Err("--reverse can not play runs of silence, convert the input with rplay --pre first".into());
+        }
+        let (base, track_len) = track.map_or((0, None), |(start, len)| (start, len));
+        let mut prefix = Vec::with_capacity(4);
+        fs::File::open(&path)
+            .and_then(|mut file| {
+                file.seek(io::SeekFrom::Start(base))?;
+                file.take(4).read_to_end(&mut prefix)
+            })
+            .map_err(|e| format!("{}: {e}", path.display()))?;
+        if compress::is_compressed(&prefix) {
+            return Err("--reverse can not read a compressed file backwards, decompress it first".into());
+        }
+
+        let start = base + data_offset + skip;
+        let end = [
+            data_len.map(|len| base + data_offset + len),
+            track_len.map(|len| base + len),
+            limit.map(|limit| start + limit),
+        ].into_iter().flatten().fold(u64::MAX, u64::min);
+        let frame_len = frame_bits as usize / 8;
+        let open = move || -> io::Result<Box<dyn io::Read + Send>> {
+            Ok(Box::new(reverse::Reverse::open(&path, start, end, frame_len)?))
+        };
+        input = open().map_err(|e| format!("{e}"))?;
+        reopen = Some(Box::new(open));
+    }
+
+    let input: Box<dyn io::Read + Send> = match opt.prebuffer {
+        _ if opt.preload => {
+            if opt.command.is_some() || opt.rtp_listen.is_some() || datagrams || opt.capture.is_some() {
+                return Err("--preload needs an input that ends, not a generator, datagrams or a capture".into());
+            }
+            let mut samples = Vec::new();
+            input.read_to_end(&mut samples).map_err(|e| format!("failed to preload the input: {e}"))?;
+            eprintln!("[!] preloaded {} bytes", samples.len());
+            let samples: Arc<[u8]> = samples.into();
+            let preloaded = samples.clone();
+            reopen = Some(Box::new(move || Ok(Box::new(io::Cursor::new(preloaded.clone())))));
+            Box::new(io::Cursor::new(samples))
+        },
+        Some(threshold) => {
+            Box::new(Prebuffer::fill(input, threshold.bytes(opt.sample_rate, frame_bits as usize)))
+        },
+        None => input,
+    };
+
+    let input: Box<dyn io::Read + Send> = match opt.loop_passes {
+        // the first pass of these would be kept in memory for as long as they play
+        Some(_) if opt.loop_region.is_none()
+            && (opt.command.is_some() || opt.rtp_listen.is_some() || datagrams || opt.capture.is_some()) => {
+            return Err("--loop needs an input that ends, not a generator, datagrams or a capture".into());
+        },
+        Some(passes) => {
+            let how = if reopen.is_some() { "rewinding the input" } else { "replaying the first pass from memory" };
+            match passes {
+                Some(passes) => eprintln!("[!] playing {passes} times, {how}"),
+                None => eprintln!("[!] looping until stopped, {how}"),
+            }
+            Box::new(looper::Looper::new(input, reopen, passes))
+        },
+        None => input,
+    };
+
+    let spec = header::StreamHeader {
+        sample_size: opt.sample_size as u8,
+        ..tap_spec(opt, Tap::Pre, sample_format)
+    };
+    let mixes = opt.mix.iter()
+        .map(|mix| mix.open(&spec).map(|input| (input, mix.gain)))
+        .collect::<Result<Vec<_>, _>>()?;
+
     Ok(ValidConfigOut {
         sample_format,
         sample_source: input,
-        sample_sink: output,
+        mixes,
+        taps,
     })
 }
 
+/// The frame of silence that fills gaps between datagrams, and how many bytes of them to buffer.
+fn datagram_buffer(opt: &Opt) -> Result<(Vec<u8>, usize), String> {
+    let spec = header::StreamHeader {
+        sample_rate: opt.sample_rate,
+        channels: opt.channels,
+        sample_size: opt.sample_size as u8,
+        float: opt.float,
+        unsigned: opt.unsigned || opt.encoding == Encoding::OffsetBinary,
+        big_endian: opt.be,
+        sign_magnitude: opt.encoding == Encoding::SignMagnitude,
+        silence_runs: false,
+    };
+    let silent_frame = spec.silent_frame()
+        .ok_or("Playing datagrams needs whole byte samples")?;
+    // up to a second of audio
+    let max_buffered = silent_frame.len() * opt.sample_rate as usize;
+    Ok((silent_frame, max_buffered))
+}
+
+/// Whether the input samples are float, unsigned or signed.
+fn sample_kind(opt: &Opt) -> &'static str {
+    match (opt.float, opt.unsigned) {
+        (true, _) => "float",
+        (false, true) => "unsigned",
+        (false, false) => "signed",
+    }
+}
+
+/// Prints the input format as it will be played.
+fn print_config(opt: &Opt, sample_format: cpal::SampleFormat) {
+    let kind = sample_kind(opt);
+    let byte_order = if opt.be { "big-endian" } else { "little-endian" };
+    println!("sample rate: {}Hz", opt.sample_rate);
+    println!("sample size: {} bit {kind}, {byte_order}, read as {sample_format}", opt.sample_size);
+    if let Some(q) = opt.fixed {
+        println!("fixed point: q{}.{}", q.int_bits, q.frac_bits);
+    }
+    println!("encoding:    {:?}{}", opt.encoding, if opt.delta { ", delta" } else { "" });
+    println!("channels:    {}", opt.channels);
+    for c in 0..opt.channels as usize {
+        let name = opt.channel_names.get(c).map_or(String::new(), |n| format!(" {n}"));
+        let muted = match opt.soloed {
+            Some(solo) if solo == c => " (solo, on every channel)",
+            _ if opt.muted[c] => " (muted)",
+            _ => "",
+        };
+        let gain = opt.channel_gains.get(c).map_or(String::new(), |g| format!(" (gain {g})"));
+        println!("    {c}:{name}{muted}{gain}");
+    }
+    println!("gain:        {}", opt.gain);
+    if let Some(kind) = opt.resampler {
+        let latency = kind.build(opt.channels as usize, opt.sample_rate, opt.sample_rate).latency();
+        println!("resampler:   {kind:?}, {latency} frames of latency");
+    }
+}
+
+/// Describes the inputs of a run, hashing local files.
+fn manifest_inputs(opt: &Opt) -> Vec<Json> {
+    let mut inputs = main_manifest_inputs(opt);
+    inputs.extend(opt.mix.iter().map(|mix| match manifest::describe_file(&mix.path) {
+        Json::Object(mut fields) => {
+            fields.push(("mix gain".into(), mix.gain.into()));
+            Json::Object(fields)
+        },
+        described => described,
+    }));
+    inputs
+}
+
+/// Describes the input that is played, and the entries of a playlist.
+fn main_manifest_inputs(opt: &Opt) -> Vec<Json> {
+    let network = |kind: &str, address: String| Json::object([("network", kind.into()), ("address", address.into())]);
+    if let Some(command) = &opt.command {
+        vec![Json::object([("generator", format!("{command:?}").into())])]
+    } else if let Some(addr) = opt.rtp_listen {
+        vec![network("rtp", addr.to_string())]
+    } else if let Some(addr) = opt.listen {
+        vec![network("tcp listen", addr.to_string())]
+    } else if let Some(addr) = opt.udp {
+        vec![network("udp", addr.to_string())]
+    } else if opt.socket_activation {
+        vec![network("systemd socket", std::env::var("LISTEN_FDNAMES").unwrap_or_default())]
+    } else if let Some(name) = &opt.capture {
+        vec![Json::object([("capture", name.as_deref().unwrap_or("default").into())])]
+    } else if let Some(path) = &opt.serial {
+        vec![Json::object([("serial", path.display().to_string().into()), ("baud", opt.baud.into())])]
+    } else if let Some(addr) = opt.connect {
+        vec![network("tcp connect", addr.to_string())]
+    } else if let Some(path) = &opt.listen_unix {
+        vec![network("unix listen", path.display().to_string())]
+    } else if let (Some(left), Some(right)) = (&opt.left, &opt.right) {
+        [(left, "left"), (right, "right")].into_iter().map(|(path, channel)| match manifest::describe_file(path) {
+            Json::Object(mut fields) => {
+                fields.push(("channel".into(), channel.into()));
+                Json::Object(fields)
+            },
+            described => described,
+        }).collect()
+    } else if let Some(url) = opt.infile.as_deref().filter(|f| http::is_url(f)) {
+        vec![Json::object([("url", url.into())])]
+    } else if let Some(infile) = &opt.infile {
+        let mut inputs = vec![manifest::describe_file(Path::new(infile))];
+        if playlist::is_playlist(infile) {
+            for entry in playlist::entries(Path::new(infile)).unwrap_or_default() {
+                inputs.push(if http::is_url(&entry) {
+                    Json::object([("url", entry.into())])
+                } else {
+                    manifest::describe_file(Path::new(&entry))
+                });
+            }
+        }
+        inputs
+    } else {
+        vec![Json::object([("stdin", true.into())])]
+    }
+}
+
+/// Describes a run so that it can be reproduced: the command line, the input format, the processing
+/// and the output it is played on, `device` at the channels, rate and sample format of `output`,
+/// and the inputs.
+fn manifest(
+    opt: &Opt,
+    command_line: Vec<String>,
+    sample_format: cpal::SampleFormat,
+    device: Option<&cpal::Device>,
+    output: Option<(u16, u32, cpal::SampleFormat)>,
+) -> Json {
+    let created = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    // without a device, the pipeline writes f32 at the input's channels and rate
+    let (channels, sample_rate, output_format) = output.unwrap_or((opt.channels, opt.sample_rate, cpal::SampleFormat::F32));
+    let routes = opt.routes.iter()
+        .map(|route| Json::object([("from", route.from.into()), ("to", route.to.into()), ("gain", route.gain.into())]))
+        .collect::<Vec<_>>();
+    let passes = match opt.loop_passes {
+        None => 1u32.into(),
+        Some(None) => "forever".into(),
+        Some(Some(passes)) => passes.into(),
+    };
+    let ultrasonic = !opt.allow_ultrasonic && UltrasonicGuard::new(opt.sample_rate, 1).is_some();
+    Json::object([
+        ("software", Json::object([("name", env!("CARGO_PKG_NAME").into()), ("version", env!("CARGO_PKG_VERSION").into())])),
+        ("created", created.into()),
+        ("command_line", command_line.into()),
+        ("format", Json::object([
+            ("sample_rate", opt.sample_rate.into()),
+            ("sample_size", opt.sample_size.into()),
+            ("kind", sample_kind(opt).into()),
+            ("byte_order", if opt.be { "big-endian" } else { "little-endian" }.into()),
+            ("read_as", sample_format.to_string().into()),
+            ("fixed", opt.fixed.map(|q| format!("q{}.{}", q.int_bits, q.frac_bits)).into()),
+            ("encoding", format!("{:?}", opt.encoding).into()),
+            ("delta", opt.delta.into()),
+            ("channels", opt.channels.into()),
+            ("channel_names", opt.channel_names.clone().into()),
+        ])),
+        ("processing", Json::object([
+            ("precision", format!("{:?}", opt.precision).into()),
+            ("gain", opt.gain.into()),
+            ("muted_channels", opt.muted.clone().into()),
+            ("solo_on_every_channel", opt.soloed.into()),
+            ("channel_gains", opt.channel_gains.clone().into()),
+            ("channel_shift", opt.channel_shift.iter().map(|s| format!("{s:?}")).collect::<Vec<_>>().into()),
+            ("subsonic_high_pass_hz", (!opt.allow_subsonic).then_some(filter::SUBSONIC).into()),
+            ("ultrasonic_guard_hz", ultrasonic.then_some(filter::ULTRASONIC).into()),
+            ("resampler", opt.resampler.map(|kind| format!("{kind:?}")).into()),
+            ("routes", routes.into()),
+            ("upmix", opt.upmix.into()),
+            ("downmix", opt.downmix.into()),
+        ])),
+        ("range", Json::object([
+            ("skip_bytes", opt.skip_bytes.into()),
+            ("skip_frames", opt.skip_frames.into()),
+            ("start_seconds", opt.start.map(|start| start.duration(opt.sample_rate).as_secs_f64()).into()),
+            ("duration_seconds", opt.duration.map(|duration| duration.duration(opt.sample_rate).as_secs_f64()).into()),
+            ("loop_passes", passes),
+            ("loop_region_frames", opt.loop_region.map(|region| vec![
+                region.start.frames(opt.sample_rate),
+                region.end.frames(opt.sample_rate),
+            ]).into()),
+            ("reverse", opt.reverse.into()),
+            ("playlist_repeat", opt.repeat.map(|repeat| format!("{repeat:?}")).into()),
+        ])),
+        ("output", Json::object([
+            ("device", device.map(|device| device.name().unwrap_or_default()).into()),
+            ("clock", format!("{:?}", opt.clock).into()),
+            ("channels", channels.into()),
+            ("sample_rate", sample_rate.into()),
+            ("sample_format", output_format.to_string().into()),
+            ("dither", (output_format.sample_size() <= 2).then(|| format!("{:?}", opt.dither)).into()),
+        ])),
+        ("inputs", Json::Array(manifest_inputs(opt))),
+    ])
+}
+
+/// Describes the samples a tap writes.
+fn tap_spec(opt: &Opt, tap: Tap, sample_format: cpal::SampleFormat) -> header::StreamHeader {
+    match tap {
+        Tap::Pre => header::StreamHeader {
+            sample_rate: opt.sample_rate,
+            channels: opt.channels,
+            sample_size: (sample_format.sample_size() * 8) as u8,
+            float: opt.float,
+            unsigned: opt.unsigned,
+            big_endian: opt.be,
+            sign_magnitude: opt.encoding == Encoding::SignMagnitude,
+            silence_runs: false,
+        },
+        Tap::Post => header::StreamHeader {
+            sample_rate: opt.sample_rate,
+            channels: opt.channels,
+            sample_size: match opt.precision {
+                Precision::F32 => 32,
+                Precision::F64 => 64,
+            },
+            float: true,
+            unsigned: false,
+            big_endian: opt.be,
+            sign_magnitude: false,
+            silence_runs: false,
+        },
+    }
+}
+
 fn main() {
-    let mut opt = Opt::parse();
-    let result = config_sanity_check(&mut opt);
+    let mut args = std::env::args_os().collect::<Vec<_>>();
+    let command_line = args.iter().map(|arg| arg.to_string_lossy().into_owned()).collect::<Vec<_>>();
+    if args.first().is_some_and(control::invoked_as_rplayctl) {
+        if let Err(msg) = control::rplayctl(args) {
+            eprintln!("{msg}");
+            process::exit(1);
+        }
+        return;
+    }
+    if args.first().is_some_and(aplay::invoked_as_aplay) {
+        args = aplay::translate(args).unwrap_or_else(|msg| {
+            eprintln!("{msg}");
+            process::exit(1);
+        });
+    }
+    let zone_args = args.clone();
+    let matches = Opt::command().get_matches_from(args);
+    let mut opt = Opt::from_arg_matches(&matches)
+        .unwrap_or_else(|e| e.exit());
+
+    if opt.list_devices {
+        if let Err(msg) = devices::list() {
+            eprintln!("{msg}");
+            process::exit(1);
+        }
+        return;
+    }
+
+    if !opt.zone.is_empty() {
+        if opt.command.is_some() {
+            eprintln!("--zone plays files, it can not be combined with a subcommand");
+            process::exit(1);
+        }
+        match zone::run(&opt.zone, &zone_args) {
+            Ok(code) => process::exit(code),
+            Err(msg) => {
+                eprintln!("{msg}");
+                process::exit(1);
+            },
+        }
+    }
+
+    // tape-check reads its file like the input to play, then analyses it instead
+    let tape_check = match opt.command.take_if(|c| matches!(c, Command::TapeCheck { .. })) {
+        Some(Command::TapeCheck { file, pilot }) => {
+            if opt.infile.is_some() {
+                eprintln!("tape-check takes the file to analyse as its argument, not '--infile'");
+                process::exit(1);
+            }
+            opt.infile = Some(file.display().to_string());
+            opt.clock = Clock::Timer;
+            Some(pilot)
+        },
+        _ => None,
+    };
+
+    // a render runs the pipeline as fast as it goes, without a device
+    if opt.render.is_some() {
+        opt.clock = Clock::Free;
+    }
+    // record opens an input device alone
+    if matches!(opt.command, Some(Command::Record { .. })) {
+        opt.clock = Clock::Timer;
+    }
+
+    // open the device while the input is probed, on some hosts it takes longer than a short sound plays
+    let selector = opt.device.first().cloned();
+    let jack_name = opt.jack_name.clone()
+        .or_else(|| (!opt.jack_connect.is_empty()).then(|| "rplay".into()));
+    let connect_system = opt.jack_connect.is_empty();
+    let device_thread = (opt.clock == Clock::Device).then(|| thread::spawn(move || match jack_name {
+        Some(name) => jack::open_output(&name, connect_system),
+        None => devices::open_output(selector.as_deref()),
+    }));
+
+    let result = config_sanity_check(&mut opt, &matches);
     if let Err(msg) = result {
         eprintln!("{msg}");
         process::exit(1);
     }
-    let ValidConfigOut { sample_format, sample_source, sample_sink, } = result.unwrap();
+    let ValidConfigOut { sample_format, sample_source, mixes, taps, } = result.unwrap();
     let input = sample_source;
-    let output = sample_sink;
 
-    let host = cpal::default_host();
-    let device = host.default_output_device()
-        .expect("failed to find output device");
+    if let Some(pilot) = tape_check {
+        if let Err(msg) = tape_check_format(sample_format, &opt, input, pilot) {
+            eprintln!("{msg}");
+            process::exit(1);
+        }
+        return;
+    }
+    if let Some(Command::Record { file, from, duration }) = opt.command.clone() {
+        let duration = duration.map(|duration| duration.duration(opt.sample_rate));
+        if let Err(msg) = record_to(&opt, &file, from.as_deref(), duration) {
+            eprintln!("{msg}");
+            process::exit(1);
+        }
+        return;
+    }
+
+    let device = device_thread.map(|device_thread| {
+        device_thread.join()
+            .expect("device thread panicked")
+            .unwrap_or_else(|msg| {
+                eprintln!("{msg}");
+                process::exit(1);
+            })
+    });
+    // the channels, rate and sample format the device is opened at
+    let output = device.as_ref().map(|(device, default)| {
+        // enough channels for the highest one mapped to
+        let wanted = match opt.routes.iter().map(|route| route.to as u16 + 1).max() {
+            Some(wanted) => wanted,
+            None if opt.upmix && opt.channels == 1 => default.channels(),
+            None => opt.channels,
+        };
+        let (channels, rate) = devices::negotiate(device, wanted, opt.sample_rate);
+        let rate = if opt.resampler.is_some() { default.sample_rate().0 } else { rate };
+        (channels, rate, devices::output_format(device, channels, rate))
+    });
+    // an input rate the device does not play is resampled, settled before the manifest records
+    // it. Measure, loopcheck and punch open the device at the input rate themselves
+    let opens_device = matches!(opt.command, Some(Command::Measure { .. } | Command::Loopcheck { .. } | Command::Punch { .. }));
+    if let (Some((device, _)), Some((_, rate, _))) = (&device, output)
+        && opt.resampler.is_none()
+        && rate != opt.sample_rate
+        && !opens_device
+    {
+        let name = device.name().unwrap_or_else(|_| "the output device".into());
+        if opt.bit_perfect {
+            eprintln!("{name} does not play {}Hz, and --bit-perfect does not resample", opt.sample_rate);
+            process::exit(1);
+        }
+        eprintln!("[!] {name} does not play {}Hz, resampling to {rate}Hz, --resampler picks how", opt.sample_rate);
+        opt.resampler = Some(ResamplerKind::Sinc);
+    }
+    // without a device, the pipeline writes f32 like the callbacks of one
+    let output_format = output.map_or(cpal::SampleFormat::F32, |(_, _, format)| format);
+
+    if let Some(path) = &opt.manifest {
+        let manifest = manifest(&opt, command_line, sample_format, device.as_ref().map(|(device, _)| device), output);
+        if let Err(msg) = manifest::write(path, &manifest) {
+            eprintln!("{msg}");
+            process::exit(1);
+        }
+    }
+
+    if let Some(Command::Measure { stimulus, duration, record, stimulus_out }) = opt.command.clone()
+        && let Some((device, _)) = &device
+    {
+        let stimulus_out = stimulus_out.unwrap_or_else(|| record.with_extension("stimulus.raw"));
+        let result = stimulus.samples(opt.sample_rate, duration.duration(opt.sample_rate)).and_then(|samples| {
+            measure::run(device, opt.sample_rate, opt.channels, opt.gain, &samples, &record, &stimulus_out)
+        });
+        if let Err(msg) = result {
+            eprintln!("{msg}");
+            process::exit(1);
+        }
+        return;
+    }
+    if let Some(Command::Loopcheck { duration }) = opt.command
+        && let Some((device, _)) = &device
+    {
+        if let Err(msg) = loopcheck::run(device, opt.sample_rate, opt.channels, opt.gain, duration.duration(opt.sample_rate)) {
+            eprintln!("{msg}");
+            process::exit(1);
+        }
+        return;
+    }
+    if let Some(Command::Punch { take, punch_in, punch_out, pre_roll }) = opt.command.clone()
+        && let Some((device, _)) = &device
+    {
+        if let Err(msg) = punch::run(device, opt.sample_rate, opt.channels, opt.gain, &take, punch_in..punch_out, pre_roll) {
+            eprintln!("{msg}");
+            process::exit(1);
+        }
+        return;
+    }
+
+    if let Some(on_busy) = opt.on_busy
+        && let Some((device, _)) = &device
+    {
+        let name = device.name().unwrap_or_else(|_| "default".into());
+        if let Err(msg) = busy::claim(&name, on_busy, opt.output_control.clone()) {
+            eprintln!("{msg}");
+            process::exit(1);
+        }
+    }
 
     let channels = opt.channels;
     let sample_rate = cpal::SampleRate(opt.sample_rate);
@@ -193,115 +2012,516 @@ fn main() {
     );
     let iconfig = iconfig_s.config();
 
-    let oconfig = device.default_output_config().unwrap();
+    let (output_channels, output_rate) = match (&device, output) {
+        (Some((device, default)), Some((channels, rate, _))) => {
+            let name = device.name().unwrap_or_else(|_| "the output device".into());
+            if let Some(frames) = opt.buffer_frames
+                && let cpal::SupportedBufferSize::Range { min, max } = *default.buffer_size()
+                && !(min..=max).contains(&frames)
+            {
+                let clamped = frames.clamp(min, max);
+                eprintln!("[!] {name} takes buffers of {min}-{max} frames, using {clamped} instead of {frames}");
+                opt.buffer_frames = Some(clamped);
+            }
+            match (iconfig.channels, channels) {
+                _ if !opt.routes.is_empty() => {
+                    if let Some(to) = opt.routes.iter().map(|route| route.to).find(|&to| to >= channels as usize) {
+                        eprintln!("{name} has {channels} channels, there is no channel {to} to map to");
+                        process::exit(1);
+                    }
+                },
+                (input, output) if input == output => (),
+                (1, output) if opt.upmix => eprintln!("[!] playing the mono input on all {output} channels of {name}"),
+                (1, output) => eprintln!("[!] {name} does not play 1 channel, playing it on all {output}"),
+                (input, output) if input > output => {
+                    eprintln!("[!] {name} does not play {input} channels, playing the first {output}");
+                },
+                (input, output) => eprintln!("[!] {name} does not play {input} channels, playing them on the first {input} of {output}"),
+            }
+            (channels, cpal::SampleRate(rate))
+        },
+        _ => (iconfig.channels, iconfig.sample_rate),
+    };
     let oconfig = cpal::SupportedStreamConfig::new(
-        iconfig.channels,
-        iconfig.sample_rate,
+        output_channels,
+        output_rate,
         cpal::SupportedBufferSize::Unknown,
-        oconfig.sample_format(),
+        output_format,
     );
 
     let iformat = iconfig_s.sample_format();
+    let device = device.as_ref().map(|(device, _)| device);
+    match opt.precision {
+        Precision::F32 => run_format::<f32>(iformat, device, &oconfig, opt, input, mixes, taps),
+        Precision::F64 => run_format::<f64>(iformat, device, &oconfig, opt, input, mixes, taps),
+    }.unwrap();
+}
+
+/// Floating point type that samples are converted to and processed in.
+trait ProcessSample:
+    dasp_sample::FloatSample + ToBytes + Send + 'static
+    + dasp_sample::FromSample<i8>  + dasp_sample::FromSample<u8>
+    + dasp_sample::FromSample<i16> + dasp_sample::FromSample<u16>
+    + dasp_sample::FromSample<i32> + dasp_sample::FromSample<u32>
+    + dasp_sample::FromSample<i64> + dasp_sample::FromSample<u64>
+    + dasp_sample::ToSample<i16> + dasp_sample::ToSample<i32> {}
+
+impl ProcessSample for f32 {}
+impl ProcessSample for f64 {}
+
+/// Monomorphizes [run] for the input sample format.
+fn run_format<P: ProcessSample>(
+    iformat: cpal::SampleFormat,
+    device: Option<&cpal::Device>,
+    oconfig: &cpal::SupportedStreamConfig,
+    opt: Opt,
+    input: Box<dyn io::Read + Send>,
+    mixes: Vec<(Box<dyn io::Read + Send>, f32)>,
+    taps: Vec<TapWriter>,
+) -> Result<(), Box<dyn Error>> {
     match iformat {
-        cpal::SampleFormat::I8  => run::< i8>(&device, &oconfig.into(), opt, input, output),
-        cpal::SampleFormat::U8  => run::< u8>(&device, &oconfig.into(), opt, input, output),
+        cpal::SampleFormat::I8  => run::< i8, P>(device, oconfig, opt, input, mixes, taps),
+        cpal::SampleFormat::U8  => run::< u8, P>(device, oconfig, opt, input, mixes, taps),
 
-        cpal::SampleFormat::I16 => run::<i16>(&device, &oconfig.into(), opt, input, output),
-        cpal::SampleFormat::U16 => run::<u16>(&device, &oconfig.into(), opt, input, output),
+        cpal::SampleFormat::I16 => run::<i16, P>(device, oconfig, opt, input, mixes, taps),
+        cpal::SampleFormat::U16 => run::<u16, P>(device, oconfig, opt, input, mixes, taps),
 
-        cpal::SampleFormat::I32 => run::<i32>(&device, &oconfig.into(), opt, input, output),
-        cpal::SampleFormat::U32 => run::<u32>(&device, &oconfig.into(), opt, input, output),
+        cpal::SampleFormat::I32 => run::<i32, P>(device, oconfig, opt, input, mixes, taps),
+        cpal::SampleFormat::U32 => run::<u32, P>(device, oconfig, opt, input, mixes, taps),
 
-        cpal::SampleFormat::I64 => run::<i64>(&device, &oconfig.into(), opt, input, output),
-        cpal::SampleFormat::U64 => run::<u64>(&device, &oconfig.into(), opt, input, output),
+        cpal::SampleFormat::I64 => run::<i64, P>(device, oconfig, opt, input, mixes, taps),
+        cpal::SampleFormat::U64 => run::<u64, P>(device, oconfig, opt, input, mixes, taps),
 
-        cpal::SampleFormat::F32 => run::<f32>(&device, &oconfig.into(), opt, input, output),
-        cpal::SampleFormat::F64 => run::<f64>(&device, &oconfig.into(), opt, input, output),
+        cpal::SampleFormat::F32 => run::<f32, P>(device, oconfig, opt, input, mixes, taps),
+        cpal::SampleFormat::F64 => run::<f64, P>(device, oconfig, opt, input, mixes, taps),
         sample_format => panic!("Unsupported sample format '{sample_format}'"),
-    }.unwrap();
+    }
 }
 
-fn run<I>(
-    device: &cpal::Device,
-    oconfig: &cpal::StreamConfig,
+/// Exit code after SIGINT or SIGTERM, as a shell reports a command killed by SIGINT.
+const INTERRUPTED: i32 = 130;
+/// How often the end of playback checks for SIGINT and SIGTERM.
+const INTERRUPT_POLL: Duration = Duration::from_millis(100);
+/// How long an interrupted playback has to finish its taps before it exits without them.
+const INTERRUPT_GRACE: Duration = Duration::from_secs(2);
+
+fn run<I, P>(
+    device: Option<&cpal::Device>,
+    oconfig: &cpal::SupportedStreamConfig,
     opt: Opt,
     input: Box<dyn io::Read + Send>,
-    output: Option<Box<dyn io::Write + Send>>,
+    mixes: Vec<(Box<dyn io::Read + Send>, f32)>,
+    taps: Vec<TapWriter>,
 ) -> Result<(), Box<dyn Error>> 
 where 
-  I: cpal::SizedSample + dasp_sample::ToSample<f32> + FromBytes + ToBytes {
-    let mut bitreader = BitReader::new(input, opt.be);
-    let mut bitwriter = None;
-    if let Some(output) = output {
-        bitwriter = Some(BitWriter::new(output, opt.be));
-    }
-
-    let mut next_sample = move || -> I {
-        bitreader.read()
-            .inspect_err(|_| process::exit(1))
-            .unwrap()
+  I: cpal::SizedSample + dasp_sample::ToSample<P> + FromBytes + FromPacked + ToBytes + WrappingAdd,
+  P: ProcessSample {
+    let output_format = oconfig.sample_format();
+    let oconfig = &cpal::StreamConfig {
+        buffer_size: opt.buffer_frames.map_or(cpal::BufferSize::Default, cpal::BufferSize::Fixed),
+        ..oconfig.config()
     };
+    let mut taps = taps;
+    let sample_size = opt.sample_size;
+    let mut next_sample = sample_reader::<I>(input, &opt, opt.channels as usize);
+    let mut mixes = mixes.into_iter()
+        .map(|(input, gain)| (sample_reader::<I>(input, &opt, opt.channels as usize), P::from_sample(gain)))
+        .collect::<Vec<_>>();
 
-    let err_fn = move |err| {
-        eprintln!("an error occurred on stream: {}", err)
+    let sign_magnitude = opt.encoding == Encoding::SignMagnitude;
+    let gain = opt.gain * opt.fixed.map_or(1.0, |q| q.scale(sample_size));
+    let gain = P::from_sample(gain);
+    let convert = move |sample: I| -> P {
+        let mut value = sample.to_sample::<P>();
+        // with the sign bit set, two's complement puts the magnitude bits at an offset of -1.0
+        if sign_magnitude && value < P::EQUILIBRIUM {
+            value = -value.add_amp(<P as Sample>::IDENTITY);
+        }
+        value.mul_amp(gain)
+    };
+    let mut process_sample = move |sample: I| -> P {
+        let mut value = convert(sample);
+        mixes.retain_mut(|(next_sample, gain)| match next_sample() {
+            Ok(sample) => {
+                value = value.add_amp(convert(sample).mul_amp(*gain));
+                true
+            },
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => false,
+            Err(e) => {
+                eprintln!("[!] dropped a mixed input, {e}");
+                false
+            },
+        });
+        value
     };
 
-    let pre_out = opt.pre_out;
-    let post_out = opt.post_out;
-    let gain = opt.gain;
-    let channels = oconfig.channels as usize;
+    let channels = opt.channels as usize;
+    let device_channels = oconfig.channels as usize;
+    let mut shifter = Shifter::new(channels, &opt.channel_shift);
+    let mut detector = if opt.channel_shift.is_empty() && channels > 1 {
+        Some(ShiftDetector::new(channels, opt.sample_rate as usize))
+    } else {
+        None
+    };
+    let mut clicks = opt.clicks.map(|action| ClickDetector::new(opt.sample_rate, channels, action, opt.output_control.clone()));
+    let muted = opt.muted.clone();
+    let soloed = opt.soloed;
+    let channel_gains = opt.channel_gains.iter().map(|&gain| P::from_sample(gain)).collect::<Vec<_>>();
+    let mut ultrasonic = if opt.allow_ultrasonic {
+        None
+    } else {
+        UltrasonicGuard::new(opt.sample_rate, channels)
+    };
+    let mut subsonic = (!opt.allow_subsonic).then(|| SubsonicFilter::new(opt.sample_rate, channels));
+    let output_control = opt.output_control.clone();
+    let mut output_gain = GainRamp::new(output_control.clone(), opt.sample_rate);
+    let mut process_frame = move |frame: &mut [P]| {
+        if let Some(detector) = &mut detector {
+            detector.observe(frame);
+        }
+        if let Some(clicks) = &mut clicks {
+            clicks.observe(frame);
+        }
+        shifter.process(frame);
+        for (sample, _) in frame.iter_mut().zip(&muted).filter(|(_, muted)| **muted) {
+            *sample = P::EQUILIBRIUM;
+        }
+        for (sample, &gain) in frame.iter_mut().zip(&channel_gains) {
+            *sample = sample.mul_amp(gain);
+        }
+        // after the gains, so the soloed channel carries its own gain to every channel
+        if let Some(solo) = soloed {
+            let value = frame[solo];
+            frame.fill(value);
+        }
+        if subsonic.is_some() || ultrasonic.is_some() {
+            for (channel, sample) in frame.iter_mut().enumerate() {
+                let mut value = sample.to_sample::<f64>();
+                if let Some(filter) = &mut subsonic {
+                    value = filter.process(channel, value);
+                }
+                if let Some(guard) = &mut ultrasonic {
+                    value = guard.process(channel, value);
+                }
+                *sample = P::from_sample(value);
+            }
+        }
+        let gain = P::from_sample(output_gain.next_gain());
+        for sample in frame.iter_mut() {
+            *sample = sample.mul_amp(gain);
+        }
+    };
 
-    let stream = device.build_output_stream(
-        oconfig,
-        move |data: &mut [f32], _: &cpal::OutputCallbackInfo|{
-            write_data(
-                data, channels, gain, 
-                &mut next_sample, 
-                pre_out, post_out, 
-                &mut bitwriter,
+    let mut resampler = match opt.resampler {
+        Some(kind) if oconfig.sample_rate.0 != opt.sample_rate => {
+            let resampler = kind.build(channels, opt.sample_rate, oconfig.sample_rate.0);
+            eprintln!(
+                "[!] resampling {} Hz to {} Hz with {kind:?}, {} frames of latency",
+                opt.sample_rate, oconfig.sample_rate.0, resampler.latency(),
             );
+            Some(resampler)
         },
-        err_fn,
-        None,
-    )?;
-    stream.play()?;
+        _ => None,
+    };
+
+    let input_name = match &opt.command {
+        Some(command) => format!("{command:?}"),
+        None => opt.infile.clone().unwrap_or_else(|| "-".into()),
+    };
+    let render = opt.render.is_some();
+    let mut hooks = Hooks::new(opt.on_finish.clone(), opt.on_error.clone(), opt.on_clip.clone(), input_name, oconfig.sample_rate.0);
+
+    // capture starts first, so that it covers the start of playback
+    let recorder = match (&opt.record_response, device) {
+        (Some(path), Some(_)) => Some(Arc::new(Recorder::start(path, oconfig.sample_rate.0)?)),
+        _ => None,
+    };
+    // the audio callback sees the input end, and keeps playing silence while this thread
+    // finishes the taps, records the tail of the response, runs the hook and exits
+    let (ended_tx, ended) = mpsc::sync_channel::<(i32, Option<Hook>, Vec<TapWriter>)>(1);
+    // taps are finished on SIGINT and SIGTERM too, WAV and AU headers are written last
+    let catch = !taps.is_empty();
+    if catch {
+        interrupt::catch();
+    }
+    {
+        let recorder = recorder.clone();
+        thread::spawn(move || {
+            let mut interrupted_at = None;
+            let (mut code, hook, taps) = loop {
+                match ended.recv_timeout(INTERRUPT_POLL) {
+                    Ok(end) => break end,
+                    Err(mpsc::RecvTimeoutError::Disconnected) => return,
+                    // the callback ends playback unless it is stuck waiting for input
+                    Err(mpsc::RecvTimeoutError::Timeout) if catch && interrupt::caught() => {
+                        if interrupted_at.get_or_insert_with(Instant::now).elapsed() > INTERRUPT_GRACE {
+                            eprintln!("[!] interrupted while waiting for input, the output is not finished");
+                            process::exit(INTERRUPTED);
+                        }
+                    },
+                    Err(mpsc::RecvTimeoutError::Timeout) => (),
+                }
+            };
+            for mut tap in taps {
+                if let Err(e) = tap.finish() {
+                    eprintln!("[!] could not finish the {:?} tap, {e}", tap.tap);
+                    code = code.max(1);
+                }
+            }
+            if let Some(recorder) = &recorder {
+                recorder.finish();
+            }
+            if let Some(hook) = hook {
+                hook.run();
+            }
+            process::exit(code);
+        });
+    }
+    let mut ended = false;
 
-    std::thread::park();
+    let mut frame_buf = vec![P::EQUILIBRIUM; channels];
+    // the input's frames, before they are fit or routed to the device's channels
+    let mut unfit = Vec::new();
+    // without a device there are no device channels to map to
+    let routes = if device.is_some() { opt.routes.clone() } else { Vec::new() };
+    let fill = move |data: &mut [f32]| {
+        // paused and faded out, the input waits where it is
+        if ended || output_control.holds_input() {
+            data.fill(0.0);
+            return;
+        }
+        let frames = data.len() / device_channels;
+        let output = if routes.is_empty() && device_channels == channels {
+            &mut *data
+        } else {
+            unfit.resize(frames * channels, 0.0);
+            &mut unfit[..]
+        };
+        let interrupted = catch && interrupt::caught();
+        let result = if interrupted {
+            Err(io::ErrorKind::Interrupted.into())
+        } else {
+            write_data(
+                output,
+                resampler.as_mut(),
+                &mut next_sample,
+                &mut process_sample,
+                &mut process_frame,
+                &mut frame_buf,
+                &mut taps,
+            )
+        };
+        if let Err(e) = result {
+            // a render that reached the end of its input is done
+            let rendered = render && e.kind() == io::ErrorKind::UnexpectedEof;
+            let (code, hook) = if interrupted {
+                (INTERRUPTED, None)
+            } else {
+                (if rendered { 0 } else { 1 }, hooks.ended(&e))
+            };
+            let _ = ended_tx.try_send((code, hook, std::mem::take(&mut taps)));
+            ended = true;
+            data.fill(0.0);
+            return;
+        }
+        if !routes.is_empty() {
+            for (input, output) in unfit.chunks(channels).zip(data.chunks_mut(device_channels)) {
+                channel::route_frame(input, output, &routes);
+            }
+        } else if device_channels != channels {
+            for (input, output) in unfit.chunks(channels).zip(data.chunks_mut(device_channels)) {
+                channel::fit_frame(input, output);
+            }
+        }
+        let clipped = if hooks.watches_clipping() {
+            data.iter().filter(|s| s.abs() > 1.0).count()
+        } else {
+            0
+        };
+        hooks.played(frames, clipped);
+    };
 
+    let Some(device) = device else {
+        clock::drive(opt.clock, oconfig.sample_rate.0, channels, fill);
+    };
+    // the first device sets the pace, the others are given a copy of what it plays
+    let (mut fanout, _streams) = Fanout::open(opt.device.get(1..).unwrap_or_default(), oconfig, opt.dither)?;
+    let mut fill = fill;
+    let control = opt.output_control.clone();
+    let sample_rate = oconfig.sample_rate.0;
+    let mut reported = false;
+    let fill = move |data: &mut [f32], info: &cpal::OutputCallbackInfo| {
+        let frames = data.len() / device_channels;
+        // how long the first frame of this buffer waits before it plays
+        let timestamp = info.timestamp();
+        let latency = timestamp.playback.duration_since(&timestamp.callback).unwrap_or_default();
+        control.set_latency(frames, latency);
+        if let Some(recorder) = &recorder {
+            recorder.played(info);
+        }
+        if !reported {
+            let buffer = frames as f64 * 1000.0 / sample_rate as f64;
+            eprintln!("[!] device buffer {frames} frames ({buffer:.1} ms), output latency {:.1} ms", latency.as_secs_f64() * 1000.0);
+            reported = true;
+        }
+        fill(data);
+        fanout.push(data);
+    };
+    if opt.auto_buffer {
+        autobuffer::play(device, oconfig, output_format, opt.dither, fill)?;
+        return Ok(());
+    }
+    if !opt.jack_connect.is_empty() {
+        jack::connect(opt.jack_name.as_deref().unwrap_or("rplay"), &opt.jack_connect);
+    }
+    reconnect::play(
+        device,
+        oconfig,
+        output_format,
+        opt.dither,
+        opt.device.first().map(String::as_str),
+        opt.reconnect_wait,
+        fill,
+    )?;
     Ok(())
 }
 
-fn write_data<I>(
+/// Reads the samples of `input` one at a time, undoing any delta encoding.
+fn sample_reader<I>(input: Box<dyn io::Read + Send>, opt: &Opt, channels: usize) -> impl FnMut() -> io::Result<I> + Send + use<I>
+where
+  I: cpal::SizedSample + FromBytes + FromPacked + WrappingAdd {
+    let mut bitreader = BitReader::new(input, opt.be);
+    let mut delta = opt.delta.then(|| DeltaDecoder::new(channels, I::EQUILIBRIUM));
+    let sample_size = opt.sample_size;
+    let is_packed = sample_size as usize != I::SIZE * 8;
+    move || {
+        let sample = if is_packed {
+            bitreader.read_packed(sample_size)?
+        } else {
+            bitreader.read()?
+        };
+        Ok(match &mut delta {
+            Some(delta) => delta.decode(sample),
+            None => sample,
+        })
+    }
+}
+
+/// Records an input device to `file` in the format of the format flags.
+fn record_to(opt: &Opt, file: &Path, from: Option<&str>, duration: Option<Duration>) -> Result<(), String> {
+    if opt.encoding == Encoding::SignMagnitude {
+        return Err("record can not write sign-magnitude samples".into());
+    }
+    let spec = header::StreamHeader {
+        sample_rate: opt.sample_rate,
+        channels: opt.channels,
+        sample_size: opt.sample_size as u8,
+        float: opt.float,
+        unsigned: opt.unsigned || opt.encoding == Encoding::OffsetBinary,
+        big_endian: opt.be,
+        sign_magnitude: false,
+        silence_runs: false,
+    };
+    let encoder = opt.out_format.encoder();
+    encoder.check(&spec)?;
+    record::run(from, spec, encoder, file, duration)
+}
+
+/// Monomorphizes [tape_check] for the input sample format.
+fn tape_check_format(iformat: cpal::SampleFormat, opt: &Opt, input: Box<dyn io::Read + Send>, pilot: Option<f64>) -> Result<(), String> {
+    match iformat {
+        cpal::SampleFormat::I8  => tape_check::< i8>(opt, input, pilot),
+        cpal::SampleFormat::U8  => tape_check::< u8>(opt, input, pilot),
+
+        cpal::SampleFormat::I16 => tape_check::<i16>(opt, input, pilot),
+        cpal::SampleFormat::U16 => tape_check::<u16>(opt, input, pilot),
+
+        cpal::SampleFormat::I32 => tape_check::<i32>(opt, input, pilot),
+        cpal::SampleFormat::U32 => tape_check::<u32>(opt, input, pilot),
+
+        cpal::SampleFormat::I64 => tape_check::<i64>(opt, input, pilot),
+        cpal::SampleFormat::U64 => tape_check::<u64>(opt, input, pilot),
+
+        cpal::SampleFormat::F32 => tape_check::<f32>(opt, input, pilot),
+        cpal::SampleFormat::F64 => tape_check::<f64>(opt, input, pilot),
+        sample_format => panic!("Unsupported sample format '{sample_format}'"),
+    }
+}
+
+/// Decodes the whole input to mono and reports its wow, flutter and dropouts.
+fn tape_check<I>(opt: &Opt, input: Box<dyn io::Read + Send>, pilot: Option<f64>) -> Result<(), String>
+where
+  I: cpal::SizedSample + dasp_sample::ToSample<f32> + FromBytes + FromPacked + WrappingAdd {
+    let channels = opt.channels as usize;
+    let mut next_sample = sample_reader::<I>(input, opt, channels);
+    let sign_magnitude = opt.encoding == Encoding::SignMagnitude;
+    let gain = opt.gain * opt.fixed.map_or(1.0, |q| q.scale(opt.sample_size));
+    let mut check = TapeCheck::new(opt.sample_rate, pilot);
+    'frames: loop {
+        let mut sum = 0.0;
+        for _ in 0..channels {
+            let mut value = match next_sample() {
+                Ok(sample) => sample.to_sample::<f32>(),
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break 'frames,
+                Err(e) => return Err(format!("{e}")),
+            };
+            if sign_magnitude && value < 0.0 {
+                value = -(value + 1.0);
+            }
+            sum += value * gain;
+        }
+        check.push(sum / channels as f32);
+    }
+    check.report()
+}
+
+fn write_data<I, P>(
     output: &mut [f32],
-    channels: usize,
-    gain: f32,
-    next_sample: &mut dyn FnMut() -> I,
-    pre_out: bool,
-    post_out: bool,
-    mut out_io: &mut Option<BitWriter<Box<dyn std::io::Write + Send>>>,
-)
+    resampler: Option<&mut Box<dyn Resampler>>,
+    next_sample: &mut dyn FnMut() -> io::Result<I>,
+    process_sample: &mut dyn FnMut(I) -> P,
+    process_frame: &mut dyn FnMut(&mut [P]),
+    frame_buf: &mut [P],
+    taps: &mut [TapWriter],
+) -> io::Result<()>
 where
-  I: cpal::SizedSample + dasp_sample::ToSample<f32> + ToBytes {
-    for frame in output.chunks_mut(channels) {
-        for sample in frame.iter_mut() {
-            let pre_value = next_sample();
-            let post_value = pre_value
-                .to_sample::<f32>()
-                .mul_amp(gain);
-
-            match (&mut out_io, pre_out, post_out) {
-                (Some(out_io), true, false) => {
-                    out_io.write(pre_value).unwrap();
-                },
-                (Some(out_io), false, true) => {
-                    out_io.write(post_value).unwrap();
-                },
-                (Some(_), true, true) => panic!("--pre and --post both enabled"),
-                _ => (),
+  I: cpal::SizedSample + ToBytes,
+  P: ProcessSample {
+    let channels = frame_buf.len();
+    let mut read_frame = |frame: &mut [f32]| -> io::Result<()> {
+        for value in frame_buf.iter_mut() {
+            let pre_value = next_sample()?;
+            for out_io in taps.iter_mut().filter(|t| matches!(t.tap, Tap::Pre)) {
+                out_io.write_pre(pre_value).unwrap();
+            }
+            *value = process_sample(pre_value);
+        }
+
+        process_frame(frame_buf);
+
+        for out_io in taps.iter_mut().filter(|t| matches!(t.tap, Tap::Post)) {
+            for &post_value in frame_buf.iter() {
+                out_io.write_post(post_value).unwrap();
             }
+        }
 
-            *sample = post_value;
+        for (sample, value) in frame.iter_mut().zip(frame_buf.iter()) {
+            *sample = value.to_sample::<f32>();
         }
+        Ok(())
+    };
+
+    match resampler {
+        Some(resampler) => {
+            for frame in output.chunks_mut(channels) {
+                resampler.next_frame(frame, &mut read_frame)?;
+            }
+        },
+        None => {
+            for frame in output.chunks_mut(channels) {
+                read_frame(frame)?;
+            }
+        },
     }
+    Ok(())
 }
-
@@ -0,0 +1,170 @@
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+/// A JSON value, written indented.
+#[derive(Debug, Clone)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    /// members in the order written
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    /// An object from `(name, value)` pairs.
+    pub fn object<const N: usize>(members: [(&str, Json); N]) -> Self {
+        Json::Object(members.into_iter().map(|(name, value)| (name.to_string(), value)).collect())
+    }
+
+    fn write(&self, f: &mut fmt::Formatter, indent: usize) -> fmt::Result {
+        let pad = "  ".repeat(indent + 1);
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(b) => write!(f, "{b}"),
+            Json::Number(n) if n.is_finite() => write!(f, "{n}"),
+            Json::Number(_) => write!(f, "null"),
+            Json::String(s) => write_string(f, s),
+            Json::Array(items) if items.is_empty() => write!(f, "[]"),
+            Json::Array(items) => {
+                writeln!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    write!(f, "{pad}")?;
+                    item.write(f, indent + 1)?;
+                    writeln!(f, "{}", if i + 1 < items.len() { "," } else { "" })?;
+                }
+                write!(f, "{}]", &pad[2..])
+            },
+            Json::Object(members) if members.is_empty() => write!(f, "{{}}"),
+            Json::Object(members) => {
+                writeln!(f, "{{")?;
+                for (i, (name, value)) in members.iter().enumerate() {
+                    write!(f, "{pad}")?;
+                    write_string(f, name)?;
+                    write!(f, ": ")?;
+                    value.write(f, indent + 1)?;
+                    writeln!(f, "{}", if i + 1 < members.len() { "," } else { "" })?;
+                }
+                write!(f, "{}}}", &pad[2..])
+            },
+        }
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter, s: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{c}")?,
+        }
+    }
+    write!(f, "\"")
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.write(f, 0)
+    }
+}
+
+impl From<bool> for Json {
+    fn from(b: bool) -> Self {
+        Json::Bool(b)
+    }
+}
+
+impl From<&str> for Json {
+    fn from(s: &str) -> Self {
+        Json::String(s.to_string())
+    }
+}
+
+impl From<String> for Json {
+    fn from(s: String) -> Self {
+        Json::String(s)
+    }
+}
+
+macro_rules! json_from_number {
+    ($($t:ty),*) => {$(
+        impl From<$t> for Json {
+            fn from(n: $t) -> Self {
+                Json::Number(n as f64)
+            }
+        }
+    )*};
+}
+
+json_from_number!(u8, u16, u32, u64, usize, i64, f32, f64);
+
+impl<T: Into<Json>> From<Option<T>> for Json {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Json::Null, Into::into)
+    }
+}
+
+impl<T: Into<Json>> From<Vec<T>> for Json {
+    fn from(items: Vec<T>) -> Self {
+        Json::Array(items.into_iter().map(Into::into).collect())
+    }
+}
+
+/// Describes a local input, with its hash when it is a regular file.
+pub fn describe_file(path: &Path) -> Json {
+    let name = path.display().to_string();
+    if !fs::metadata(path).is_ok_and(|m| m.is_file()) {
+        return Json::object([("path", name.into())]);
+    }
+    match crate::sha256::hash_file(path) {
+        Ok((bytes, sha256)) => Json::object([("path", name.into()), ("bytes", bytes.into()), ("sha256", sha256.into())]),
+        Err(e) => {
+            eprintln!("[!] failed to hash {name} for the manifest: {e}");
+            Json::object([("path", name.into())])
+        },
+    }
+}
+
+/// Writes `manifest` to `path`.
+pub fn write(path: &Path, manifest: &Json) -> Result<(), String> {
+    fs::write(path, format!("{manifest}\n")).map_err(|e| format!("{}: {e}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_strings() {
+        let json = Json::from("a \"quote\", a \\ and\n\r\t\u{1}é");
+        assert_eq!(json.to_string(), r#""a \"quote\", a \\ and\n\r\t\u0001é""#);
+    }
+
+    #[test]
+    fn escapes_names() {
+        let json = Json::object([("a\"b", Json::Null)]);
+        assert_eq!(json.to_string(), "{\n  \"a\\\"b\": null\n}");
+    }
+
+    #[test]
+    fn writes_nested_values() {
+        let json = Json::object([
+            ("list", vec![1u32, 2].into()),
+            ("empty", Json::Array(Vec::new())),
+            ("nan", f64::NAN.into()),
+            ("flag", true.into()),
+        ]);
+        assert_eq!(
+            json.to_string(),
+            "{\n  \"list\": [\n    1,\n    2\n  ],\n  \"empty\": [],\n  \"nan\": null,\n  \"flag\": true\n}",
+        );
+    }
+}
@@ -0,0 +1,226 @@
+use std::f64::consts::{PI, TAU};
+use std::fs;
+use std::path::Path;
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use clap::ValueEnum;
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+
+/// Signals played to measure the response of a system.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Stimulus {
+    /// Exponential sine sweep from 20 Hz to 20 kHz, or to just below the Nyquist frequency
+    Chirp,
+    /// A single sample pulse
+    Impulse,
+}
+
+/// Peak level of the stimulus, before the gain.
+const LEVEL: f64 = 0.5;
+/// Raised-cosine fade at both ends of a chirp, avoids clicks exciting every frequency at once.
+const FADE: Duration = Duration::from_millis(10);
+const START_FREQUENCY: f64 = 20.0;
+const END_FREQUENCY: f64 = 20_000.0;
+/// Silence played after the stimulus, so the response can decay before the recording ends.
+const TAIL: Duration = Duration::from_secs(1);
+/// How long to keep recording past the end of playback for the latency of the input.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Start and end frequency of the chirp at `sample_rate`.
+pub fn sweep_range(sample_rate: u32) -> (f64, f64) {
+    (START_FREQUENCY, END_FREQUENCY.min(0.45 * sample_rate as f64))
+}
+
+impl Stimulus {
+    /// Mono samples of the stimulus, a chirp sweeps for `duration`.
+    pub fn samples(self, sample_rate: u32, duration: Duration) -> Result<Vec<f32>, String> {
+        let fs = sample_rate as f64;
+        match self {
+            Stimulus::Chirp => {
+                let len = (duration.as_secs_f64() * fs).round() as usize;
+                let fade = ((FADE.as_secs_f64() * fs) as usize).max(1);
+                if len < 4 * fade {
+                    return Err(format!("Chirp duration {duration:?} is too short, expected at least {:?}", FADE * 4));
+                }
+                // the phase of a sweep whose frequency rises by a constant ratio per second
+                let (f1, f2) = sweep_range(sample_rate);
+                let (t, log_ratio) = (duration.as_secs_f64(), (f2 / f1).ln());
+                let samples = (0..len)
+                    .map(|i| {
+                        let phase = TAU * f1 * t / log_ratio * ((i as f64 / fs / t * log_ratio).exp() - 1.0);
+                        let edge = i.min(len - 1 - i);
+                        let envelope = if edge < fade {
+                            0.5 - 0.5 * (PI * edge as f64 / fade as f64).cos()
+                        } else {
+                            1.0
+                        };
+                        (LEVEL * envelope * phase.sin()) as f32
+                    })
+                    .collect();
+                Ok(samples)
+            },
+            Stimulus::Impulse => Ok(vec![LEVEL as f32]),
+        }
+    }
+}
+
+/// Samples captured from the input device, and when the first of them was captured.
+#[derive(Default)]
+struct Recording {
+    start: Option<cpal::StreamInstant>,
+    samples: Vec<f32>,
+}
+
+/// A stimulus as played and the response recorded alongside it.
+pub struct Capture {
+    /// mono, the stimulus with the gain applied followed by a silent tail
+    pub played: Vec<f32>,
+    /// interleaved, from the instant playback started and as long as `played`
+    pub response: Vec<f32>,
+    pub input_channels: usize,
+}
+
+/// The default input device and its channel count.
+pub fn default_input() -> Result<(cpal::Device, u16), String> {
+    let input = cpal::default_host().default_input_device()
+        .ok_or("failed to find input device")?;
+    let channels = input.default_input_config()
+        .map_err(|e| format!("failed to get the input config: {e}"))?
+        .channels();
+    Ok((input, channels))
+}
+
+/// Plays `stimulus` on every channel of `output` while recording the default input device.
+pub fn capture(
+    output: &cpal::Device,
+    sample_rate: u32,
+    channels: u16,
+    gain: f32,
+    stimulus: &[f32],
+) -> Result<Capture, String> {
+    let (input, input_channels) = default_input()?;
+    let iconfig = cpal::StreamConfig {
+        channels: input_channels,
+        sample_rate: cpal::SampleRate(sample_rate),
+        buffer_size: cpal::BufferSize::Default,
+    };
+    let oconfig = cpal::StreamConfig {
+        channels,
+        sample_rate: cpal::SampleRate(sample_rate),
+        buffer_size: cpal::BufferSize::Default,
+    };
+
+    let tail = (TAIL.as_secs_f64() * sample_rate as f64) as usize;
+    let played = stimulus.iter()
+        .map(|s| s * gain)
+        .chain(std::iter::repeat_n(0.0, tail))
+        .collect::<Vec<_>>();
+
+    let err_fn = move |err| {
+        eprintln!("an error occurred on stream: {}", err)
+    };
+
+    let recording = Arc::new(Mutex::new(Recording::default()));
+    let input_stream = {
+        let recording = recording.clone();
+        input.build_input_stream(
+            &iconfig,
+            move |data: &[f32], info: &cpal::InputCallbackInfo| {
+                let mut recording = recording.lock().unwrap();
+                recording.start.get_or_insert(info.timestamp().capture);
+                recording.samples.extend_from_slice(data);
+            },
+            err_fn,
+            None,
+        ).map_err(|e| format!("failed to open the input device: {e}"))?
+    };
+
+    let playback_start = Arc::new(Mutex::new(None));
+    let (done_tx, done_rx) = mpsc::channel();
+    let output_stream = {
+        let playback_start = playback_start.clone();
+        let played = played.clone();
+        let mut position = 0;
+        let mut done_tx = Some(done_tx);
+        output.build_output_stream(
+            &oconfig,
+            move |data: &mut [f32], info: &cpal::OutputCallbackInfo| {
+                playback_start.lock().unwrap().get_or_insert(info.timestamp().playback);
+                for frame in data.chunks_mut(channels as usize) {
+                    frame.fill(played.get(position).copied().unwrap_or(0.0));
+                    position += 1;
+                }
+                if position >= played.len() && let Some(done_tx) = done_tx.take() {
+                    let _ = done_tx.send(());
+                }
+            },
+            err_fn,
+            None,
+        ).map_err(|e| format!("failed to open the output device: {e}"))?
+    };
+
+    // capture runs first, so that it covers the start of playback
+    input_stream.play().map_err(|e| format!("{e}"))?;
+    output_stream.play().map_err(|e| format!("{e}"))?;
+    done_rx.recv().map_err(|e| format!("{e}"))?;
+
+    // both streams timestamp their buffers on the host's clock, which aligns the recording
+    let frames = |d: Duration| (d.as_secs_f64() * sample_rate as f64).round() as usize;
+    let input_channels = input_channels as usize;
+    let deadline = Instant::now() + TIMEOUT;
+    let mut response = loop {
+        let recording = recording.lock().unwrap();
+        let playback_start = *playback_start.lock().unwrap();
+        if let (Some(capture), Some(playback)) = (recording.start, playback_start) {
+            let (skip, pad) = match playback.duration_since(&capture) {
+                Some(lead) => (frames(lead), 0),
+                None => (0, capture.duration_since(&playback).map_or(0, frames)),
+            };
+            if recording.samples.len() / input_channels >= skip + played.len().saturating_sub(pad) {
+                if pad > 0 {
+                    eprintln!("[!] recording started {pad} frames after playback, padded with silence");
+                }
+                let mut response = vec![0.0; pad * input_channels];
+                response.extend_from_slice(&recording.samples[skip * input_channels..]);
+                break response;
+            }
+        }
+        if Instant::now() > deadline {
+            return Err("Recording stopped before the end of playback".into());
+        }
+        drop(recording);
+        std::thread::sleep(Duration::from_millis(10));
+    };
+    drop(output_stream);
+    drop(input_stream);
+    response.truncate(played.len() * input_channels);
+    Ok(Capture { played, response, input_channels })
+}
+
+/// Plays `stimulus` on every channel of `output` while recording the default input device,
+/// then writes the recording from the instant playback started, and the stimulus as played,
+/// as f32 little-endian samples.
+pub fn run(
+    output: &cpal::Device,
+    sample_rate: u32,
+    channels: u16,
+    gain: f32,
+    stimulus: &[f32],
+    record: &Path,
+    stimulus_out: &Path,
+) -> Result<(), String> {
+    let Capture { played, response, input_channels } = capture(output, sample_rate, channels, gain, stimulus)?;
+    let write = |path: &Path, samples: &[f32]| {
+        let bytes = samples.iter().flat_map(|s| s.to_le_bytes()).collect::<Vec<_>>();
+        fs::write(path, bytes).map_err(|e| format!("{}: {e}", path.display()))
+    };
+    write(record, &response)?;
+    write(stimulus_out, &played)?;
+    eprintln!(
+        "[!] recorded {input_channels} channel f32le at {sample_rate} Hz to {}, stimulus as played in mono to {}",
+        record.display(),
+        stimulus_out.display(),
+    );
+    Ok(())
+}
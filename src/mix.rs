@@ -0,0 +1,60 @@
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::compress;
+use crate::container;
+use crate::header::StreamHeader;
+
+/// A file summed into the input by `--mix`, scaled by its own gain.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MixInput {
+    pub path: PathBuf,
+    pub gain: f32,
+}
+
+impl FromStr for MixInput {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // a number after the last colon is the gain, anything else is part of the path
+        match s.rsplit_once(':').and_then(|(path, gain)| Some((path, gain.parse::<f32>().ok()?))) {
+            Some((path, gain)) if gain.is_finite() => Ok(MixInput { path: path.into(), gain }),
+            Some(_) => Err(format!("Invalid gain in '{s}'")),
+            None => Ok(MixInput { path: s.into(), gain: 1.0 }),
+        }
+    }
+}
+
+impl MixInput {
+    /// Opens the samples of the file, which must be in the `expected` format of the input it is mixed into.
+    pub fn open(&self, expected: &StreamHeader) -> Result<Box<dyn Read + Send>, String> {
+        open(&self.path, expected, "the input it is mixed into")
+    }
+}
+
+/// Opens the samples of the file at `path`, which must be in the `expected` format of `what`.
+pub fn open(path: &Path, expected: &StreamHeader, what: &str) -> Result<Box<dyn Read + Send>, String> {
+    let error = |e: io::Error| format!("{}: {e}", path.display());
+    let file = File::open(path).map_err(error)?;
+    let (detected, input) = container::detect(compress::decompress(Box::new(io::BufReader::new(file)))?)?;
+    if let Some(detected) = detected {
+        let spec = StreamHeader { silence_runs: false, ..detected.spec };
+        if spec != *expected {
+            return Err(format!("{} is {}, {what} is {}", path.display(), describe(&spec), describe(expected)));
+        }
+    }
+    Ok(input)
+}
+
+fn describe(spec: &StreamHeader) -> String {
+    let kind = match (spec.float, spec.unsigned, spec.sign_magnitude) {
+        (true, _, _) => "float",
+        (false, true, _) => "unsigned",
+        (false, false, true) => "sign-magnitude",
+        (false, false, false) => "signed",
+    };
+    let byte_order = if spec.big_endian { "big-endian" } else { "little-endian" };
+    format!("{} channel {} bit {kind} {byte_order} at {} Hz", spec.channels, spec.sample_size, spec.sample_rate)
+}
@@ -0,0 +1,247 @@
+use std::collections::VecDeque;
+use std::fs;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::path::Path;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+
+const MAX_DATAGRAM_LEN: usize = 65_536;
+
+/// Waits for a sender to connect to `addr` and returns its connection.
+pub fn accept(addr: SocketAddr) -> io::Result<TcpStream> {
+    accept_from(TcpListener::bind(addr)?)
+}
+
+/// Waits for a sender to connect to `listener` and returns its connection.
+pub fn accept_from(listener: TcpListener) -> io::Result<TcpStream> {
+    eprintln!("[!] listening on {}", listener.local_addr()?);
+    let (stream, peer) = listener.accept()?;
+    eprintln!("[!] playing from {peer}");
+    Ok(stream)
+}
+
+/// Connects to a server at `addr` that sends a stream, e.g. a netcat on a capture box.
+pub fn connect(addr: SocketAddr) -> io::Result<TcpStream> {
+    let stream = TcpStream::connect(addr)?;
+    eprintln!("[!] playing from {addr}");
+    Ok(stream)
+}
+
+/// Whether `path` is a unix domain socket, which is connected to rather than opened.
+#[cfg(unix)]
+pub fn is_unix_socket(path: &Path) -> bool {
+    use std::os::unix::fs::FileTypeExt;
+    fs::metadata(path).is_ok_and(|m| m.file_type().is_socket())
+}
+
+#[cfg(not(unix))]
+pub fn is_unix_socket(_path: &Path) -> bool {
+    false
+}
+
+/// Connects to a local daemon's unix domain socket at `path` and returns the stream it sends.
+#[cfg(unix)]
+pub fn connect_unix(path: &Path) -> io::Result<Box<dyn io::Read + Send>> {
+    let stream = std::os::unix::net::UnixStream::connect(path)?;
+    eprintln!("[!] playing from {}", path.display());
+    Ok(Box::new(stream))
+}
+
+#[cfg(not(unix))]
+pub fn connect_unix(_path: &Path) -> io::Result<Box<dyn io::Read + Send>> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+/// Waits for a sender to connect to a unix domain socket created at `path` and returns its connection.
+/// A socket left at `path`, e.g. by an earlier run, is replaced, and the socket is removed again once connected.
+#[cfg(unix)]
+pub fn accept_unix(path: &Path) -> io::Result<Box<dyn io::Read + Send>> {
+    use std::os::unix::fs::FileTypeExt;
+    if fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_socket()) {
+        fs::remove_file(path)?;
+    }
+    let listener = std::os::unix::net::UnixListener::bind(path)?;
+    eprintln!("[!] listening on {}", path.display());
+    let accepted = listener.accept();
+    let _ = fs::remove_file(path);
+    let (stream, _) = accepted?;
+    eprintln!("[!] playing from {}", path.display());
+    Ok(Box::new(stream))
+}
+
+#[cfg(not(unix))]
+pub fn accept_unix(_path: &Path) -> io::Result<Box<dyn io::Read + Send>> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+/// The socket systemd passed to a socket-activated service.
+pub enum Activated {
+    /// the first connection to a listening socket, or the one systemd accepted with Accept=yes
+    Stream(Box<dyn io::Read + Send>),
+    Datagram(UdpSocket),
+}
+
+/// Takes the one socket systemd passes with socket activation, see sd_listen_fds(3).
+#[cfg(unix)]
+pub fn activated() -> io::Result<Activated> {
+    use std::os::fd::{FromRawFd, RawFd};
+    use std::os::unix::net::{UnixListener, UnixStream};
+
+    /// the first descriptor passed, after stdin, stdout and stderr
+    const LISTEN_FDS_START: RawFd = 3;
+
+    let var = |name: &str| std::env::var(name).ok().and_then(|v| v.parse::<u32>().ok());
+    if var("LISTEN_PID") != Some(std::process::id()) {
+        return Err(io::Error::other("no socket passed, LISTEN_PID is not set to this process"));
+    }
+    match var("LISTEN_FDS") {
+        Some(1) => (),
+        Some(0) | None => return Err(io::Error::other("no socket passed, LISTEN_FDS is not set")),
+        Some(n) => return Err(io::Error::other(format!("{n} sockets passed, expected one"))),
+    }
+
+    let fd = LISTEN_FDS_START;
+    let option = |name: libc::c_int| -> io::Result<libc::c_int> {
+        let mut value: libc::c_int = 0;
+        let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+        if unsafe { libc::getsockopt(fd, libc::SOL_SOCKET, name, (&mut value as *mut libc::c_int).cast(), &mut len) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(value)
+    };
+    let kind = option(libc::SO_TYPE)?;
+    let listening = option(libc::SO_ACCEPTCONN)? != 0;
+    let mut addr = unsafe { std::mem::zeroed::<libc::sockaddr_storage>() };
+    let mut len = std::mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+    if unsafe { libc::getsockname(fd, (&mut addr as *mut libc::sockaddr_storage).cast(), &mut len) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let unix = addr.ss_family as libc::c_int == libc::AF_UNIX;
+    unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) };
+
+    // the descriptor is owned from here on, by whichever socket wraps it
+    Ok(match (kind, unix, listening) {
+        (libc::SOCK_DGRAM, false, _) => Activated::Datagram(unsafe { UdpSocket::from_raw_fd(fd) }),
+        (libc::SOCK_STREAM, false, true) => {
+            let listener = unsafe { TcpListener::from_raw_fd(fd) };
+            Activated::Stream(Box::new(accept_from(listener)?))
+        },
+        (libc::SOCK_STREAM, true, true) => {
+            let listener = unsafe { UnixListener::from_raw_fd(fd) };
+            eprintln!("[!] listening on the socket passed by systemd");
+            let (stream, _) = listener.accept()?;
+            eprintln!("[!] playing from a connection to the socket passed by systemd");
+            Activated::Stream(Box::new(stream))
+        },
+        (libc::SOCK_STREAM, unix, false) => {
+            eprintln!("[!] playing from the connection passed by systemd");
+            if unix {
+                Activated::Stream(Box::new(unsafe { UnixStream::from_raw_fd(fd) }))
+            } else {
+                Activated::Stream(Box::new(unsafe { TcpStream::from_raw_fd(fd) }))
+            }
+        },
+        _ => return Err(io::Error::other("the passed socket is neither a stream nor a UDP socket")),
+    })
+}
+
+#[cfg(not(unix))]
+pub fn activated() -> io::Result<Activated> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+/// Binds a socket receiving datagrams sent to `addr`, joining the group if it is multicast.
+pub fn bind_receiver(addr: SocketAddr) -> io::Result<UdpSocket> {
+    match addr.ip() {
+        IpAddr::V4(group) if group.is_multicast() => {
+            let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, addr.port()))?;
+            socket.join_multicast_v4(&group, &Ipv4Addr::UNSPECIFIED)?;
+            Ok(socket)
+        },
+        IpAddr::V6(group) if group.is_multicast() => {
+            let socket = UdpSocket::bind((Ipv6Addr::UNSPECIFIED, addr.port()))?;
+            socket.join_multicast_v6(&group, 0)?;
+            Ok(socket)
+        },
+        _ => UdpSocket::bind(addr),
+    }
+}
+
+/// Reads raw samples from datagrams in the order they arrive.
+/// Once the first datagram is in, gaps in the stream are played as silence instead of waiting.
+pub struct UdpSource {
+    shared: Arc<(Mutex<VecDeque<u8>>, Condvar)>,
+    silent_frame: Vec<u8>,
+    /// bytes read so far, to keep silence aligned to frames
+    read: usize,
+    primed: bool,
+}
+
+impl UdpSource {
+    /// Buffers up to `max_buffered` bytes, older audio is dropped when the player falls behind.
+    pub fn new(addr: SocketAddr, silent_frame: Vec<u8>, max_buffered: usize) -> io::Result<Self> {
+        Ok(UdpSource::from_socket(bind_receiver(addr)?, silent_frame, max_buffered))
+    }
+
+    /// Receives the datagrams sent to a bound `socket`.
+    pub fn from_socket(socket: UdpSocket, silent_frame: Vec<u8>, max_buffered: usize) -> Self {
+        let shared = Arc::new((Mutex::new(VecDeque::new()), Condvar::new()));
+        let receiver_shared = Arc::clone(&shared);
+        let frame_len = silent_frame.len();
+        thread::spawn(move || receive_datagrams(socket, receiver_shared, frame_len, max_buffered));
+        UdpSource { shared, silent_frame, read: 0, primed: false }
+    }
+}
+
+impl io::Read for UdpSource {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let frame_len = self.silent_frame.len();
+        let (lock, ready) = &*self.shared;
+        let mut buffer = lock.lock().unwrap();
+        if !self.primed {
+            buffer = ready.wait_while(buffer, |b| b.is_empty()).unwrap();
+            self.primed = true;
+        }
+
+        let in_frame = self.read % frame_len;
+        let len = if !buffer.is_empty() && in_frame == 0 {
+            let len = buf.len().min(buffer.len());
+            for (dst, src) in buf.iter_mut().zip(buffer.drain(..len)) {
+                *dst = src;
+            }
+            len
+        } else {
+            // lost or late datagrams, fill with silence up to the next frame boundary or
+            // with whole frames, so the samples that follow stay aligned
+            let len = if in_frame > 0 { frame_len - in_frame } else { frame_len.max(buf.len() / frame_len * frame_len) };
+            let len = len.min(buf.len());
+            for (i, byte) in buf[..len].iter_mut().enumerate() {
+                *byte = self.silent_frame[(in_frame + i) % frame_len];
+            }
+            len
+        };
+        self.read += len;
+        Ok(len)
+    }
+}
+
+/// Queues the whole frames of each datagram for [UdpSource].
+fn receive_datagrams(socket: UdpSocket, shared: Arc<(Mutex<VecDeque<u8>>, Condvar)>, frame_len: usize, max_buffered: usize) {
+    let (lock, ready) = &*shared;
+    let mut datagram = vec![0u8; MAX_DATAGRAM_LEN];
+    loop {
+        let len = match socket.recv(&mut datagram) {
+            Ok(len) => len / frame_len * frame_len,
+            Err(e) => {
+                eprintln!("[!] UDP receive failed: {e}");
+                continue;
+            },
+        };
+        let mut buffer = lock.lock().unwrap();
+        buffer.extend(&datagram[..len]);
+        let excess = (buffer.len().saturating_sub(max_buffered).div_ceil(frame_len) * frame_len).min(buffer.len());
+        buffer.drain(..excess);
+        ready.notify_one();
+    }
+}
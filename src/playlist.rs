@@ -0,0 +1,466 @@
+use std::collections::VecDeque;
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::bit_io::{BitReader, BitWriter};
+use crate::channel;
+use crate::container;
+use crate::header::{self, StreamHeader};
+use crate::http;
+use crate::resample::{Resampler, ResamplerKind};
+
+/// Deepest nesting of playlists, guards against playlists that include themselves.
+const MAX_DEPTH: usize = 8;
+
+/// Starts a line giving the format of the raw entry after it, in fields that override the
+/// format given on the command line, e.g. `#RPLAY-FORMAT rate=8000 size=8 channels=1 unsigned`.
+const FORMAT_DIRECTIVE: &str = "#RPLAY-FORMAT";
+
+/// Frames converted at a time for an entry in a format other than the stream's.
+const CONVERT_FRAMES: usize = 1024;
+
+/// How often a playlist plays.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Repeat {
+    /// the whole playlist, forever
+    All,
+    /// each entry forever, so only the first that opens is heard
+    One,
+    /// the whole playlist this many times
+    Times(u32),
+}
+
+impl FromStr for Repeat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "all" => Ok(Repeat::All),
+            "one" => Ok(Repeat::One),
+            times => match times.parse() {
+                Ok(times) if times > 0 => Ok(Repeat::Times(times)),
+                _ => Err(format!("Invalid repeat '{s}', expected all, one or a number of times")),
+            },
+        }
+    }
+}
+
+/// Whether `path` names an M3U playlist.
+pub fn is_playlist(path: &str) -> bool {
+    Path::new(path).extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("m3u") || e.eq_ignore_ascii_case("m3u8"))
+}
+
+/// An entry of a playlist.
+#[derive(Debug, Clone)]
+struct Entry {
+    path: String,
+    /// format given by a [FORMAT_DIRECTIVE] before it, a container header overrides it
+    format: Option<StreamHeader>,
+}
+
+/// Resolves the fields of a [FORMAT_DIRECTIVE] over the format given on the command line,
+/// with the checks applied to that format.
+fn parse_format(fields: &str, flags: &StreamHeader) -> Result<StreamHeader, String> {
+    let mut format = StreamHeader { silence_runs: false, ..*flags };
+    for field in fields.split_whitespace() {
+        let number = |value: &str| value.parse::<u32>()
+            .map_err(|_| format!("Invalid {FORMAT_DIRECTIVE} field '{field}', expected a number"));
+        match field.split_once('=') {
+            Some(("rate", value)) => format.sample_rate = number(value)?,
+            Some(("size", value)) => format.sample_size = number(value)?.try_into()
+                .map_err(|_| format!("Unsupported sample size: '{value}'"))?,
+            Some(("channels", value)) => format.channels = number(value)?.try_into()
+                .map_err(|_| format!("Too many channels '{value}'"))?,
+            None if field == "signed" => (format.float, format.unsigned, format.sign_magnitude) = (false, false, false),
+            None if field == "unsigned" => (format.float, format.unsigned, format.sign_magnitude) = (false, true, false),
+            None if field == "sign-magnitude" => (format.float, format.unsigned, format.sign_magnitude) = (false, false, true),
+            None if field == "float" => (format.float, format.unsigned, format.sign_magnitude) = (true, false, false),
+            None if field == "le" => format.big_endian = false,
+            None if field == "be" => format.big_endian = true,
+            _ => return Err(format!(
+                "Invalid {FORMAT_DIRECTIVE} field '{field}', expected rate=, size=, channels=, signed, unsigned, sign-magnitude, float, le or be"
+            )),
+        }
+    }
+    header::sample_format(format.float, format.unsigned, format.sample_size as u32)?;
+    if format.sample_rate == 0 || format.channels == 0 {
+        return Err("Sample rate and channels must be at least 1".into());
+    }
+    if format.big_endian && format.sample_size == 8 {
+        eprintln!("[!] {FORMAT_DIRECTIVE} {fields}: big-endian has no effect on 8 bit samples");
+    }
+    Ok(format)
+}
+
+/// Appends the entries of the playlist at `path` to `entries`, each with the [FORMAT_DIRECTIVE]
+/// before it, expanding nested playlists and resolving relative paths against the directory
+/// of the playlist that lists them.
+fn collect(path: &Path, depth: usize, entries: &mut Vec<(String, Option<String>)>) -> Result<(), String> {
+    if depth > MAX_DEPTH {
+        return Err(format!("Playlists nested more than {MAX_DEPTH} deep at {}", path.display()));
+    }
+    let bytes = fs::read(path).map_err(|e| format!("{}: {e}", path.display()))?;
+    let dir = path.parent().unwrap_or(Path::new(""));
+    let mut format = None;
+    for line in String::from_utf8_lossy(&bytes).lines() {
+        let line = line.trim().trim_start_matches('\u{feff}');
+        if let Some(fields) = line.strip_prefix(FORMAT_DIRECTIVE) {
+            format = Some(fields.trim().to_string());
+            continue;
+        }
+        // #EXTM3U, #EXTINF and other directives only describe the entries
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let entry = if http::is_url(line) {
+            line.to_string()
+        } else {
+            let local = line.strip_prefix("file://").unwrap_or(line);
+            dir.join(local).to_string_lossy().into_owned()
+        };
+        if is_playlist(&entry) && !http::is_url(&entry) {
+            if format.take().is_some() {
+                eprintln!("[!] {FORMAT_DIRECTIVE} before the playlist {entry} ignored, it describes a single entry");
+            }
+            collect(Path::new(&entry), depth + 1, entries)?;
+        } else {
+            entries.push((entry, format.take()));
+        }
+    }
+    Ok(())
+}
+
+/// Every entry of the playlist at `path`, nested playlists expanded.
+pub fn entries(path: &Path) -> Result<Vec<String>, String> {
+    let mut entries = Vec::new();
+    collect(path, 0, &mut entries)?;
+    Ok(entries.into_iter().map(|(entry, _)| entry).collect())
+}
+
+fn open_entry(entry: &str) -> Result<Box<dyn Read + Send>, String> {
+    if http::is_url(entry) {
+        return Ok(Box::new(io::BufReader::new(http::HttpSource::open(entry)?)));
+    }
+    let file = fs::File::open(entry).map_err(|e| format!("{entry}: {e}"))?;
+    Ok(Box::new(io::BufReader::new(file)))
+}
+
+/// Plays the entries of an M3U playlist one after another as a single stream.
+///
+/// Container headers are taken off every entry; the format of the first one, from its header or
+/// a [FORMAT_DIRECTIVE], is passed on as an rplay stream header in front of the samples.
+/// Later entries with a header or directive giving another format are converted to it.
+pub struct Playlist {
+    /// every entry, in the order of the playlist
+    all: Vec<Entry>,
+    /// entries left to play in this pass
+    entries: VecDeque<Entry>,
+    input: Box<dyn Read + Send>,
+    /// entry playing, if `repeat` is [Repeat::One]
+    current: Option<Entry>,
+    /// format given on the command line
+    flags: StreamHeader,
+    /// format of the first entry, if it has a header or directive
+    spec: Option<StreamHeader>,
+    started: bool,
+    shuffle: bool,
+    repeat: Option<Repeat>,
+    /// passes over the playlist started, including the first
+    passes: u32,
+    /// whether samples were read during the current pass, a pass without any ends the repeats
+    heard_in_pass: bool,
+    /// whether samples were read from the current entry, one without any is not repeated
+    heard: bool,
+    /// xorshift state for shuffling
+    rng: u64,
+}
+
+impl Playlist {
+    /// Opens the playlist at `path`, `flags` is the format given on the command line.
+    pub fn open(path: &Path, flags: StreamHeader, shuffle: bool, repeat: Option<Repeat>) -> Result<Self, String> {
+        let mut entries = Vec::new();
+        collect(path, 0, &mut entries)?;
+        if entries.is_empty() {
+            return Err(format!("Playlist {} has no entries", path.display()));
+        }
+        let entries = entries.into_iter()
+            .map(|(path, format)| Ok(Entry {
+                format: format.map(|f| parse_format(&f, &flags).map_err(|e| format!("{path}: {e}"))).transpose()?,
+                path,
+            }))
+            .collect::<Result<Vec<_>, String>>()?;
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos() as u64;
+        let mut playlist = Playlist {
+            all: entries,
+            entries: VecDeque::new(),
+            input: Box::new(io::empty()),
+            current: None,
+            flags,
+            spec: None,
+            started: false,
+            shuffle,
+            repeat,
+            passes: 0,
+            heard_in_pass: false,
+            heard: false,
+            // xorshift state must not be zero
+            rng: seed | 1,
+        };
+        playlist.start_pass();
+        if !playlist.next_entry().map_err(|e| format!("{e}"))? {
+            return Err(format!("No entry of playlist {} could be opened", path.display()));
+        }
+        if let Some(spec) = playlist.spec {
+            let header = io::Cursor::new(spec.to_bytes());
+            let input = std::mem::replace(&mut playlist.input, Box::new(io::empty()));
+            playlist.input = Box::new(header.chain(input));
+        }
+        Ok(playlist)
+    }
+
+    /// Queues the entries for another pass over the playlist, shuffled if asked.
+    fn start_pass(&mut self) {
+        let mut entries = self.all.clone();
+        if self.shuffle {
+            // Fisher-Yates
+            for i in (1..entries.len()).rev() {
+                self.rng ^= self.rng << 13;
+                self.rng ^= self.rng >> 7;
+                self.rng ^= self.rng << 17;
+                entries.swap(i, (self.rng % (i as u64 + 1)) as usize);
+            }
+        }
+        self.entries = entries.into();
+        self.passes += 1;
+        self.heard_in_pass = false;
+    }
+
+    /// The entry to play after the current one, None at the end of the playlist.
+    fn upcoming(&mut self) -> Option<Entry> {
+        if let Some(entry) = self.current.take() && self.heard {
+            return Some(entry);
+        }
+        if self.entries.is_empty() && self.heard_in_pass {
+            match self.repeat {
+                Some(Repeat::All) => self.start_pass(),
+                Some(Repeat::Times(times)) if self.passes < times => self.start_pass(),
+                _ => (),
+            }
+        }
+        self.entries.pop_front()
+    }
+
+    /// Moves on to the next entry that opens, false at the end of the playlist.
+    fn next_entry(&mut self) -> io::Result<bool> {
+        while let Some(entry) = self.upcoming() {
+            let input = match open_entry(&entry.path) {
+                Ok(input) => input,
+                Err(msg) => {
+                    eprintln!("[!] {msg}, skipping it");
+                    continue;
+                },
+            };
+            let (detected, input) = container::detect(input).map_err(io::Error::other)?;
+            // silence runs are decoded by detect, the samples passed on are plain
+            let spec = detected.map(|d| StreamHeader { silence_runs: false, ..d.spec }).or(entry.format);
+            if !self.started {
+                self.spec = spec;
+            }
+            let stream = self.spec.unwrap_or(self.flags);
+            let input: Box<dyn Read + Send> = match spec {
+                Some(spec) if spec != stream => {
+                    eprintln!("[!] {} has a different format from the first entry, converting it", entry.path);
+                    Box::new(Convert::new(input, spec, stream))
+                },
+                _ => input,
+            };
+            self.started = true;
+            self.heard = false;
+            eprintln!("[!] playing {}", entry.path);
+            if self.repeat == Some(Repeat::One) {
+                self.current = Some(entry);
+            }
+            self.input = input;
+            return Ok(true);
+        }
+        Ok(false)
+    }
+}
+
+impl Read for Playlist {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let len = self.input.read(buf)?;
+            if len > 0 {
+                self.heard = true;
+                self.heard_in_pass = true;
+            }
+            if len > 0 || buf.is_empty() || !self.next_entry()? {
+                return Ok(len);
+            }
+        }
+    }
+}
+
+/// Reads an entry in one format as samples of another, fitting its channels and resampling it.
+struct Convert {
+    input: BitReader<Box<dyn Read + Send>>,
+    from: StreamHeader,
+    to: StreamHeader,
+    resampler: Option<Box<dyn Resampler>>,
+    /// a frame of the entry
+    frame: Vec<f32>,
+    /// a frame of the stream
+    converted: Vec<f32>,
+    output: BitWriter<Vec<u8>>,
+    /// bytes of `output` already read
+    position: usize,
+    ended: bool,
+}
+
+impl Convert {
+    fn new(input: Box<dyn Read + Send>, from: StreamHeader, to: StreamHeader) -> Self {
+        let resampler = (from.sample_rate != to.sample_rate)
+            .then(|| ResamplerKind::Sinc.build(to.channels as usize, from.sample_rate, to.sample_rate));
+        Convert {
+            input: BitReader::new(input, from.big_endian),
+            from,
+            to,
+            resampler,
+            frame: vec![0.0; from.channels as usize],
+            converted: vec![0.0; to.channels as usize],
+            output: BitWriter::new(Vec::new(), to.big_endian),
+            position: 0,
+            ended: false,
+        }
+    }
+
+    /// Converts the next frame of the entry into `output`.
+    fn convert_frame(&mut self) -> io::Result<()> {
+        let Convert { input, from, frame, .. } = self;
+        let mut next = |fitted: &mut [f32]| -> io::Result<()> {
+            for sample in frame.iter_mut() {
+                *sample = decode(input.read_packed(from.sample_size as u32)?, from);
+            }
+            channel::fit_frame(frame, fitted);
+            Ok(())
+        };
+        match &mut self.resampler {
+            Some(resampler) => resampler.next_frame(&mut self.converted, &mut next)?,
+            None => next(&mut self.converted)?,
+        }
+        for &sample in &self.converted {
+            self.output.write_packed(encode(sample, &self.to), self.to.sample_size as u32)?;
+        }
+        Ok(())
+    }
+}
+
+impl Read for Convert {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.output.get_mut().len() && !self.ended {
+            self.output.get_mut().clear();
+            self.position = 0;
+            for _ in 0..CONVERT_FRAMES {
+                match self.convert_frame() {
+                    Ok(()) => (),
+                    Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                        self.ended = true;
+                        break;
+                    },
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+        let position = self.position;
+        let converted = &self.output.get_mut()[position..];
+        let len = converted.len().min(buf.len());
+        buf[..len].copy_from_slice(&converted[..len]);
+        self.position += len;
+        Ok(len)
+    }
+}
+
+/// A sample of `spec`, read left-justified into 64 bits, as a float from -1 to 1.
+fn decode(raw: u64, spec: &StreamHeader) -> f32 {
+    let scale = 2f64.powi(63);
+    match spec.sample_size {
+        32 if spec.float => f32::from_bits((raw >> 32) as u32),
+        _ if spec.float => f64::from_bits(raw) as f32,
+        _ if spec.unsigned => ((raw ^ 1 << 63) as i64 as f64 / scale) as f32,
+        _ if spec.sign_magnitude => {
+            let magnitude = (raw & !(1 << 63)) as f64 / scale;
+            (if raw >> 63 == 1 { -magnitude } else { magnitude }) as f32
+        },
+        _ => (raw as i64 as f64 / scale) as f32,
+    }
+}
+
+/// The inverse of [decode], rounding to the nearest level of `spec`.
+fn encode(sample: f32, spec: &StreamHeader) -> u64 {
+    let shift = 64 - spec.sample_size as u32;
+    let scale = 2f64.powi(spec.sample_size as i32 - 1);
+    match spec.sample_size {
+        32 if spec.float => (sample.to_bits() as u64) << 32,
+        _ if spec.float => (sample as f64).to_bits(),
+        _ if spec.sign_magnitude => {
+            let magnitude = (sample.abs() as f64 * scale).round().min(scale - 1.0) as u64;
+            magnitude << shift | if sample < 0.0 { 1 << 63 } else { 0 }
+        },
+        _ => {
+            let level = (sample as f64 * scale).round().clamp(-scale, scale - 1.0) as i64 as u64;
+            let raw = level << shift;
+            if spec.unsigned { raw ^ 1 << 63 } else { raw }
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FLAGS: StreamHeader = StreamHeader {
+        sample_rate: 44100,
+        channels: 2,
+        sample_size: 16,
+        float: false,
+        unsigned: false,
+        big_endian: false,
+        sign_magnitude: false,
+        silence_runs: false,
+    };
+
+    #[test]
+    fn format_directive_overrides_flags() {
+        let format = parse_format("rate=8000 size=8 channels=1 unsigned", &FLAGS).unwrap();
+        assert_eq!(format, StreamHeader { sample_rate: 8000, channels: 1, sample_size: 8, unsigned: true, ..FLAGS });
+        assert_eq!(parse_format("", &FLAGS).unwrap(), FLAGS);
+        assert!(parse_format("size=24 float", &FLAGS).is_err());
+        assert!(parse_format("channels=0", &FLAGS).is_err());
+        assert!(parse_format("loud", &FLAGS).is_err());
+    }
+
+    #[test]
+    fn samples_survive_encoding() {
+        let formats = [
+            FLAGS,
+            StreamHeader { sample_size: 12, unsigned: true, ..FLAGS },
+            StreamHeader { sample_size: 24, sign_magnitude: true, ..FLAGS },
+            StreamHeader { sample_size: 32, float: true, ..FLAGS },
+            StreamHeader { sample_size: 64, float: true, ..FLAGS },
+        ];
+        for spec in formats {
+            for sample in [0.0, 0.5, -0.5, -0.25] {
+                let raw = encode(sample, &spec);
+                assert_eq!(decode(raw, &spec), sample, "{spec:?}");
+            }
+        }
+    }
+}
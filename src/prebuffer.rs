@@ -0,0 +1,117 @@
+use std::collections::VecDeque;
+use std::io::{self, Read};
+use std::str::FromStr;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+
+use crate::timecode::Time;
+
+/// Least read ahead once playing, for thresholds smaller than a pipe buffer.
+const MIN_CAPACITY: usize = 1 << 16;
+
+/// How much input to hold before playback starts.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Threshold {
+    Time(Time),
+    Bytes(usize),
+}
+
+impl Threshold {
+    /// The threshold in bytes of input with frames of `frame_bits` at `sample_rate`.
+    pub fn bytes(self, sample_rate: u32, frame_bits: usize) -> usize {
+        match self {
+            Threshold::Time(time) => (time.frames(sample_rate) as usize * frame_bits).div_ceil(8),
+            Threshold::Bytes(bytes) => bytes,
+        }
+    }
+}
+
+impl FromStr for Threshold {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // a bare number is bytes, anything else a time
+        match s.trim().parse() {
+            Ok(bytes) => Ok(Threshold::Bytes(bytes)),
+            Err(_) => s.parse()
+                .map(Threshold::Time)
+                .map_err(|_| format!("Invalid prebuffer '{s}', expected a duration such as 500ms, frames such as @24000 or a number of bytes")),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Buffer {
+    data: VecDeque<u8>,
+    /// the input ended, with the error that ended it if any
+    end: Option<Option<io::Error>>,
+}
+
+/// Reads the input ahead on a thread, and waits for a threshold to be buffered before
+/// handing out any of it.
+pub struct Prebuffer {
+    shared: Arc<(Mutex<Buffer>, Condvar)>,
+}
+
+impl Prebuffer {
+    /// Starts reading `input` and returns once `threshold` bytes are buffered or the input ended.
+    pub fn fill(input: Box<dyn Read + Send>, threshold: usize) -> Self {
+        let shared = Arc::new((Mutex::new(Buffer::default()), Condvar::new()));
+        let capacity = (2 * threshold).max(MIN_CAPACITY);
+        let reader_shared = Arc::clone(&shared);
+        thread::spawn(move || read_ahead(input, reader_shared, capacity));
+
+        let (lock, changed) = &*shared;
+        let buffer = lock.lock().unwrap();
+        let buffer = changed.wait_while(buffer, |b| b.data.len() < threshold && b.end.is_none()).unwrap();
+        eprintln!("[!] prebuffered {} bytes", buffer.data.len());
+        drop(buffer);
+        Prebuffer { shared }
+    }
+}
+
+/// Keeps up to `capacity` bytes of `input` queued for [Prebuffer].
+fn read_ahead(mut input: Box<dyn Read + Send>, shared: Arc<(Mutex<Buffer>, Condvar)>, capacity: usize) {
+    let (lock, changed) = &*shared;
+    let mut chunk = vec![0; MIN_CAPACITY];
+    loop {
+        let room = {
+            let buffer = lock.lock().unwrap();
+            let buffer = changed.wait_while(buffer, |b| b.data.len() >= capacity).unwrap();
+            capacity - buffer.data.len()
+        };
+        let len = chunk.len().min(room);
+        let result = input.read(&mut chunk[..len]);
+        let mut buffer = lock.lock().unwrap();
+        match result {
+            Ok(0) => buffer.end = Some(None),
+            Ok(len) => buffer.data.extend(&chunk[..len]),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => buffer.end = Some(Some(e)),
+        }
+        changed.notify_all();
+        if buffer.end.is_some() {
+            return;
+        }
+    }
+}
+
+impl Read for Prebuffer {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let (lock, changed) = &*self.shared;
+        let buffer = lock.lock().unwrap();
+        let mut buffer = changed.wait_while(buffer, |b| b.data.is_empty() && b.end.is_none()).unwrap();
+        if buffer.data.is_empty() {
+            return match buffer.end.as_mut().and_then(Option::take) {
+                Some(e) => Err(e),
+                None => Ok(0),
+            };
+        }
+        let len = buf.len().min(buffer.data.len());
+        for (dst, src) in buf.iter_mut().zip(buffer.data.drain(..len)) {
+            *dst = src;
+        }
+        changed.notify_all();
+        Ok(len)
+    }
+}
@@ -0,0 +1,82 @@
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::path::Path;
+
+use crate::measure::{self, Capture};
+use crate::timecode::{self, Time};
+
+/// Bytes in an f32le sample of a take.
+const SAMPLE_BYTES: u64 = 4;
+
+/// Replaces the frames of `take` in `punch`, from the punch-in to the punch-out point, with the
+/// default input device, while playing the take from `pre_roll` before punch-in to cue the player.
+///
+/// The take is f32le with the input device's channels, as `measure` records it. The cue is
+/// the take mixed to mono on every channel of `output`, and the recording is aligned to it
+/// on the host's clock, so the replaced frames line up with the ones around them.
+pub fn run(
+    output: &cpal::Device,
+    sample_rate: u32,
+    channels: u16,
+    gain: f32,
+    take: &Path,
+    punch: Range<Time>,
+    pre_roll: Time,
+) -> Result<(), String> {
+    let (punch_in, punch_out) = (punch.start.frames(sample_rate), punch.end.frames(sample_rate));
+    let at = |frame| timecode::position(frame, sample_rate);
+    if punch_out <= punch_in {
+        return Err(format!("Punch-out at {} is not after punch-in at {}", at(punch_out), at(punch_in)));
+    }
+
+    let (_, input_channels) = measure::default_input()?;
+    let frame_bytes = input_channels as u64 * SAMPLE_BYTES;
+    let error = |e: std::io::Error| format!("{}: {e}", take.display());
+    let mut file = OpenOptions::new().read(true).write(true).open(take).map_err(error)?;
+    let len = file.metadata().map_err(error)?.len();
+    if len % frame_bytes != 0 {
+        return Err(format!(
+            "{} is not f32le with the input device's {input_channels} channels, its size is not a whole number of frames",
+            take.display(),
+        ));
+    }
+    if punch_out > len / frame_bytes {
+        return Err(format!("Punch-out at {} is past the end of the take at {}", at(punch_out), at(len / frame_bytes)));
+    }
+
+    let start = punch_in.saturating_sub(pre_roll.frames(sample_rate));
+    let cue = read_frames(&mut file, start * frame_bytes, punch_out - start, input_channels as usize)
+        .map_err(error)?
+        .chunks(input_channels as usize)
+        .map(|frame| frame.iter().sum::<f32>() / frame.len() as f32)
+        .collect::<Vec<_>>();
+
+    eprintln!(
+        "[!] playing the take from {}, recording from {} to {} ({} frames)",
+        at(start), at(punch_in), at(punch_out), punch_out - punch_in,
+    );
+    let Capture { response, input_channels: recorded_channels, .. } = measure::capture(output, sample_rate, channels, gain, &cue)?;
+    if recorded_channels != input_channels as usize {
+        return Err(format!("The input device changed from {input_channels} to {recorded_channels} channels"));
+    }
+
+    let offset = (punch_in - start) as usize * recorded_channels;
+    let replaced = &response[offset..offset + (punch_out - punch_in) as usize * recorded_channels];
+    let bytes = replaced.iter().flat_map(|s| s.to_le_bytes()).collect::<Vec<_>>();
+    file.seek(SeekFrom::Start(punch_in * frame_bytes))
+        .and_then(|_| file.write_all(&bytes))
+        .map_err(error)?;
+    eprintln!("[!] replaced {} to {} of {}", at(punch_in), at(punch_out), take.display());
+    Ok(())
+}
+
+/// Reads `frames` interleaved f32le frames of `channels` from `offset` in `file`.
+fn read_frames(file: &mut File, offset: u64, frames: u64, channels: usize) -> std::io::Result<Vec<f32>> {
+    let mut bytes = vec![0; frames as usize * channels * SAMPLE_BYTES as usize];
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut bytes)?;
+    Ok(bytes.chunks_exact(SAMPLE_BYTES as usize)
+        .map(|b| f32::from_le_bytes(b.try_into().unwrap()))
+        .collect())
+}
@@ -0,0 +1,200 @@
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+
+use crate::channel;
+use crate::devices;
+use crate::dither::Dither;
+use crate::resample::{Resampler, ResamplerKind};
+use crate::timecode;
+
+/// How often to look for an output device while there is none, and for a new default one.
+const RETRY: Duration = Duration::from_millis(500);
+
+/// The name ALSA gives its default output whichever device it is. Played through a sound server
+/// such as PipeWire or PulseAudio it follows a new default by itself, so its name is not watched.
+const ALSA_DEFAULT: &str = "default";
+
+/// Frames filled at a time for a device that plays another channel count or rate.
+const ADAPT_FRAMES: usize = 512;
+
+/// Plays `fill` on `device` in `config` and `format` with `dither`. When the device goes away, e.g. a USB DAC
+/// unplugged or a Bluetooth link dropped, waits up to `wait` for the device `selector` picks or the
+/// default one to open and resumes on it where playback stopped, in the channels, rate and format
+/// it takes. Without `selector`, playback also moves to the default output device when another one
+/// becomes the default, e.g. headphones plugged in. A change of default is seen as a change of its
+/// name, which hosts naming it after the device it is give, e.g. CoreAudio and WASAPI, but not ALSA.
+pub fn play(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    format: cpal::SampleFormat,
+    dither: Dither,
+    selector: Option<&str>,
+    wait: Duration,
+    mut fill: impl FnMut(&mut [f32], &cpal::OutputCallbackInfo) + Send + 'static,
+) -> Result<(), String> {
+    let mut device = device.clone();
+    let mut device_config = config.clone();
+    let mut device_format = format;
+    // when playback stopped, while it waits for a device
+    let mut lost_at = None;
+    let mut default = default_name();
+    let follow_default = selector.is_none() && default.as_deref() != Some(ALSA_DEFAULT);
+    loop {
+        let (lost_tx, lost_rx) = mpsc::channel();
+        // the input and processing state carry over from one stream to the next
+        let (mut lent, returned) = devices::lend(fill);
+        let mut adapt = Adapt::new(config, &device_config);
+        let stream = devices::build_output_stream(
+            &device,
+            &device_config,
+            device_format,
+            dither,
+            move |data, info| match &mut adapt {
+                Some(adapt) => adapt.fill(data, info, lent.get()),
+                None => lent.get()(data, info),
+            },
+            move |err| match err {
+                cpal::StreamError::DeviceNotAvailable => {
+                    let _ = lost_tx.send(());
+                },
+                err => eprintln!("an error occurred on stream: {}", err),
+            },
+        )
+        .map_err(|e| format!("{e}"))
+        .and_then(|stream| stream.play().map(|()| stream).map_err(|e| format!("{e}")));
+        let name = device.name().unwrap_or_else(|_| "the output device".into());
+        let stream = match (stream, lost_at) {
+            (Ok(stream), _) => stream,
+            (Err(e), None) => return Err(format!("failed to open the output device: {e}")),
+            // not ready yet, or gone again
+            (Err(e), Some(lost_at)) => {
+                eprintln!("[!] could not play on {name}: {e}");
+                fill = devices::take_back(&returned)?;
+                (device, device_config, device_format) = wait_for_device(selector, config, lost_at + wait)?;
+                continue;
+            },
+        };
+        if lost_at.take().is_some() {
+            eprintln!(
+                "[!] resumed on {name}, {} channels at {}Hz in {device_format}",
+                device_config.channels, device_config.sample_rate.0,
+            );
+        }
+
+        // a lost device stops the stream without ending it
+        let lost = loop {
+            match lost_rx.recv_timeout(RETRY) {
+                Err(mpsc::RecvTimeoutError::Timeout) if follow_default => {
+                    let name = default_name();
+                    if name.is_some() && name != default {
+                        default = name;
+                        break false;
+                    }
+                },
+                Err(mpsc::RecvTimeoutError::Timeout) => (),
+                _ => break true,
+            }
+        };
+        drop(stream);
+        fill = devices::take_back(&returned)?;
+        let now = Instant::now();
+        lost_at = Some(now);
+        (device, device_config, device_format) = if lost {
+            eprintln!("[!] lost the output device, waiting up to {} for one to play on", timecode::clock(wait));
+            wait_for_device(selector, config, now + wait)?
+        } else {
+            eprintln!("[!] the default output device changed, moving playback to it");
+            wait_for_device(None, config, now + wait)?
+        };
+    }
+}
+
+fn default_name() -> Option<String> {
+    cpal::default_host().default_output_device().and_then(|device| device.name().ok())
+}
+
+/// Waits until `deadline` for the device `selector` picks to open, and negotiates the channels,
+/// rate and sample format nearest `config` that it plays.
+fn wait_for_device(
+    selector: Option<&str>,
+    config: &cpal::StreamConfig,
+    deadline: Instant,
+) -> Result<(cpal::Device, cpal::StreamConfig, cpal::SampleFormat), String> {
+    loop {
+        if Instant::now() >= deadline {
+            return Err("no output device to play on, giving up".into());
+        }
+        thread::sleep(RETRY);
+        if let Ok(device) = devices::output_device(selector)
+            && device.default_output_config().is_ok()
+        {
+            let (channels, rate) = devices::negotiate(&device, config.channels, config.sample_rate.0);
+            let format = devices::output_format(&device, channels, rate);
+            let device_config = cpal::StreamConfig {
+                channels,
+                sample_rate: cpal::SampleRate(rate),
+                buffer_size: config.buffer_size,
+            };
+            return Ok((device, device_config, format));
+        }
+    }
+}
+
+/// Plays frames filled in the channels and rate playback started in on a device that took others,
+/// fitting their channels and resampling them.
+struct Adapt {
+    /// channels of the filled frames
+    channels: usize,
+    device_channels: usize,
+    resampler: Option<Box<dyn Resampler>>,
+    filled: Vec<f32>,
+    /// samples of `filled` played
+    position: usize,
+}
+
+impl Adapt {
+    /// None when the device plays `config` as it is.
+    fn new(config: &cpal::StreamConfig, device_config: &cpal::StreamConfig) -> Option<Self> {
+        let (channels, rate) = (config.channels as usize, config.sample_rate.0);
+        let (device_channels, device_rate) = (device_config.channels as usize, device_config.sample_rate.0);
+        if (channels, rate) == (device_channels, device_rate) {
+            return None;
+        }
+        Some(Adapt {
+            channels,
+            device_channels,
+            resampler: (rate != device_rate)
+                .then(|| ResamplerKind::Sinc.build(device_channels, rate, device_rate)),
+            filled: vec![0.0; ADAPT_FRAMES * channels],
+            position: ADAPT_FRAMES * channels,
+        })
+    }
+
+    fn fill(
+        &mut self,
+        data: &mut [f32],
+        info: &cpal::OutputCallbackInfo,
+        fill: &mut impl FnMut(&mut [f32], &cpal::OutputCallbackInfo),
+    ) {
+        let Adapt { channels, filled, position, .. } = self;
+        let mut next = |frame: &mut [f32]| {
+            if *position == filled.len() {
+                fill(filled, info);
+                *position = 0;
+            }
+            channel::fit_frame(&filled[*position..*position + *channels], frame);
+            *position += *channels;
+            Ok(())
+        };
+        for frame in data.chunks_mut(self.device_channels) {
+            // filling never fails
+            let _ = match &mut self.resampler {
+                Some(resampler) => resampler.next_frame(frame, &mut next),
+                None => next(frame),
+            };
+        }
+    }
+}
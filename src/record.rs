@@ -0,0 +1,56 @@
+use std::io::Read;
+use std::path::Path;
+use std::time::Duration;
+
+use cpal::traits::DeviceTrait;
+
+use crate::capture::CaptureSource;
+use crate::devices;
+use crate::header::StreamHeader;
+use crate::interrupt;
+use crate::sink::{Encoder, FileSink};
+use crate::tap::{Tap, TapWriter};
+use crate::timecode;
+
+/// Records the input device `selector` picks, or the default one, into `path` in the format of
+/// `spec` through `encoder`, for `duration` or until interrupted.
+pub fn run(
+    selector: Option<&str>,
+    spec: StreamHeader,
+    encoder: Box<dyn Encoder>,
+    path: &Path,
+    duration: Option<Duration>,
+) -> Result<(), String> {
+    if !spec.float && spec.sample_size > 32 {
+        return Err(format!("record writes integer samples of up to 32 bits, not {}", spec.sample_size));
+    }
+    let device = devices::input_device(selector)?;
+    let name = device.name().unwrap_or_else(|_| "the input device".into());
+    let config = cpal::StreamConfig {
+        channels: spec.channels,
+        sample_rate: cpal::SampleRate(spec.sample_rate),
+        buffer_size: cpal::BufferSize::Default,
+    };
+    let mut source = CaptureSource::open(device, config, None)?;
+    let sink = FileSink::create(path).map_err(|e| format!("{}: {e}", path.display()))?;
+    let mut writer = TapWriter::new(Tap::Post, encoder, spec, Box::new(sink)).map_err(|e| format!("{e}"))?;
+    let limit = duration.map(|d| (d.as_secs_f64() * spec.sample_rate as f64).round() as u64 * spec.channels as u64);
+    // SIGINT and SIGTERM end the recording where it is, so the file is finalized
+    interrupt::catch();
+    match duration {
+        Some(duration) => eprintln!("[!] recording {name} for {}", timecode::clock(duration)),
+        None => eprintln!("[!] recording {name}, interrupt to stop"),
+    }
+
+    let mut sample = [0u8; 4];
+    let mut samples = 0;
+    while limit.is_none_or(|limit| samples < limit) && !interrupt::caught() {
+        source.read_exact(&mut sample).map_err(|_| format!("{name} stopped recording"))?;
+        writer.write_recorded(f32::from_le_bytes(sample)).map_err(|e| format!("{e}"))?;
+        samples += 1;
+    }
+    writer.finish().map_err(|e| format!("{e}"))?;
+    let frames = samples / spec.channels as u64;
+    eprintln!("[!] recorded {}", timecode::position(frames, spec.sample_rate));
+    Ok(())
+}
@@ -0,0 +1,167 @@
+use std::collections::VecDeque;
+use std::f64::consts::PI;
+use std::io;
+
+use clap::ValueEnum;
+
+/// Kernel values tabulated per zero crossing, looked up with linear interpolation.
+const SINC_RESOLUTION: usize = 512;
+
+/// Resampling algorithms, from cheapest to cleanest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ResamplerKind {
+    /// Linear interpolation between neighbouring frames, aliases and dulls the top octave
+    Linear,
+    /// Blackman windowed sinc over 16 zero crossings, band-limited to 90% of Nyquist
+    Fast,
+    /// Blackman windowed sinc over 32 zero crossings, band-limited to 95% of Nyquist
+    Sinc,
+    /// Blackman windowed sinc over 128 zero crossings, band-limited to 98% of Nyquist. Four
+    /// times the CPU of sinc, about 130 multiply-adds per output sample and channel and more
+    /// when downsampling
+    Best,
+}
+
+impl ResamplerKind {
+    pub fn build(self, channels: usize, from: u32, to: u32) -> Box<dyn Resampler> {
+        let step = from as f64 / to as f64;
+        match self {
+            ResamplerKind::Linear => Box::new(Linear {
+                step,
+                position: 0.0,
+                frames: [vec![0.0; channels], vec![0.0; channels]],
+                primed: false,
+            }),
+            ResamplerKind::Fast => Box::new(Sinc::new(channels, step, 8, 0.90)),
+            ResamplerKind::Sinc => Box::new(Sinc::new(channels, step, 16, 0.95)),
+            ResamplerKind::Best => Box::new(Sinc::new(channels, step, 64, 0.98)),
+        }
+    }
+}
+
+/// Converts frames at one rate into frames at another.
+pub trait Resampler: Send {
+    /// Writes the next output frame, pulling input frames from `input` as needed.
+    fn next_frame(&mut self, output: &mut [f32], input: &mut dyn FnMut(&mut [f32]) -> io::Result<()>) -> io::Result<()>;
+
+    /// Input frames read ahead of the output.
+    fn latency(&self) -> usize;
+}
+
+struct Linear {
+    /// input frames per output frame
+    step: f64,
+    /// position of the next output frame between `frames`
+    position: f64,
+    frames: [Vec<f32>; 2],
+    primed: bool,
+}
+
+impl Resampler for Linear {
+    fn next_frame(&mut self, output: &mut [f32], input: &mut dyn FnMut(&mut [f32]) -> io::Result<()>) -> io::Result<()> {
+        if !self.primed {
+            input(&mut self.frames[0])?;
+            input(&mut self.frames[1])?;
+            self.primed = true;
+        }
+        while self.position >= 1.0 {
+            self.frames.swap(0, 1);
+            input(&mut self.frames[1])?;
+            self.position -= 1.0;
+        }
+        let t = self.position as f32;
+        for (c, sample) in output.iter_mut().enumerate() {
+            *sample = self.frames[0][c] + t * (self.frames[1][c] - self.frames[0][c]);
+        }
+        self.position += self.step;
+        Ok(())
+    }
+
+    fn latency(&self) -> usize {
+        1
+    }
+}
+
+struct Sinc {
+    step: f64,
+    /// kernel scale, below 1 when downsampling to move the cutoff under the output's Nyquist frequency
+    cutoff: f64,
+    /// windowed sinc from its centre outwards, `SINC_RESOLUTION` values per zero crossing
+    table: Vec<f64>,
+    /// input frames around the output position, the oldest first
+    history: VecDeque<Vec<f32>>,
+    /// frames dropped from `history`, reused to keep allocations out of the audio callback
+    spare: Vec<Vec<f32>>,
+    /// position of the next output frame, in input frames from the start of `history`
+    position: f64,
+    /// frames read ahead of the output
+    half: usize,
+}
+
+impl Sinc {
+    /// A kernel of `zeros` crossings on each side of its centre, passing `passband` of the lower
+    /// Nyquist frequency, the rest of it is the transition band.
+    fn new(channels: usize, step: f64, zeros: usize, passband: f64) -> Self {
+        let cutoff = passband * step.recip().min(1.0);
+        // wide enough to keep `zeros` crossings of the scaled kernel
+        let half = (zeros as f64 / cutoff).ceil() as usize;
+        let table = (0..=zeros * SINC_RESOLUTION + 1)
+            .map(|i| {
+                let x = i as f64 / SINC_RESOLUTION as f64;
+                let sinc = if i == 0 { 1.0 } else { (PI * x).sin() / (PI * x) };
+                // Blackman window over the kernel's width
+                let w = PI * (x / zeros as f64 + 1.0);
+                let window = if x >= zeros as f64 { 0.0 } else { 0.42 - 0.5 * w.cos() + 0.08 * (2.0 * w).cos() };
+                sinc * window
+            })
+            .collect();
+        Sinc {
+            step,
+            cutoff,
+            table,
+            history: (0..half).map(|_| vec![0.0; channels]).collect(),
+            spare: Vec::new(),
+            // at the first input frame, after `half` frames of silence
+            position: half as f64,
+            half,
+        }
+    }
+
+    fn kernel(&self, distance: f64) -> f64 {
+        let x = distance.abs() * self.cutoff * SINC_RESOLUTION as f64;
+        let i = x as usize;
+        match (self.table.get(i), self.table.get(i + 1)) {
+            (Some(a), Some(b)) => a + (x - i as f64) * (b - a),
+            _ => 0.0,
+        }
+    }
+}
+
+impl Resampler for Sinc {
+    fn next_frame(&mut self, output: &mut [f32], input: &mut dyn FnMut(&mut [f32]) -> io::Result<()>) -> io::Result<()> {
+        // frames that fell out of reach of the kernel
+        while self.position >= self.half as f64 {
+            self.spare.extend(self.history.pop_front());
+            self.position -= 1.0;
+        }
+        let last = self.position as usize + self.half;
+        while self.history.len() <= last {
+            let mut frame = self.spare.pop().unwrap_or_else(|| vec![0.0; output.len()]);
+            input(&mut frame)?;
+            self.history.push_back(frame);
+        }
+        output.fill(0.0);
+        for (k, frame) in self.history.iter().enumerate() {
+            let weight = (self.kernel(self.position - k as f64) * self.cutoff) as f32;
+            for (sample, value) in output.iter_mut().zip(frame) {
+                *sample += weight * value;
+            }
+        }
+        self.position += self.step;
+        Ok(())
+    }
+
+    fn latency(&self) -> usize {
+        self.half
+    }
+}
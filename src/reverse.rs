@@ -0,0 +1,53 @@
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// Frames read from the file at a time, stepping back through it.
+const CHUNK_FRAMES: u64 = 4096;
+
+/// Reads the frames of a region of a file from the last to the first.
+pub struct Reverse {
+    file: File,
+    start: u64,
+    /// end of the frames not read yet
+    position: u64,
+    frame_len: usize,
+    /// frames of the last chunk read, in reverse order
+    chunk: Vec<u8>,
+    /// bytes of `chunk` handed out
+    consumed: usize,
+}
+
+impl Reverse {
+    /// Opens `path` to read the whole frames of `frame_len` bytes from `start` up to `end` backwards.
+    pub fn open(path: &Path, start: u64, end: u64, frame_len: usize) -> io::Result<Self> {
+        let file = File::open(path)?;
+        let end = end.min(file.metadata()?.len()).max(start);
+        let position = start + (end - start) / frame_len as u64 * frame_len as u64;
+        Ok(Reverse { file, start, position, frame_len, chunk: Vec::new(), consumed: 0 })
+    }
+}
+
+impl Read for Reverse {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.consumed == self.chunk.len() {
+            let len = (self.position - self.start).min(CHUNK_FRAMES * self.frame_len as u64);
+            if len == 0 {
+                return Ok(0);
+            }
+            self.position -= len;
+            let mut frames = vec![0; len as usize];
+            self.file.seek(SeekFrom::Start(self.position))?;
+            self.file.read_exact(&mut frames)?;
+            self.chunk.clear();
+            for frame in frames.chunks_exact(self.frame_len).rev() {
+                self.chunk.extend_from_slice(frame);
+            }
+            self.consumed = 0;
+        }
+        let len = buf.len().min(self.chunk.len() - self.consumed);
+        buf[..len].copy_from_slice(&self.chunk[self.consumed..self.consumed + len]);
+        self.consumed += len;
+        Ok(len)
+    }
+}
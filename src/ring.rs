@@ -0,0 +1,119 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
+/// Samples shared by the two ends of a ring, as the bits of f32s.
+struct Shared {
+    samples: Box<[AtomicU32]>,
+    /// samples written and read since the start, their difference is what the ring holds
+    written: AtomicUsize,
+    read: AtomicUsize,
+}
+
+/// Creates a ring of `capacity` samples passing audio from one thread to another without locks
+/// or allocation, e.g. between the callbacks of two devices.
+pub fn ring(capacity: usize) -> (Writer, Reader) {
+    let shared = Arc::new(Shared {
+        samples: (0..capacity.max(1)).map(|_| AtomicU32::new(0)).collect(),
+        written: AtomicUsize::new(0),
+        read: AtomicUsize::new(0),
+    });
+    (Writer(shared.clone()), Reader(shared))
+}
+
+/// The end of a [ring] that writes.
+pub struct Writer(Arc<Shared>);
+
+impl Writer {
+    /// Writes as much of `data` as fits in whole frames of `channels` samples, and returns how
+    /// many samples it wrote.
+    pub fn write(&mut self, data: &[f32], channels: usize) -> usize {
+        let shared = &self.0;
+        let capacity = shared.samples.len();
+        let written = shared.written.load(Ordering::Relaxed);
+        let free = capacity - written.wrapping_sub(shared.read.load(Ordering::Acquire));
+        let len = data.len().min(free) / channels * channels;
+        for (i, sample) in data[..len].iter().enumerate() {
+            shared.samples[written.wrapping_add(i) % capacity].store(sample.to_bits(), Ordering::Relaxed);
+        }
+        shared.written.store(written.wrapping_add(len), Ordering::Release);
+        len
+    }
+}
+
+/// The end of a [ring] that reads.
+pub struct Reader(Arc<Shared>);
+
+impl Reader {
+    /// Reads what was written into `data`, up to its length, and returns how many samples it read.
+    pub fn read(&mut self, data: &mut [f32]) -> usize {
+        let shared = &self.0;
+        let capacity = shared.samples.len();
+        let read = shared.read.load(Ordering::Relaxed);
+        let available = shared.written.load(Ordering::Acquire).wrapping_sub(read);
+        let len = data.len().min(available);
+        for (i, sample) in data[..len].iter_mut().enumerate() {
+            *sample = f32::from_bits(shared.samples[read.wrapping_add(i) % capacity].load(Ordering::Relaxed));
+        }
+        shared.read.store(read.wrapping_add(len), Ordering::Release);
+        len
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_nothing_when_empty() {
+        let (_, mut reader) = ring(8);
+        assert_eq!(reader.read(&mut [0.0; 4]), 0);
+    }
+
+    #[test]
+    fn writes_whole_frames_that_fit() {
+        let (mut writer, mut reader) = ring(7);
+        // three stereo frames fit in seven samples
+        assert_eq!(writer.write(&[1.0; 8], 2), 6);
+        assert_eq!(writer.write(&[1.0; 2], 2), 0);
+        let mut data = [0.0; 8];
+        assert_eq!(reader.read(&mut data), 6);
+        assert_eq!(data, [1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn wraps_around() {
+        let (mut writer, mut reader) = ring(5);
+        let mut data = [0.0; 5];
+        for pass in 0..10 {
+            let samples = [pass as f32, pass as f32 + 0.5, -(pass as f32)];
+            assert_eq!(writer.write(&samples, 1), 3);
+            assert_eq!(reader.read(&mut data), 3);
+            assert_eq!(data[..3], samples);
+        }
+    }
+
+    #[test]
+    fn keeps_order_across_threads() {
+        const SAMPLES: usize = 100_000;
+        let (mut writer, mut reader) = ring(64);
+        let writing = std::thread::spawn(move || {
+            let mut next = 0;
+            while next < SAMPLES {
+                let chunk = (next..SAMPLES.min(next + 10)).map(|i| i as f32).collect::<Vec<_>>();
+                next += writer.write(&chunk, 1);
+                std::thread::yield_now();
+            }
+        });
+        let mut expected = 0;
+        let mut data = [0.0; 16];
+        while expected < SAMPLES {
+            let len = reader.read(&mut data);
+            for &sample in &data[..len] {
+                assert_eq!(sample, expected as f32);
+                expected += 1;
+            }
+            std::thread::yield_now();
+        }
+        writing.join().unwrap();
+    }
+}
@@ -0,0 +1,403 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use clap::ValueEnum;
+
+use crate::net;
+use crate::sink::Sink;
+
+/// RTP version 2, no padding, extension or contributing sources
+const RTP_VERSION: u8 = 0x80;
+/// first dynamic payload type, as AES67 senders use for L16/L24
+pub const PAYLOAD_TYPE: u8 = 96;
+pub const HEADER_LEN: usize = 12;
+/// longest the sender is allowed to fall behind before old audio is dropped
+const MAX_BUFFERED: Duration = Duration::from_secs(1);
+
+/// Linear PCM payload formats for RTP.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum RtpFormat {
+    /// 16 bit big-endian
+    L16,
+    /// 24 bit big-endian
+    L24,
+}
+
+impl RtpFormat {
+    pub fn sample_size(self) -> u32 {
+        match self {
+            RtpFormat::L16 => 16,
+            RtpFormat::L24 => 24,
+        }
+    }
+}
+
+struct Shared {
+    buffer: VecDeque<u8>,
+    finished: bool,
+}
+
+/// Sends bytes written to it as RTP packets of a fixed packet time,
+/// paced by the system clock on a thread of its own.
+pub struct RtpSink {
+    shared: Arc<Mutex<Shared>>,
+    sender: Option<thread::JoinHandle<()>>,
+    frame_len: usize,
+    max_buffered: usize,
+}
+
+impl RtpSink {
+    pub fn new(
+        addr: SocketAddr,
+        sample_rate: u32,
+        frame_len: usize,
+        packet_time: Duration,
+    ) -> io::Result<Self> {
+        let bind_addr: SocketAddr = if addr.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" }
+            .parse()
+            .unwrap();
+        let socket = UdpSocket::bind(bind_addr)?;
+        if addr.ip().is_multicast() && addr.is_ipv4() {
+            // keep test material inside the local network segment
+            socket.set_multicast_ttl_v4(1)?;
+        }
+        socket.connect(addr)?;
+
+        let frames_per_packet = ((sample_rate as f64 * packet_time.as_secs_f64()).round() as usize).max(1);
+        let packet_time = Duration::from_secs_f64(frames_per_packet as f64 / sample_rate as f64);
+        let max_buffered = MAX_BUFFERED.as_secs_f64() * sample_rate as f64 * frame_len as f64;
+
+        let shared = Arc::new(Mutex::new(Shared { buffer: VecDeque::new(), finished: false }));
+        let sender_shared = Arc::clone(&shared);
+        let sender = thread::spawn(move || {
+            send_packets(socket, sender_shared, frame_len, frames_per_packet, packet_time)
+        });
+
+        Ok(RtpSink {
+            shared,
+            sender: Some(sender),
+            frame_len,
+            max_buffered: max_buffered as usize,
+        })
+    }
+}
+
+impl Write for RtpSink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut shared = self.shared.lock().unwrap();
+        shared.buffer.extend(buf);
+        let excess = shared.buffer.len().saturating_sub(self.max_buffered);
+        let excess = (excess.div_ceil(self.frame_len) * self.frame_len).min(shared.buffer.len());
+        shared.buffer.drain(..excess);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Sink for RtpSink {
+    /// Sends what is still buffered, then stops the sender.
+    fn finish(&mut self, _header: Option<&[u8]>) -> io::Result<()> {
+        self.shared.lock().unwrap().finished = true;
+        if let Some(sender) = self.sender.take() {
+            sender.join().map_err(|_| io::Error::other("RTP sender panicked"))?;
+        }
+        Ok(())
+    }
+}
+
+/// Sends one packet per `packet_time`, filling with silence when no audio is buffered
+/// so receivers see an uninterrupted stream.
+fn send_packets(
+    socket: UdpSocket,
+    shared: Arc<Mutex<Shared>>,
+    frame_len: usize,
+    frames_per_packet: usize,
+    packet_time: Duration,
+) {
+    let payload_len = frames_per_packet * frame_len;
+    let seed = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .subsec_nanos() ^ std::process::id();
+    let ssrc = seed.rotate_left(16);
+    let mut sequence = seed as u16;
+    let mut timestamp = seed.wrapping_mul(2_654_435_761);
+
+    let mut packet = vec![0u8; HEADER_LEN + payload_len];
+    packet[0] = RTP_VERSION;
+    packet[1] = PAYLOAD_TYPE;
+    packet[8..12].copy_from_slice(&ssrc.to_be_bytes());
+
+    let mut next = Instant::now();
+    loop {
+        {
+            let mut shared = shared.lock().unwrap();
+            if shared.finished && shared.buffer.len() < frame_len {
+                return;
+            }
+            // whole frames only, so a partially written frame stays aligned for the next packet
+            let available = shared.buffer.len().min(payload_len) / frame_len * frame_len;
+            for (dst, src) in packet[HEADER_LEN..].iter_mut().zip(shared.buffer.drain(..available)) {
+                *dst = src;
+            }
+            packet[HEADER_LEN + available..].fill(0);
+        }
+
+        packet[2..4].copy_from_slice(&sequence.to_be_bytes());
+        packet[4..8].copy_from_slice(&timestamp.to_be_bytes());
+        if let Err(e) = socket.send(&packet) {
+            eprintln!("[!] RTP send failed: {e}");
+        }
+        sequence = sequence.wrapping_add(1);
+        timestamp = timestamp.wrapping_add(frames_per_packet as u32);
+
+        next += packet_time;
+        if let Some(wait) = next.checked_duration_since(Instant::now()) {
+            thread::sleep(wait);
+        }
+    }
+}
+
+/// Longest a missing packet is waited for, in packets, before it is played as silence.
+const MAX_REORDER: usize = 64;
+/// Sequence numbers further ahead than this restart the stream instead of counting as lost packets.
+const MAX_SEQUENCE_JUMP: i16 = 1000;
+const MAX_PACKET_LEN: usize = 1500;
+/// Longest timestamp gap over lost packets that is filled with silence, in seconds of audio.
+const MAX_GAP: u32 = 1;
+
+/// Header fields of an RTP packet.
+struct RtpHeader {
+    sequence: u16,
+    timestamp: u32,
+    payload_type: u8,
+}
+
+/// Sample rate and channels of the static L16 payload types of RFC 3551.
+fn static_format(payload_type: u8) -> Option<(u32, usize)> {
+    match payload_type {
+        10 => Some((44_100, 2)),
+        11 => Some((44_100, 1)),
+        _ => None,
+    }
+}
+
+struct Received {
+    /// timestamps and payloads by sequence number
+    packets: HashMap<u16, (u32, Vec<u8>)>,
+    /// sequence number of the next packet to play
+    next: Option<u16>,
+    /// timestamp that follows the last packet played
+    timestamp: Option<u32>,
+    /// packets given up on since the last packet played
+    lost: usize,
+    /// frames of audio held before playback starts, packets are reordered within them
+    depth: usize,
+    buffered_frames: usize,
+}
+
+/// Receives a linear PCM RTP stream and reads it as big-endian samples, best effort
+/// without PTP: packets are reordered in a small jitter buffer and lost ones played as silence.
+pub struct RtpSource {
+    shared: Arc<(Mutex<Received>, Condvar)>,
+    frame_len: usize,
+    packet: Vec<u8>,
+    position: usize,
+    /// packet to play after the silence standing in for lost ones
+    pending: Option<Vec<u8>>,
+    max_gap: u32,
+    /// bytes read so far, to keep silence aligned to frames
+    read: usize,
+    primed: bool,
+}
+
+impl RtpSource {
+    pub fn new(addr: SocketAddr, sample_rate: u32, frame_len: usize, jitter: Duration) -> io::Result<Self> {
+        let socket = net::bind_receiver(addr)?;
+
+        let depth = (sample_rate as f64 * jitter.as_secs_f64()).round() as usize;
+        let shared = Arc::new((
+            Mutex::new(Received { packets: HashMap::new(), next: None, timestamp: None, lost: 0, depth, buffered_frames: 0 }),
+            Condvar::new(),
+        ));
+        let receiver_shared = Arc::clone(&shared);
+        thread::spawn(move || receive_packets(socket, receiver_shared, sample_rate, frame_len));
+
+        Ok(RtpSource {
+            shared,
+            frame_len,
+            packet: Vec::new(),
+            position: 0,
+            pending: None,
+            max_gap: MAX_GAP * sample_rate,
+            read: 0,
+            primed: false,
+        })
+    }
+
+    /// Takes the next packet off the jitter buffer, a packet of silence when it was lost.
+    /// None when the buffer ran dry.
+    fn next_packet(&mut self) -> Option<Vec<u8>> {
+        if let Some(packet) = self.pending.take() {
+            return Some(packet);
+        }
+        let (lock, ready) = &*self.shared;
+        let mut received = lock.lock().unwrap();
+        if !self.primed {
+            // wait for the stream to fill the jitter buffer before starting
+            received = ready.wait_while(received, |r| r.next.is_none() || r.buffered_frames < r.depth.max(1)).unwrap();
+            self.primed = true;
+        }
+
+        loop {
+            let next = received.next?;
+            if let Some((timestamp, payload)) = received.packets.remove(&next) {
+                let frames = payload.len() / self.frame_len;
+                received.next = Some(next.wrapping_add(1));
+                received.buffered_frames -= frames;
+                let expected = received.timestamp.replace(timestamp.wrapping_add(frames as u32));
+                let lost = std::mem::take(&mut received.lost);
+                if lost == 0 {
+                    return Some(payload);
+                }
+                // the timestamp gap is how much audio the lost packets held,
+                // unless the sender restarted its clock
+                let gap = expected.map_or(0, |expected| timestamp.wrapping_sub(expected) as i32);
+                let gap = if 0 < gap && gap as u32 <= self.max_gap { gap as usize } else { lost * frames };
+                self.pending = Some(payload);
+                return Some(vec![0; gap * self.frame_len]);
+            }
+            if received.packets.is_empty()
+                || (received.packets.len() < MAX_REORDER && received.buffered_frames < received.depth) {
+                return None;
+            }
+            // later packets are waiting, so this one is lost
+            received.next = Some(next.wrapping_add(1));
+            received.lost += 1;
+        }
+    }
+}
+
+impl Read for RtpSource {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position == self.packet.len() && self.read.is_multiple_of(self.frame_len)
+            && let Some(packet) = self.next_packet() {
+            self.packet = packet;
+            self.position = 0;
+        }
+
+        let len = if self.position < self.packet.len() {
+            let len = buf.len().min(self.packet.len() - self.position);
+            buf[..len].copy_from_slice(&self.packet[self.position..self.position + len]);
+            self.position += len;
+            len
+        } else {
+            // underrun, keep the device fed with silence that ends on a frame boundary
+            let to_boundary = (self.frame_len - self.read % self.frame_len) % self.frame_len;
+            let len = if to_boundary > 0 { to_boundary } else { self.frame_len.max(buf.len() / self.frame_len * self.frame_len) };
+            let len = len.min(buf.len());
+            buf[..len].fill(0);
+            len
+        };
+        self.read += len;
+        Ok(len)
+    }
+}
+
+/// Validates RTP packets and queues their payload for [RtpSource].
+/// Plays the first L16/L24 payload type received, other payload types
+/// such as comfort noise or telephone events are ignored.
+fn receive_packets(
+    socket: UdpSocket,
+    shared: Arc<(Mutex<Received>, Condvar)>,
+    sample_rate: u32,
+    frame_len: usize,
+) {
+    let (lock, ready) = &*shared;
+    let mut buf = [0u8; MAX_PACKET_LEN];
+    let mut payload_type = None;
+    let mut ignored = HashSet::new();
+    loop {
+        let len = match socket.recv(&mut buf) {
+            Ok(len) => len,
+            Err(e) => {
+                eprintln!("[!] RTP receive failed: {e}");
+                continue;
+            },
+        };
+        let Some((header, payload)) = parse_packet(&buf[..len]) else {
+            continue;
+        };
+        if payload_type.is_none() && matches!(header.payload_type, 10 | 11 | 96..=127) {
+            payload_type = Some(header.payload_type);
+            if let Some((rate, channels)) = static_format(header.payload_type)
+                && (rate != sample_rate || channels * 2 != frame_len) {
+                eprintln!(
+                    "[!] RTP payload type {} is L16 with {channels} channels at {rate} Hz, play it with -r {rate} -c {channels} --rtp-format l16",
+                    header.payload_type,
+                );
+            }
+        }
+        if payload_type != Some(header.payload_type) {
+            if ignored.insert(header.payload_type) {
+                eprintln!("[!] ignoring RTP payload type {}", header.payload_type);
+            }
+            continue;
+        }
+        let sequence = header.sequence;
+        if payload.is_empty() || !payload.len().is_multiple_of(frame_len) {
+            continue;
+        }
+
+        let mut received = lock.lock().unwrap();
+        let next = *received.next.get_or_insert(sequence);
+        match sequence.wrapping_sub(next) as i16 {
+            // arrived after its turn was played
+            ..0 => continue,
+            0..MAX_SEQUENCE_JUMP => (),
+            _ => {
+                eprintln!("[!] RTP stream jumped to sequence number {sequence}, restarting");
+                received.packets.clear();
+                received.buffered_frames = 0;
+                received.next = Some(sequence);
+                received.timestamp = None;
+                received.lost = 0;
+            },
+        }
+        let frames = payload.len() / frame_len;
+        if received.packets.insert(sequence, (header.timestamp, payload.to_vec())).is_none() {
+            received.buffered_frames += frames;
+        }
+        ready.notify_one();
+    }
+}
+
+/// Header and payload of an RTP packet.
+fn parse_packet(packet: &[u8]) -> Option<(RtpHeader, &[u8])> {
+    if packet.len() < HEADER_LEN || packet[0] & 0xC0 != RTP_VERSION {
+        return None;
+    }
+    let csrc_count = (packet[0] & 0x0F) as usize;
+    let header = RtpHeader {
+        sequence: u16::from_be_bytes([packet[2], packet[3]]),
+        timestamp: u32::from_be_bytes(packet[4..8].try_into().unwrap()),
+        payload_type: packet[1] & 0x7F,
+    };
+    let mut start = HEADER_LEN + 4 * csrc_count;
+    if packet[0] & 0x10 != 0 {
+        let extension = packet.get(start..start + 4)?;
+        start += 4 + 4 * u16::from_be_bytes([extension[2], extension[3]]) as usize;
+    }
+    let mut end = packet.len();
+    if packet[0] & 0x20 != 0 {
+        end = end.checked_sub(*packet.last()? as usize)?;
+    }
+    Some((header, packet.get(start..end)?))
+}
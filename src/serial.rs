@@ -0,0 +1,80 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Opens the serial device at `path` raw at `baud`, 8 data bits without parity or flow
+/// control, so the samples are read as they were sent.
+#[cfg(unix)]
+pub fn open(path: &Path, baud: u32) -> io::Result<fs::File> {
+    use std::os::fd::AsRawFd;
+    use std::os::unix::fs::OpenOptionsExt;
+
+    let speed = speed(baud).ok_or_else(|| io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("unsupported baud rate {baud}, supported rates are {}", supported().join(", ")),
+    ))?;
+    let device = fs::File::options()
+        .read(true)
+        .custom_flags(libc::O_NOCTTY)
+        .open(path)?;
+    let fd = device.as_raw_fd();
+    let mut termios = unsafe { std::mem::zeroed::<libc::termios>() };
+    if unsafe { libc::tcgetattr(fd, &mut termios) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    unsafe { libc::cfmakeraw(&mut termios) };
+    termios.c_cflag |= libc::CREAD | libc::CLOCAL;
+    termios.c_cflag &= !(libc::CSTOPB | libc::CRTSCTS);
+    termios.c_iflag &= !(libc::IXON | libc::IXOFF);
+    termios.c_cc[libc::VMIN] = 1;
+    termios.c_cc[libc::VTIME] = 0;
+    if unsafe { libc::cfsetispeed(&mut termios, speed) } != 0 || unsafe { libc::cfsetospeed(&mut termios, speed) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    if unsafe { libc::tcsetattr(fd, libc::TCSANOW, &termios) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // bytes that arrived before the line was set up are at the wrong speed
+    unsafe { libc::tcflush(fd, libc::TCIFLUSH) };
+    Ok(device)
+}
+
+#[cfg(not(unix))]
+pub fn open(_path: &Path, _baud: u32) -> io::Result<fs::File> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+/// The termios speed of a baud rate.
+#[cfg(unix)]
+fn speed(baud: u32) -> Option<libc::speed_t> {
+    SPEEDS.iter().find(|(rate, _)| *rate == baud).map(|(_, speed)| *speed)
+}
+
+#[cfg(unix)]
+fn supported() -> Vec<String> {
+    SPEEDS.iter().map(|(rate, _)| rate.to_string()).collect()
+}
+
+#[cfg(unix)]
+const SPEEDS: &[(u32, libc::speed_t)] = &[
+    (9600, libc::B9600),
+    (19200, libc::B19200),
+    (38400, libc::B38400),
+    (57600, libc::B57600),
+    (115200, libc::B115200),
+    (230400, libc::B230400),
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    (460800, libc::B460800),
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    (921600, libc::B921600),
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    (1000000, libc::B1000000),
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    (1500000, libc::B1500000),
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    (2000000, libc::B2000000),
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    (3000000, libc::B3000000),
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    (4000000, libc::B4000000),
+];
@@ -0,0 +1,126 @@
+use std::fs;
+use std::io::{self, Seek, Write};
+use std::path::Path;
+
+use crate::header::StreamHeader;
+
+/// Byte level container around a stream of samples.
+pub trait Encoder: Send {
+    /// Checks that samples described by `spec` can be stored by this encoder.
+    fn check(&self, spec: &StreamHeader) -> Result<(), String>;
+
+    /// Bytes that precede the samples, `data_len` is None while it is unknown.
+    fn header(&self, spec: &StreamHeader, data_len: Option<u64>) -> Vec<u8>;
+
+    /// Byte order the container fixes for samples, None to keep the byte order of `spec`.
+    fn big_endian(&self) -> Option<bool> {
+        None
+    }
+
+    /// Whether the header changes once the data length is known.
+    fn rewrites_header(&self) -> bool {
+        false
+    }
+
+    /// Sink the samples go through after the header, for encoders that transform them.
+    fn wrap(&self, _spec: &StreamHeader, sink: Box<dyn Sink>) -> Box<dyn Sink> {
+        sink
+    }
+}
+
+/// Destination for encoded bytes.
+pub trait Sink: Write + Send {
+    /// Flushes the sink and, where the sink is able to, overwrites the start of
+    /// what was written with `header`.
+    fn finish(&mut self, header: Option<&[u8]>) -> io::Result<()>;
+}
+
+/// Writes to stdout, rewriting headers in place when stdout is a regular file.
+pub struct StdoutSink {
+    stdout: io::Stdout,
+    /// stdout as a regular file and where the sink started in it
+    seekable: Option<(fs::File, u64)>,
+}
+
+impl StdoutSink {
+    pub fn new() -> Self {
+        StdoutSink {
+            stdout: io::stdout(),
+            seekable: seekable_stdout(),
+        }
+    }
+}
+
+impl Write for StdoutSink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stdout.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stdout.flush()
+    }
+}
+
+impl Sink for StdoutSink {
+    fn finish(&mut self, header: Option<&[u8]>) -> io::Result<()> {
+        self.stdout.flush()?;
+        if let (Some(header), Some((file, start))) = (header, &mut self.seekable) {
+            file.seek(io::SeekFrom::Start(*start))?;
+            file.write_all(header)?;
+            file.flush()?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes to a file, rewriting headers in place.
+pub struct FileSink {
+    file: io::BufWriter<fs::File>,
+}
+
+impl FileSink {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        Ok(FileSink { file: io::BufWriter::new(fs::File::create(path)?) })
+    }
+}
+
+impl Write for FileSink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl Sink for FileSink {
+    fn finish(&mut self, header: Option<&[u8]>) -> io::Result<()> {
+        self.file.flush()?;
+        if let Some(header) = header {
+            let file = self.file.get_mut();
+            file.seek(io::SeekFrom::Start(0))?;
+            file.write_all(header)?;
+            file.flush()?;
+        }
+        Ok(())
+    }
+}
+
+/// A second handle to stdout, if it is redirected to a regular file.
+#[cfg(unix)]
+fn seekable_stdout() -> Option<(fs::File, u64)> {
+    use std::os::fd::AsFd;
+    let fd = io::stdout().as_fd().try_clone_to_owned().ok()?;
+    let mut file = fs::File::from(fd);
+    if !file.metadata().ok()?.is_file() {
+        return None;
+    }
+    let start = file.stream_position().ok()?;
+    Some((file, start))
+}
+
+#[cfg(not(unix))]
+fn seekable_stdout() -> Option<(fs::File, u64)> {
+    None
+}
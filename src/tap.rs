@@ -0,0 +1,491 @@
+use std::io::{self, Write};
+
+use clap::ValueEnum;
+use dasp_sample::{Sample, ToSample};
+
+use crate::bit_io::{BitWriter, ToBytes};
+use crate::header::{self, StreamHeader};
+use crate::sink::{Encoder, Sink};
+
+/// Which point of the signal path is sent to stdout.
+#[derive(Debug, Clone, Copy)]
+pub enum Tap {
+    /// Configured input values, before any processing
+    Pre,
+    /// Processed values, as sent to the device
+    Post,
+}
+
+/// Container written around the tapped samples.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum TapFormat {
+    /// Bare samples
+    Raw,
+    /// Samples prefixed with an rplay stream header, which rplay detects on input
+    Rplay,
+    /// RIFF WAVE, sizes are filled in on exit when stdout is a regular file
+    Wav,
+    /// Sun AU, streamable with an unknown length
+    Au,
+    /// rplay stream with runs of silence stored as a frame count and an index of the runs at
+    /// the end, for mostly idle recordings. rplay plays it back, `rplay --pre` converts it to raw
+    Sparse,
+}
+
+impl TapFormat {
+    pub fn encoder(self) -> Box<dyn Encoder> {
+        match self {
+            TapFormat::Raw => Box::new(RawEncoder),
+            TapFormat::Rplay => Box::new(RplayEncoder),
+            TapFormat::Wav => Box::new(WavEncoder),
+            TapFormat::Au => Box::new(AuEncoder),
+            TapFormat::Sparse => Box::new(SparseEncoder),
+        }
+    }
+}
+
+/// Writes tapped samples through an [Encoder] into a [Sink].
+pub struct TapWriter {
+    pub tap: Tap,
+    encoder: Box<dyn Encoder>,
+    spec: StreamHeader,
+    writer: BitWriter<Box<dyn Sink>>,
+}
+
+impl TapWriter {
+    pub fn new(
+        tap: Tap,
+        encoder: Box<dyn Encoder>,
+        spec: StreamHeader,
+        mut sink: Box<dyn Sink>,
+    ) -> io::Result<Self> {
+        sink.write_all(&encoder.header(&spec, None))?;
+        let sink = encoder.wrap(&spec, sink);
+        let big_endian = encoder.big_endian().unwrap_or(spec.big_endian);
+        Ok(TapWriter {
+            tap,
+            encoder,
+            spec,
+            writer: BitWriter::new(sink, big_endian),
+        })
+    }
+
+    /// Writes an input value as it was read.
+    pub fn write_pre<T: ToBytes>(&mut self, t: T) -> io::Result<()> {
+        self.writer.write(t)
+    }
+
+    /// Writes a processed value, converted to integers when the spec isn't floating point.
+    pub fn write_post<P>(&mut self, value: P) -> io::Result<()>
+    where
+      P: ToBytes + Sample + ToSample<i16> + ToSample<i32> {
+        match (self.spec.float, self.spec.sample_size) {
+            (true, _) => self.writer.write(value),
+            (false, 16) => self.writer.write(value.to_sample::<i16>()),
+            (false, size) => self.writer.write_packed(value.to_sample::<i32>(), size as u32),
+        }
+    }
+
+    /// Writes a recorded value in the spec's format, integers keep its top bits.
+    pub fn write_recorded(&mut self, value: f32) -> io::Result<()> {
+        match (self.spec.float, self.spec.unsigned, self.spec.sample_size) {
+            (true, _, 64) => self.writer.write(value as f64),
+            (true, _, _) => self.writer.write(value),
+            (false, true, size) => self.writer.write_packed(value.to_sample::<u32>(), size as u32),
+            (false, false, size) => self.writer.write_packed(value.to_sample::<i32>(), size as u32),
+        }
+    }
+
+    /// Flushes the sink and finalizes the container where the encoder needs it.
+    pub fn finish(&mut self) -> io::Result<()> {
+        let header = self.encoder.rewrites_header()
+            .then(|| self.encoder.header(&self.spec, Some(self.writer.written())));
+        self.writer.get_mut().finish(header.as_deref())
+    }
+}
+
+/// Bare samples.
+struct RawEncoder;
+
+impl Encoder for RawEncoder {
+    fn check(&self, _spec: &StreamHeader) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn header(&self, _spec: &StreamHeader, _data_len: Option<u64>) -> Vec<u8> {
+        Vec::new()
+    }
+}
+
+/// Samples prefixed with an rplay [StreamHeader].
+struct RplayEncoder;
+
+impl Encoder for RplayEncoder {
+    fn check(&self, _spec: &StreamHeader) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn header(&self, spec: &StreamHeader, _data_len: Option<u64>) -> Vec<u8> {
+        spec.to_bytes().to_vec()
+    }
+}
+
+const WAV_HEADER_LEN: usize = 44;
+const WAV_FORMAT_PCM: u16 = 1;
+const WAV_FORMAT_IEEE_FLOAT: u16 = 3;
+
+/// RIFF WAVE, sizes are left at their maximum while the data length is unknown.
+struct WavEncoder;
+
+impl Encoder for WavEncoder {
+    fn check(&self, spec: &StreamHeader) -> Result<(), String> {
+        if spec.float {
+            return Ok(());
+        }
+        if spec.sign_magnitude {
+            return Err("WAV output can not store sign-magnitude samples".into());
+        }
+        match (spec.unsigned, spec.sample_size) {
+            (true, 8) | (false, 16..) => Ok(()),
+            (true, size) => Err(format!("WAV output can not store unsigned {size} bit samples")),
+            (false, size) => Err(format!("WAV output can not store signed {size} bit samples")),
+        }
+    }
+
+    fn header(&self, spec: &StreamHeader, data_len: Option<u64>) -> Vec<u8> {
+        let format = if spec.float { WAV_FORMAT_IEEE_FLOAT } else { WAV_FORMAT_PCM };
+        let block_align = spec.channels * (spec.sample_size as u16 / 8);
+        let byte_rate = spec.sample_rate * block_align as u32;
+        let data_len = data_len
+            .map_or(u32::MAX, |len| len.min((u32::MAX as usize - WAV_HEADER_LEN) as u64) as u32);
+        let riff_len = data_len.saturating_add(WAV_HEADER_LEN as u32 - 8);
+
+        let mut header = Vec::with_capacity(WAV_HEADER_LEN);
+        header.extend_from_slice(b"RIFF");
+        header.extend_from_slice(&riff_len.to_le_bytes());
+        header.extend_from_slice(b"WAVE");
+        header.extend_from_slice(b"fmt ");
+        header.extend_from_slice(&16u32.to_le_bytes());
+        header.extend_from_slice(&format.to_le_bytes());
+        header.extend_from_slice(&spec.channels.to_le_bytes());
+        header.extend_from_slice(&spec.sample_rate.to_le_bytes());
+        header.extend_from_slice(&byte_rate.to_le_bytes());
+        header.extend_from_slice(&block_align.to_le_bytes());
+        header.extend_from_slice(&(spec.sample_size as u16).to_le_bytes());
+        header.extend_from_slice(b"data");
+        header.extend_from_slice(&data_len.to_le_bytes());
+        header
+    }
+
+    fn big_endian(&self) -> Option<bool> {
+        Some(false)
+    }
+
+    fn rewrites_header(&self) -> bool {
+        true
+    }
+}
+
+const AU_HEADER_LEN: usize = 24;
+
+/// Sun AU, the data size is left as unknown until it is known.
+struct AuEncoder;
+
+impl Encoder for AuEncoder {
+    fn check(&self, spec: &StreamHeader) -> Result<(), String> {
+        if spec.float {
+            return Ok(());
+        }
+        if spec.sign_magnitude {
+            return Err("AU output can not store sign-magnitude samples".into());
+        }
+        match (spec.unsigned, spec.sample_size) {
+            (false, 8 | 16 | 32) => Ok(()),
+            (true, size) => Err(format!("AU output can not store unsigned {size} bit samples")),
+            (false, size) => Err(format!("AU output can not store signed {size} bit samples")),
+        }
+    }
+
+    fn header(&self, spec: &StreamHeader, data_len: Option<u64>) -> Vec<u8> {
+        let encoding: u32 = match (spec.float, spec.sample_size) {
+            (false, 8) => 2,
+            (false, 16) => 3,
+            (false, 32) => 5,
+            (true, 32) => 6,
+            (true, 64) => 7,
+            (_, size) => panic!("Unsupported AU sample size '{size}'"),
+        };
+        // all ones marks an unknown data size
+        let data_len = data_len.map_or(u32::MAX, |len| len.min(u32::MAX as u64 - 1) as u32);
+
+        let mut header = Vec::with_capacity(AU_HEADER_LEN);
+        header.extend_from_slice(b".snd");
+        header.extend_from_slice(&(AU_HEADER_LEN as u32).to_be_bytes());
+        header.extend_from_slice(&data_len.to_be_bytes());
+        header.extend_from_slice(&encoding.to_be_bytes());
+        header.extend_from_slice(&spec.sample_rate.to_be_bytes());
+        header.extend_from_slice(&(spec.channels as u32).to_be_bytes());
+        header
+    }
+
+    fn big_endian(&self) -> Option<bool> {
+        Some(true)
+    }
+
+    fn rewrites_header(&self) -> bool {
+        true
+    }
+}
+
+/// Silent frames in a row that make up a run of silence, shorter stretches stay in runs of samples.
+const MIN_SILENT_FRAMES: u32 = 16;
+/// Sample bytes held before a run of samples is written, so a live tap lags little.
+const MAX_RUN_BYTES: usize = 1 << 16;
+
+/// rplay stream header with silence runs, see [header::SilenceRuns].
+struct SparseEncoder;
+
+impl Encoder for SparseEncoder {
+    fn check(&self, spec: &StreamHeader) -> Result<(), String> {
+        spec.silent_frame()
+            .map(|_| ())
+            .ok_or_else(|| format!("Sparse output needs whole byte samples, not '{}' bit ones", spec.sample_size))
+    }
+
+    fn header(&self, spec: &StreamHeader, _data_len: Option<u64>) -> Vec<u8> {
+        StreamHeader { silence_runs: true, ..*spec }.to_bytes().to_vec()
+    }
+
+    fn wrap(&self, spec: &StreamHeader, sink: Box<dyn Sink>) -> Box<dyn Sink> {
+        let silent_frame = spec.silent_frame().expect("checked by SparseEncoder::check");
+        Box::new(SilenceRunWriter {
+            inner: sink,
+            frame: Vec::with_capacity(silent_frame.len()),
+            silent_frame,
+            samples: Vec::new(),
+            silent_tail: 0,
+            silence: 0,
+            frames: 0,
+            written: 0,
+            index: Vec::new(),
+            finished: false,
+        })
+    }
+}
+
+/// Splits samples into runs of samples and runs of silence.
+struct SilenceRunWriter {
+    inner: Box<dyn Sink>,
+    silent_frame: Vec<u8>,
+    /// bytes of a frame not complete yet
+    frame: Vec<u8>,
+    /// frames of the current run of samples
+    samples: Vec<u8>,
+    /// silent frames at the end of `samples`
+    silent_tail: u32,
+    /// frames of the current run of silence
+    silence: u32,
+    /// frames written in runs so far
+    frames: u64,
+    /// bytes written after the header so far
+    written: u64,
+    /// first frame and token offset of every run
+    index: Vec<(u64, u64)>,
+    finished: bool,
+}
+
+impl SilenceRunWriter {
+    fn write_run(&mut self, token: u32, samples: &[u8]) -> io::Result<()> {
+        let frames = (token & !header::RUN_SILENCE) as u64;
+        self.index.push((self.frames, self.written));
+        self.inner.write_all(&token.to_le_bytes())?;
+        self.inner.write_all(samples)?;
+        self.frames += frames;
+        self.written += 4 + samples.len() as u64;
+        Ok(())
+    }
+
+    fn flush_samples(&mut self, len: usize) -> io::Result<()> {
+        if len > 0 {
+            let samples = self.samples.drain(..len).collect::<Vec<_>>();
+            self.write_run((len / self.silent_frame.len()) as u32, &samples)?;
+        }
+        Ok(())
+    }
+
+    fn flush_silence(&mut self) -> io::Result<()> {
+        if self.silence > 0 {
+            self.write_run(header::RUN_SILENCE | self.silence, &[])?;
+            self.silence = 0;
+        }
+        Ok(())
+    }
+
+    fn push_frame(&mut self) -> io::Result<()> {
+        let silent = self.frame == self.silent_frame;
+        if silent && self.silence > 0 {
+            if self.silence == !header::RUN_SILENCE {
+                // the longest run a token counts, the silence goes on in the next one
+                self.flush_silence()?;
+            }
+            self.silence += 1;
+        } else if silent {
+            self.samples.extend_from_slice(&self.frame);
+            self.silent_tail += 1;
+            if self.silent_tail == MIN_SILENT_FRAMES {
+                // the silent tail starts a run of silence
+                let len = self.samples.len() - self.silent_tail as usize * self.silent_frame.len();
+                self.flush_samples(len)?;
+                self.samples.clear();
+                self.silence = self.silent_tail;
+                self.silent_tail = 0;
+            }
+        } else {
+            self.flush_silence()?;
+            self.samples.extend_from_slice(&self.frame);
+            self.silent_tail = 0;
+            if self.samples.len() >= MAX_RUN_BYTES {
+                self.flush_samples(self.samples.len())?;
+            }
+        }
+        self.frame.clear();
+        Ok(())
+    }
+}
+
+impl Write for SilenceRunWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for &byte in buf {
+            self.frame.push(byte);
+            if self.frame.len() == self.silent_frame.len() {
+                self.push_frame()?;
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl Sink for SilenceRunWriter {
+    fn finish(&mut self, header: Option<&[u8]>) -> io::Result<()> {
+        if std::mem::replace(&mut self.finished, true) {
+            return self.inner.finish(header);
+        }
+        self.flush_samples(self.samples.len())?;
+        self.flush_silence()?;
+        self.inner.write_all(&header::RUNS_END.to_le_bytes())?;
+        for &(frame, offset) in &self.index {
+            self.inner.write_all(&frame.to_le_bytes())?;
+            self.inner.write_all(&offset.to_le_bytes())?;
+        }
+        self.inner.write_all(&(self.index.len() as u64).to_le_bytes())?;
+        self.inner.write_all(&header::INDEX_MAGIC)?;
+        self.inner.finish(header)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    /// A sink whose bytes stay readable after it is boxed.
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Sink for Shared {
+        fn finish(&mut self, _header: Option<&[u8]>) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    const SPEC: StreamHeader = StreamHeader {
+        sample_rate: 48000,
+        channels: 2,
+        sample_size: 16,
+        float: false,
+        unsigned: false,
+        big_endian: false,
+        sign_magnitude: false,
+        silence_runs: false,
+    };
+
+    /// Encodes `samples` into runs and returns them.
+    fn encode(samples: &[u8]) -> Vec<u8> {
+        let shared = Shared::default();
+        let mut sink = SparseEncoder.wrap(&SPEC, Box::new(shared.clone()));
+        sink.write_all(samples).unwrap();
+        sink.finish(None).unwrap();
+        shared.0.lock().unwrap().clone()
+    }
+
+    /// The run tokens of encoded runs, up to the end of the runs.
+    fn tokens(runs: &[u8]) -> Vec<u32> {
+        let mut tokens = Vec::new();
+        let mut rest = runs;
+        loop {
+            let token = u32::from_le_bytes(rest[..4].try_into().unwrap());
+            tokens.push(token);
+            if token == header::RUNS_END {
+                return tokens;
+            }
+            let samples = if token & header::RUN_SILENCE != 0 { 0 } else { token as usize * 4 };
+            rest = &rest[4 + samples..];
+        }
+    }
+
+    #[test]
+    fn silence_runs_round_trip() {
+        let silence = |frames: usize| vec![0u8; frames * 4];
+        let sound = |frames: usize| (1..=frames * 4).map(|i| i as u8).collect::<Vec<_>>();
+        // silence at the start, in the middle, too short to be a run, and at the end
+        let samples = [silence(20), sound(5), silence(30), sound(3), silence(4), sound(2), silence(17)].concat();
+
+        let runs = encode(&samples);
+        let silent = header::RUN_SILENCE;
+        assert_eq!(tokens(&runs), [silent | 20, 5, silent | 30, 9, silent | 17, header::RUNS_END]);
+
+        let mut decoded = Vec::new();
+        header::SilenceRuns::new(&runs[..], &SPEC).unwrap().read_to_end(&mut decoded).unwrap();
+        assert_eq!(decoded, samples);
+    }
+
+    #[test]
+    fn splits_silence_longer_than_a_run() {
+        let shared = Shared::default();
+        let mut writer = SilenceRunWriter {
+            inner: Box::new(shared.clone()),
+            silent_frame: vec![0; 4],
+            frame: Vec::new(),
+            samples: Vec::new(),
+            silent_tail: 0,
+            // most of the way through the longest run of silence a token holds
+            silence: !header::RUN_SILENCE - 2,
+            frames: 0,
+            written: 0,
+            index: Vec::new(),
+            finished: false,
+        };
+        writer.write_all(&[0; 5 * 4]).unwrap();
+        writer.finish(None).unwrap();
+        let runs = shared.0.lock().unwrap().clone();
+        let silent = header::RUN_SILENCE;
+        assert_eq!(tokens(&runs), [silent | !silent, silent | 3, header::RUNS_END]);
+    }
+}
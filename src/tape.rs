@@ -0,0 +1,340 @@
+use std::f64::consts::TAU;
+use std::time::Duration;
+
+use crate::loopcheck::fft;
+use crate::timecode;
+
+/// Input searched for a steady tone when no pilot frequency is given.
+const SEARCH: Duration = Duration::from_secs(4);
+/// Least input to search for a tone in.
+const MIN_SEARCH: Duration = Duration::from_secs(1);
+/// Range searched for a tone, from above mains hum to below the top octave tapes lose first.
+const SEARCH_RANGE: (f64, f64) = (40.0, 10_000.0);
+/// How far the found tone must stand out of the median of the spectrum.
+const TONALITY_DB: f64 = 20.0;
+/// Highest speed variation tracked, sets the bandwidth around the tone.
+const MAX_FLUTTER: f64 = 200.0;
+/// Edges of the wow and flutter bands, as in IEC 60386.
+const WOW_BAND: (f64, f64) = (0.5, 4.0);
+const FLUTTER_BAND: (f64, f64) = (4.0, MAX_FLUTTER);
+/// Rate the speed is sampled at for the wow and flutter filters.
+const TICK_RATE: f64 = 1000.0;
+/// Filter settling time before the speed counts towards wow and flutter.
+const SETTLE: Duration = Duration::from_secs(1);
+/// Largest period error accepted from a zero crossing, larger ones are noise or a dropout.
+const MAX_PERIOD_ERROR: f64 = 0.1;
+const BLOCK: Duration = Duration::from_millis(10);
+/// Blocks on each side of a block that give the level it is compared with.
+const NEIGHBOURHOOD: usize = 25;
+/// Drop in the tone's level that counts as a dropout.
+const DROPOUT_DB: f64 = 6.0;
+/// Time constants of the tone's power now and of its usual power, crossings are skipped while
+/// the first is more than [DROPOUT_DB] below the second.
+const ENVELOPE: (f64, f64) = (100.0, 1.0);
+/// Tone level below which there is nothing to measure.
+const SILENCE_DB: f64 = -60.0;
+
+/// One-pole lowpass filter.
+struct OnePole {
+    a: f64,
+    y: f64,
+}
+
+impl OnePole {
+    fn new(cutoff: f64, rate: f64) -> Self {
+        OnePole { a: 1.0 - (-TAU * cutoff / rate).exp(), y: 0.0 }
+    }
+
+    fn lowpass(&mut self, x: f64) -> f64 {
+        self.y += self.a * (x - self.y);
+        self.y
+    }
+
+    fn highpass(&mut self, x: f64) -> f64 {
+        x - self.lowpass(x)
+    }
+}
+
+/// A band of the speed variation, between a one-pole highpass and lowpass.
+struct Band {
+    highpass: OnePole,
+    lowpass: OnePole,
+    squares: f64,
+    peak: f64,
+}
+
+impl Band {
+    fn new((low, high): (f64, f64), rate: f64) -> Self {
+        Band { highpass: OnePole::new(low, rate), lowpass: OnePole::new(high, rate), squares: 0.0, peak: 0.0 }
+    }
+
+    fn process(&mut self, x: f64, settled: bool) {
+        let y = self.lowpass.lowpass(self.highpass.highpass(x));
+        if settled {
+            self.squares += y * y;
+            self.peak = self.peak.max(y.abs());
+        }
+    }
+}
+
+/// Follows the frequency of a tone through the zero crossings of a bandpass around it.
+struct Tracker {
+    sample_rate: f64,
+    tone: f64,
+    /// RBJ bandpass coefficients and state
+    b: [f64; 3],
+    a: [f64; 2],
+    x: [f64; 2],
+    y: [f64; 2],
+    frames: u64,
+    last_crossing: Option<f64>,
+    /// power of the tone now and usually, at the [ENVELOPE] cutoffs
+    envelope: (OnePole, OnePole),
+    /// speed relative to the tone's frequency at the last crossing
+    speed: Option<f64>,
+    /// sum and count of the speeds measured, for the average
+    speeds: (f64, u64),
+    tick_len: u64,
+    ticks: u64,
+    /// ticks before the filters settle
+    settle_ticks: u64,
+    wow: Band,
+    flutter: Band,
+    total: Band,
+    block_len: usize,
+    block: (f64, usize),
+    /// level of the tone in each block, in dB
+    levels: Vec<f64>,
+}
+
+impl Tracker {
+    fn new(sample_rate: u32, tone: f64) -> Self {
+        let fs = sample_rate as f64;
+        let q = (tone / (2.0 * MAX_FLUTTER)).clamp(0.5, 20.0);
+        let w = TAU * tone / fs;
+        let alpha = w.sin() / (2.0 * q);
+        let a0 = 1.0 + alpha;
+        let tick_len = (fs / TICK_RATE).round().max(1.0) as u64;
+        let tick_rate = fs / tick_len as f64;
+        Tracker {
+            sample_rate: fs,
+            tone,
+            b: [alpha / a0, 0.0, -alpha / a0],
+            a: [-2.0 * w.cos() / a0, (1.0 - alpha) / a0],
+            x: [0.0; 2],
+            y: [0.0; 2],
+            frames: 0,
+            last_crossing: None,
+            envelope: (OnePole::new(ENVELOPE.0, fs), OnePole::new(ENVELOPE.1, fs)),
+            speed: None,
+            speeds: (0.0, 0),
+            tick_len,
+            ticks: 0,
+            settle_ticks: (SETTLE.as_secs_f64() * tick_rate) as u64,
+            wow: Band::new(WOW_BAND, tick_rate),
+            flutter: Band::new(FLUTTER_BAND, tick_rate),
+            total: Band::new((WOW_BAND.0, FLUTTER_BAND.1), tick_rate),
+            block_len: (BLOCK.as_secs_f64() * fs) as usize,
+            block: (0.0, 0),
+            levels: Vec::new(),
+        }
+    }
+
+    fn push(&mut self, x: f64) {
+        let y = self.b[0] * x + self.b[1] * self.x[0] + self.b[2] * self.x[1] - self.a[0] * self.y[0] - self.a[1] * self.y[1];
+        let previous = self.y[0];
+        self.x = [x, self.x[0]];
+        self.y = [y, previous];
+
+        // the phase of a tone that drops out is not its speed
+        let power = self.envelope.0.lowpass(y * y);
+        let usual = self.envelope.1.lowpass(y * y);
+        let steady = power > usual * 10f64.powf(-DROPOUT_DB / 10.0);
+        if !steady {
+            self.last_crossing = None;
+        }
+
+        // rising zero crossing, placed between the frames by linear interpolation
+        if steady && previous < 0.0 && y >= 0.0 {
+            let crossing = self.frames as f64 - 1.0 + previous / (previous - y);
+            if let Some(last) = self.last_crossing {
+                let frequency = self.sample_rate / (crossing - last);
+                let speed = frequency / self.tone;
+                if (speed - 1.0).abs() < MAX_PERIOD_ERROR {
+                    self.speed = Some(speed);
+                    self.speeds.0 += speed;
+                    self.speeds.1 += 1;
+                }
+            }
+            self.last_crossing = Some(crossing);
+        }
+
+        self.frames += 1;
+        if self.frames.is_multiple_of(self.tick_len) && let Some(speed) = self.speed {
+            let settled = self.ticks >= self.settle_ticks;
+            let deviation = speed - 1.0;
+            self.wow.process(deviation, settled);
+            self.flutter.process(deviation, settled);
+            self.total.process(deviation, settled);
+            self.ticks += 1;
+        }
+
+        self.block.0 += y * y;
+        self.block.1 += 1;
+        if self.block.1 == self.block_len {
+            self.levels.push(db((self.block.0 / self.block_len as f64).sqrt()));
+            self.block = (0.0, 0);
+        }
+    }
+}
+
+/// Estimates the speed variation of a digitized tape from a steady tone on it, a test tone or
+/// pilot if there is one, and lists the dropouts in that tone.
+pub struct TapeCheck {
+    sample_rate: u32,
+    pilot: Option<f64>,
+    /// input held while searching for a tone
+    head: Vec<f32>,
+    tracker: Option<Tracker>,
+    /// why no tone was found in the start of the input
+    search_error: Option<String>,
+}
+
+impl TapeCheck {
+    /// Tracks the `pilot` frequency, or searches the start of the input for a tone without it.
+    pub fn new(sample_rate: u32, pilot: Option<f64>) -> Self {
+        TapeCheck {
+            sample_rate,
+            pilot,
+            head: Vec::new(),
+            tracker: pilot.map(|pilot| Tracker::new(sample_rate, pilot)),
+            search_error: None,
+        }
+    }
+
+    /// Takes the next frame, mixed down to mono.
+    pub fn push(&mut self, x: f32) {
+        match &mut self.tracker {
+            Some(tracker) => tracker.push(x as f64),
+            None if self.search_error.is_some() => (),
+            None => {
+                self.head.push(x);
+                if self.head.len() as f64 >= SEARCH.as_secs_f64() * self.sample_rate as f64 {
+                    // reported at the end
+                    self.search_error = self.start_tracking().err();
+                }
+            },
+        }
+    }
+
+    /// Finds the tone in the input held so far and tracks it from the start.
+    fn start_tracking(&mut self) -> Result<(), String> {
+        let tone = find_tone(&self.head, self.sample_rate)?;
+        let mut tracker = Tracker::new(self.sample_rate, tone);
+        for &x in &self.head {
+            tracker.push(x as f64);
+        }
+        self.head = Vec::new();
+        self.tracker = Some(tracker);
+        Ok(())
+    }
+
+    /// Prints the report on standard output.
+    pub fn report(mut self) -> Result<(), String> {
+        if let Some(error) = self.search_error {
+            return Err(error);
+        }
+        if self.tracker.is_none() {
+            self.start_tracking()?;
+        }
+        let tracker = self.tracker.unwrap();
+        let fs = tracker.sample_rate;
+        let mut levels = tracker.levels.clone();
+        levels.sort_by(f64::total_cmp);
+        if levels.get(levels.len() / 2).is_none_or(|&median| median < SILENCE_DB) {
+            return Err(format!("The {:.1} Hz tone is too quiet to track", tracker.tone));
+        }
+
+        println!("duration: {}", timecode::clock(Duration::from_secs_f64(tracker.frames as f64 / fs)));
+        match self.pilot {
+            Some(pilot) => {
+                println!("tone: {pilot:.1} Hz pilot");
+                if tracker.speeds.1 > 0 {
+                    let speed = tracker.speeds.0 / tracker.speeds.1 as f64;
+                    println!("speed: {:+.3} %, the pilot plays at {:.1} Hz", (speed - 1.0) * 100.0, speed * pilot);
+                }
+            },
+            None => println!("tone: {:.1} Hz found, give --pilot with its recorded frequency for the absolute speed", tracker.tone),
+        }
+        let settled = tracker.ticks.saturating_sub(tracker.settle_ticks);
+        if settled == 0 {
+            println!("wow and flutter: too short to measure, needs more than {:?} of the tone", SETTLE);
+        } else {
+            let rms = |band: &Band| (band.squares / settled as f64).sqrt() * 100.0;
+            println!("wow: {:.3} % rms, {}-{} Hz", rms(&tracker.wow), WOW_BAND.0, WOW_BAND.1);
+            println!("flutter: {:.3} % rms, {}-{} Hz", rms(&tracker.flutter), FLUTTER_BAND.0, FLUTTER_BAND.1);
+            println!("peak deviation: {:.3} %", tracker.total.peak * 100.0);
+        }
+
+        let levels = &tracker.levels;
+        let block = BLOCK.as_secs_f64();
+        let mut dropouts = Vec::new();
+        for (i, &level) in levels.iter().enumerate() {
+            let mut neighbourhood = levels[i.saturating_sub(NEIGHBOURHOOD)..(i + NEIGHBOURHOOD + 1).min(levels.len())].to_vec();
+            neighbourhood.sort_by(f64::total_cmp);
+            let median = neighbourhood[neighbourhood.len() / 2];
+            if median < SILENCE_DB || level > median - DROPOUT_DB {
+                continue;
+            }
+            // blocks in a row are one dropout, with the depth of the deepest
+            let depth = level - median;
+            match dropouts.last_mut() {
+                Some((start, blocks, deepest)) if *start + *blocks == i => {
+                    *blocks += 1;
+                    *deepest = f64::min(*deepest, depth);
+                },
+                _ => dropouts.push((i, 1, depth)),
+            }
+        }
+        println!("dropouts: {}", dropouts.len());
+        for (start, blocks, depth) in dropouts {
+            println!("dropout: at {:.3} s, {:.0} ms, {depth:.1} dB", start as f64 * block, blocks as f64 * block * 1000.0);
+        }
+        Ok(())
+    }
+}
+
+/// Frequency of the strongest steady tone in `samples`, from the peak of their spectrum.
+fn find_tone(samples: &[f32], sample_rate: u32) -> Result<f64, String> {
+    let fs = sample_rate as f64;
+    if (samples.len() as f64) < MIN_SEARCH.as_secs_f64() * fs {
+        return Err(format!("The input is too short to find a tone in, needs at least {MIN_SEARCH:?}"));
+    }
+    // the largest power of two that fits, Hann windowed
+    let n = 1 << samples.len().ilog2();
+    let mut re = samples[..n].iter().enumerate()
+        .map(|(i, &x)| x as f64 * (0.5 - 0.5 * (TAU * i as f64 / n as f64).cos()))
+        .collect::<Vec<_>>();
+    let mut im = vec![0.0; n];
+    fft(&mut re, &mut im, false);
+    let power = |k: usize| re[k] * re[k] + im[k] * im[k];
+
+    let bin = |frequency: f64| (frequency * n as f64 / fs) as usize;
+    let range = bin(SEARCH_RANGE.0)..bin(SEARCH_RANGE.1.min(0.45 * fs));
+    let peak = range.clone().max_by(|&a, &b| power(a).total_cmp(&power(b))).ok_or("No frequencies to search for a tone")?;
+    let mut powers = range.map(power).collect::<Vec<_>>();
+    powers.sort_by(f64::total_cmp);
+    let median = powers[powers.len() / 2];
+    if 10.0 * (power(peak) / median.max(f64::MIN_POSITIVE)).log10() < TONALITY_DB {
+        return Err("Found no steady tone to track, record a test tone or give its frequency with --pilot".into());
+    }
+    // between the bins, by a parabola through the log power around the peak
+    let (a, b, c) = (power(peak - 1).ln(), power(peak).ln(), power(peak + 1).ln());
+    let offset = 0.5 * (a - c) / (a - 2.0 * b + c);
+    let offset = if offset.is_finite() { offset.clamp(-0.5, 0.5) } else { 0.0 };
+    Ok((peak as f64 + offset) * fs / n as f64)
+}
+
+fn db(amplitude: f64) -> f64 {
+    20.0 * amplitude.max(1e-10).log10()
+}
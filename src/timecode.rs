@@ -0,0 +1,157 @@
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Parses a duration in seconds, optionally suffixed with `ms`, `s`, `m` or `h`, e.g. 0.2, 3500ms, 1h,
+/// a sum of suffixed parts such as 1h2m3.5s, or a clock time such as 01:02:03.5 or 02:03.5.
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let invalid = || format!("Invalid duration '{s}', expected e.g. 0.5, 3500ms, 30m, 1h2m3.5s or 01:02:03.5");
+    let mut rest = s.trim();
+    if rest.contains(':') {
+        return parse_clock(rest).ok_or_else(invalid);
+    }
+    let mut seconds = match rest.parse::<f64>() {
+        Ok(seconds) => return Duration::try_from_secs_f64(seconds).map_err(|_| invalid()),
+        Err(_) if rest.is_empty() => return Err(invalid()),
+        Err(_) => 0.0,
+    };
+    while !rest.is_empty() {
+        let number_len = rest.find(|c: char| !(c.is_ascii_digit() || c == '.')).unwrap_or(rest.len());
+        let (number, tail) = rest.split_at(number_len);
+        let tail = tail.trim_start();
+        let unit_len = tail.find(|c: char| !c.is_ascii_alphabetic()).unwrap_or(tail.len());
+        let (unit, tail) = tail.split_at(unit_len);
+        let unit = match unit {
+            "ms" => 0.001,
+            "s" => 1.0,
+            "m" => 60.0,
+            "h" => 3600.0,
+            _ => return Err(invalid()),
+        };
+        seconds += number.parse::<f64>().map_err(|_| invalid())? * unit;
+        rest = tail.trim_start();
+    }
+    Duration::try_from_secs_f64(seconds).map_err(|_| invalid())
+}
+
+/// Parses `[HH:]MM:SS[.fraction]`, minutes and seconds below 60 after the first field.
+fn parse_clock(s: &str) -> Option<Duration> {
+    let fields = s.split(':').collect::<Vec<_>>();
+    let (seconds, whole) = fields.split_last()?;
+    if whole.len() > 2 || !seconds.starts_with(|c: char| c.is_ascii_digit()) {
+        return None;
+    }
+    let seconds = seconds.parse::<f64>().ok().filter(|&s| s < 60.0 || whole.is_empty())?;
+    let mut total = 0u64;
+    for (i, field) in whole.iter().enumerate() {
+        let value = field.parse::<u64>().ok().filter(|&v| i == 0 || v < 60)?;
+        total = total.checked_mul(60)?.checked_add(value)?;
+    }
+    Duration::try_from_secs_f64(total as f64 * 60.0 + seconds).ok()
+}
+
+/// A time in the audio, as a number of frames or a duration. Frames are written `@48000`,
+/// anything else is a duration as [parse_duration] takes it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Time {
+    Frames(u64),
+    Duration(Duration),
+}
+
+impl Time {
+    /// The time in frames at `sample_rate`.
+    pub fn frames(self, sample_rate: u32) -> u64 {
+        match self {
+            Time::Frames(frames) => frames,
+            Time::Duration(duration) => (duration.as_secs_f64() * sample_rate as f64).round() as u64,
+        }
+    }
+
+    /// The time as a duration at `sample_rate`.
+    pub fn duration(self, sample_rate: u32) -> Duration {
+        match self {
+            Time::Frames(frames) => Duration::from_secs_f64(frames as f64 / sample_rate as f64),
+            Time::Duration(duration) => duration,
+        }
+    }
+}
+
+impl FromStr for Time {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().strip_prefix('@') {
+            Some(frames) => frames.trim().parse().map(Time::Frames)
+                .map_err(|_| format!("Invalid frame count '{s}', expected e.g. @48000")),
+            None => parse_duration(s).map(Time::Duration)
+                .map_err(|e| format!("{e}, or frames such as @48000")),
+        }
+    }
+}
+
+/// Formats a duration the way [parse_duration] takes it, as `HH:MM:SS.mmm`.
+pub fn clock(duration: Duration) -> String {
+    let millis = duration.as_millis();
+    let (seconds, millis) = (millis / 1000, millis % 1000);
+    format!("{:02}:{:02}:{:02}.{millis:03}", seconds / 3600, seconds / 60 % 60, seconds % 60)
+}
+
+/// Formats the position of `frame` at `sample_rate` as its clock time and frame number, both in
+/// the forms a [Time] is written in.
+pub fn position(frame: u64, sample_rate: u32) -> String {
+    format!("{} (@{frame})", clock(Time::Frames(frame).duration(sample_rate)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_compound_durations() {
+        assert_eq!(parse_duration("1m30s"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("1h 2m"), Ok(Duration::from_secs(3720)));
+        assert_eq!(parse_duration("1h2m3.5s"), Ok(Duration::from_secs_f64(3723.5)));
+    }
+
+    #[test]
+    fn parses_single_units() {
+        assert_eq!(parse_duration("200ms"), Ok(Duration::from_millis(200)));
+        assert_eq!(parse_duration("3500ms"), Ok(Duration::from_millis(3500)));
+        assert_eq!(parse_duration("2s"), Ok(Duration::from_secs(2)));
+    }
+
+    #[test]
+    fn parses_bare_seconds() {
+        assert_eq!(parse_duration("0.5"), Ok(Duration::from_millis(500)));
+    }
+
+    #[test]
+    fn parses_clock_times() {
+        assert_eq!(parse_duration("01:02:03.5"), Ok(Duration::from_secs_f64(3723.5)));
+        assert_eq!(parse_duration("02:03.5"), Ok(Duration::from_secs_f64(123.5)));
+        assert_eq!(parse_duration("90:00"), Ok(Duration::from_secs(5400)));
+    }
+
+    #[test]
+    fn rejects_invalid_durations() {
+        for s in ["1.2.3s", "-1", "", "1x", "inf", "ms", "1:60", "1:2:3:4", "1:-2", ":5", "1:02:60"] {
+            assert!(parse_duration(s).is_err(), "{s:?} parsed");
+        }
+    }
+
+    #[test]
+    fn parses_frames_and_durations() {
+        assert_eq!("@123456".parse(), Ok(Time::Frames(123456)));
+        assert_eq!("1m".parse(), Ok(Time::Duration(Duration::from_secs(60))));
+        assert!("@1.5".parse::<Time>().is_err());
+        assert_eq!(Time::Frames(24000).duration(48000), Duration::from_millis(500));
+        assert_eq!(Time::Duration(Duration::from_millis(500)).frames(48000), 24000);
+    }
+
+    #[test]
+    fn formats_what_it_parses() {
+        let duration = Duration::from_secs_f64(3723.5);
+        assert_eq!(clock(duration), "01:02:03.500");
+        assert_eq!(parse_duration(&clock(duration)), Ok(duration));
+        assert_eq!(position(72000, 48000), "00:00:01.500 (@72000)");
+    }
+}
@@ -0,0 +1,77 @@
+use std::ffi::OsString;
+use std::io::{self, BufRead};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+use std::thread;
+
+/// An input played on a device of its own by `--zone`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Zone {
+    pub file: PathBuf,
+    pub device: String,
+}
+
+impl FromStr for Zone {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // device names can hold an `=`, file names seldom do
+        match s.split_once('=') {
+            Some((file, device)) if !file.is_empty() && !device.is_empty() => {
+                Ok(Zone { file: file.into(), device: device.into() })
+            },
+            _ => Err(format!("Invalid zone '{s}', expected FILE=DEVICE such as kitchen.wav=USB")),
+        }
+    }
+}
+
+/// Plays every zone in an rplay of its own, with the rest of the command line `args`, and waits
+/// for all of them. Their messages are prefixed with the zone. Returns the exit code of the
+/// first one that failed, or 0.
+pub fn run(zones: &[Zone], args: &[OsString]) -> Result<i32, String> {
+    let exe = std::env::current_exe().map_err(|e| format!("failed to find the rplay executable: {e}"))?;
+    let args = without_zones(args);
+    let mut children = Vec::new();
+    for zone in zones {
+        let label = format!("[{} on {}]", zone.file.display(), zone.device);
+        let mut child = Command::new(&exe)
+            .args(&args)
+            .arg("--device").arg(&zone.device)
+            .arg("--").arg(&zone.file)
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("failed to start {label}: {e}"))?;
+        let stderr = child.stderr.take().unwrap();
+        let printer = thread::spawn(move || {
+            for line in io::BufReader::new(stderr).lines().map_while(Result::ok) {
+                eprintln!("{label} {line}");
+            }
+        });
+        children.push((child, printer));
+    }
+
+    let mut code = 0;
+    for (mut child, printer) in children {
+        let status = child.wait().map_err(|e| format!("{e}"))?;
+        let _ = printer.join();
+        if code == 0 && !status.success() {
+            code = status.code().unwrap_or(1);
+        }
+    }
+    Ok(code)
+}
+
+/// `args` without the program name and the `--zone` options.
+fn without_zones(args: &[OsString]) -> Vec<OsString> {
+    let mut out = Vec::new();
+    let mut args = args.iter().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--zone" {
+            args.next();
+        } else if !arg.to_str().is_some_and(|a| a.starts_with("--zone=")) {
+            out.push(arg.clone());
+        }
+    }
+    out
+}